@@ -21,9 +21,9 @@ use std::path::Path;
 
 fn main() {
 	let out_dir = env::var("OUT_DIR").unwrap();
-	let mut file = File::create(&Path::new(&out_dir).join("bindings.rs")).unwrap();
+	let mut file = File::create(Path::new(&out_dir).join("bindings.rs")).unwrap();
 
-	Registry::new(Api::Gl, (4, 3), Profile::Core, Fallbacks::All, [])
+	Registry::new(Api::Gl, (4, 3), Profile::Compatibility, Fallbacks::All, ["GL_ARB_buffer_storage", "GL_ARB_clip_control"])
 		.write_bindings(GlobalGenerator, &mut file)
 		.unwrap();
 }
@@ -0,0 +1,262 @@
+use crate::input::{ActionMap, Binding, Input};
+
+use cgmath::{prelude::*, Deg, Matrix4, Point3, Vector3};
+use glutin::event::{MouseButton, VirtualKeyCode};
+
+use std::time::Duration;
+
+/// Degrees of rotation per pixel of mouse movement
+const MOUSE_SENSITIVITY: f32 = 0.1;
+
+/// Something that turns mouse / keyboard input into a view matrix.
+pub trait CameraController {
+	fn name(&self) -> &'static str;
+	fn view_matrix(&self) -> Matrix4<f32>;
+	fn eye(&self) -> Point3<f32>;
+	fn forward(&self) -> Vector3<f32>;
+	/// Place the camera at `eye` looking along `forward`.  Used to hand over when switching controllers.
+	fn set_pose(&mut self, eye: Point3<f32>, forward: Vector3<f32>);
+
+	fn mouse_move(&mut self, diff_x: f32, diff_y: f32, input: &Input);
+	fn scroll(&mut self, diff_y: f32);
+	/// Called every frame with the time since the last frame so motion doesn't depend on the frame rate.
+	fn update(&mut self, _elapsed: Duration, _input: &Input) {}
+}
+
+/// The actions that the camera controllers respond to.
+pub fn register_actions(actions: &mut ActionMap) {
+	actions.register("camera.rotate", Binding::Mouse(MouseButton::Left));
+	actions.register("camera.pan", Binding::Mouse(MouseButton::Middle));
+	actions.register("camera.forward", Binding::Key(VirtualKeyCode::W));
+	actions.register("camera.back", Binding::Key(VirtualKeyCode::S));
+	actions.register("camera.left", Binding::Key(VirtualKeyCode::A));
+	actions.register("camera.right", Binding::Key(VirtualKeyCode::D));
+	actions.register("camera.up", Binding::Key(VirtualKeyCode::E));
+	actions.register("camera.down", Binding::Key(VirtualKeyCode::Q));
+	actions.register("camera.fast", Binding::Key(VirtualKeyCode::LShift));
+	actions.register("camera.slow", Binding::Key(VirtualKeyCode::LControl));
+}
+
+/// A unit vector pointing in the direction of yaw / pitch.  A yaw of 0 looks down -Z.
+fn direction(yaw: Deg<f32>, pitch: Deg<f32>) -> Vector3<f32> {
+	let (yaw_sin, yaw_cos) = yaw.sin_cos();
+	let (pitch_sin, pitch_cos) = pitch.sin_cos();
+	Vector3::new(pitch_cos * yaw_sin, pitch_sin, -pitch_cos * yaw_cos)
+}
+/// The inverse of `direction`
+fn yaw_pitch(forward: Vector3<f32>) -> (Deg<f32>, Deg<f32>) {
+	let forward = forward.normalize();
+	(
+		Deg::from(cgmath::Rad(forward.x.atan2(-forward.z))),
+		Deg::from(cgmath::Rad(forward.y.asin())),
+	)
+}
+fn clamp_pitch(pitch: Deg<f32>) -> Deg<f32> {
+	// Looking straight up or down makes look_at degenerate
+	Deg(pitch.0.clamp(-89.0, 89.0))
+}
+
+fn axis(positive: bool, negative: bool) -> f32 {
+	positive as i32 as f32 - negative as i32 as f32
+}
+/// How far to move along (right, up, forward), each in the range -1 to 1
+fn movement(input: &Input) -> Vector3<f32> {
+	Vector3::new(
+		axis(input.action_down("camera.right"), input.action_down("camera.left")),
+		axis(input.action_down("camera.up"), input.action_down("camera.down")),
+		axis(input.action_down("camera.forward"), input.action_down("camera.back")),
+	)
+}
+fn speed_factor(input: &Input) -> f32 {
+	match (input.action_down("camera.fast"), input.action_down("camera.slow")) {
+		(true, false) => 4.0,
+		(false, true) => 0.25,
+		_ => 1.0,
+	}
+}
+
+/// Rotates around (and zooms towards) a target point.  Left drag rotates, middle drag pans and
+/// scrolling changes the distance.
+pub struct Orbit {
+	pub target: Point3<f32>,
+	pub distance: f32,
+	pub yaw: Deg<f32>,
+	pub pitch: Deg<f32>,
+	pub min_distance: f32,
+	pub max_distance: f32,
+}
+impl Orbit {
+	/// Orbit around `target` starting out from `eye`
+	pub fn new(eye: Point3<f32>, target: Point3<f32>) -> Orbit {
+		let mut orbit = Orbit {
+			target,
+			distance: (target - eye).magnitude(),
+			yaw: Deg(0.0),
+			pitch: Deg(0.0),
+			min_distance: 0.5,
+			max_distance: 500.0,
+		};
+		orbit.set_pose(eye, target - eye);
+		orbit
+	}
+	fn right(&self) -> Vector3<f32> {
+		self.forward().cross(Vector3::unit_y()).normalize()
+	}
+}
+impl CameraController for Orbit {
+	fn name(&self) -> &'static str {
+		"Orbit"
+	}
+	fn view_matrix(&self) -> Matrix4<f32> {
+		Matrix4::look_at(self.eye(), self.target, Vector3::unit_y())
+	}
+	fn eye(&self) -> Point3<f32> {
+		self.target - self.forward() * self.distance
+	}
+	fn forward(&self) -> Vector3<f32> {
+		direction(self.yaw, self.pitch)
+	}
+	fn set_pose(&mut self, eye: Point3<f32>, forward: Vector3<f32>) {
+		let (yaw, pitch) = yaw_pitch(forward);
+		self.yaw = yaw;
+		self.pitch = clamp_pitch(pitch);
+		// Orbit around whatever is in front of the camera at the current distance
+		self.target = eye + self.forward() * self.distance;
+	}
+	fn mouse_move(&mut self, diff_x: f32, diff_y: f32, input: &Input) {
+		if input.action_down("camera.rotate") {
+			self.yaw -= Deg(diff_x * MOUSE_SENSITIVITY);
+			self.pitch = clamp_pitch(self.pitch + Deg(diff_y * MOUSE_SENSITIVITY));
+		}
+		if input.action_down("camera.pan") {
+			// Pan in the camera's plane, scaled so that the target roughly follows the mouse
+			let right = self.right();
+			let up = right.cross(self.forward());
+			let scale = self.distance * 0.002;
+			self.target += (right * -diff_x + up * diff_y) * scale;
+		}
+	}
+	fn scroll(&mut self, diff_y: f32) {
+		self.distance = (self.distance * 1.1f32.powf(diff_y)).clamp(self.min_distance, self.max_distance);
+	}
+}
+
+/// Free flying camera: (by default) WASD to move along the view direction, Q / E to move down / up,
+/// left drag to look around, shift / ctrl to go faster / slower and scroll to change the base speed.
+pub struct Fly {
+	pub position: Point3<f32>,
+	pub yaw: Deg<f32>,
+	pub pitch: Deg<f32>,
+	/// World units per second
+	pub speed: f32,
+}
+impl Fly {
+	pub fn new(position: Point3<f32>, forward: Vector3<f32>) -> Fly {
+		let (yaw, pitch) = yaw_pitch(forward);
+		Fly {
+			position,
+			yaw,
+			pitch: clamp_pitch(pitch),
+			speed: 10.0,
+		}
+	}
+}
+impl CameraController for Fly {
+	fn name(&self) -> &'static str {
+		"Fly"
+	}
+	fn view_matrix(&self) -> Matrix4<f32> {
+		Matrix4::look_at_dir(self.position, self.forward(), Vector3::unit_y())
+	}
+	fn eye(&self) -> Point3<f32> {
+		self.position
+	}
+	fn forward(&self) -> Vector3<f32> {
+		direction(self.yaw, self.pitch)
+	}
+	fn set_pose(&mut self, eye: Point3<f32>, forward: Vector3<f32>) {
+		let (yaw, pitch) = yaw_pitch(forward);
+		self.position = eye;
+		self.yaw = yaw;
+		self.pitch = clamp_pitch(pitch);
+	}
+	fn mouse_move(&mut self, diff_x: f32, diff_y: f32, input: &Input) {
+		if input.action_down("camera.rotate") {
+			self.yaw += Deg(diff_x * MOUSE_SENSITIVITY);
+			self.pitch = clamp_pitch(self.pitch - Deg(diff_y * MOUSE_SENSITIVITY));
+		}
+	}
+	fn scroll(&mut self, diff_y: f32) {
+		self.speed = (self.speed * 1.1f32.powf(-diff_y)).clamp(0.1, 1000.0);
+	}
+	fn update(&mut self, elapsed: Duration, input: &Input) {
+		let amounts = movement(input);
+		if amounts == Vector3::zero() {
+			return;
+		}
+		let forward = self.forward();
+		let right = forward.cross(Vector3::unit_y()).normalize();
+		let movement = right * amounts.x + Vector3::unit_y() * amounts.y + forward * amounts.z;
+		self.position += movement.normalize() * self.speed * speed_factor(input) * elapsed.as_secs_f32();
+	}
+}
+
+/// Like `Fly` but walking: movement stays in the horizontal plane at a fixed eye height.
+pub struct FirstPerson {
+	pub position: Point3<f32>,
+	pub eye_height: f32,
+	pub yaw: Deg<f32>,
+	pub pitch: Deg<f32>,
+	/// World units per second
+	pub speed: f32,
+}
+impl FirstPerson {
+	pub fn new(position: Point3<f32>, eye_height: f32) -> FirstPerson {
+		FirstPerson {
+			position: Point3::new(position.x, eye_height, position.z),
+			eye_height,
+			yaw: Deg(0.0),
+			pitch: Deg(0.0),
+			speed: 4.0,
+		}
+	}
+}
+impl CameraController for FirstPerson {
+	fn name(&self) -> &'static str {
+		"First Person"
+	}
+	fn view_matrix(&self) -> Matrix4<f32> {
+		Matrix4::look_at_dir(self.position, self.forward(), Vector3::unit_y())
+	}
+	fn eye(&self) -> Point3<f32> {
+		self.position
+	}
+	fn forward(&self) -> Vector3<f32> {
+		direction(self.yaw, self.pitch)
+	}
+	fn set_pose(&mut self, eye: Point3<f32>, forward: Vector3<f32>) {
+		let (yaw, pitch) = yaw_pitch(forward);
+		self.position = Point3::new(eye.x, self.eye_height, eye.z);
+		self.yaw = yaw;
+		self.pitch = clamp_pitch(pitch);
+	}
+	fn mouse_move(&mut self, diff_x: f32, diff_y: f32, input: &Input) {
+		if input.action_down("camera.rotate") {
+			self.yaw += Deg(diff_x * MOUSE_SENSITIVITY);
+			self.pitch = clamp_pitch(self.pitch - Deg(diff_y * MOUSE_SENSITIVITY));
+		}
+	}
+	fn scroll(&mut self, _diff_y: f32) {}
+	fn update(&mut self, elapsed: Duration, input: &Input) {
+		let amounts = movement(input);
+		// Walk along the ground no matter where we're looking
+		let forward = direction(self.yaw, Deg(0.0));
+		let right = forward.cross(Vector3::unit_y());
+		let movement = right * amounts.x + forward * amounts.z;
+		if movement == Vector3::zero() {
+			return;
+		}
+		self.position += movement.normalize() * self.speed * speed_factor(input) * elapsed.as_secs_f32();
+		self.position.y = self.eye_height;
+	}
+}
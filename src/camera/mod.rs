@@ -0,0 +1,7 @@
+mod controller;
+mod projection;
+mod uniforms;
+
+pub use controller::{register_actions, CameraController, FirstPerson, Fly, Orbit};
+pub use projection::{Projection, ProjectionMode};
+pub use uniforms::{check_camera_block, CameraBuffer, CameraUniforms, CAMERA_BINDING};
@@ -0,0 +1,125 @@
+use crate::gl;
+
+use cgmath::{ortho, Deg, Matrix4, PerspectiveFov, Rad, Vector2, Vector4};
+use glutin::dpi::PhysicalSize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectionMode {
+	Perspective,
+	Orthographic,
+}
+
+/// Tracks the size of the framebuffer (in physical pixels) and builds a projection matrix with
+/// the matching aspect ratio.
+pub struct Projection {
+	pub mode: ProjectionMode,
+	pub fovy: Deg<f32>,
+	pub near: f32,
+	pub far: f32,
+	/// Map the near plane to 1 and an infinitely distant far plane to 0.  This spreads depth
+	/// precision out evenly, but needs `glClipControl`; perspective mode only.
+	pub reversed_z: bool,
+	/// How many world units tall the view is in orthographic mode.
+	pub ortho_height: f32,
+	size: PhysicalSize,
+}
+impl Projection {
+	pub fn new(size: PhysicalSize) -> Projection {
+		let mut projection = Projection {
+			mode: ProjectionMode::Perspective,
+			fovy: Deg(90.0),
+			near: 0.1,
+			far: 1000.0,
+			reversed_z: false,
+			ortho_height: 4.0,
+			size,
+		};
+		projection.resize(size);
+		projection
+	}
+	pub fn resize(&mut self, size: PhysicalSize) {
+		self.size = size;
+		unsafe {
+			gl::Viewport(0, 0, size.width as i32, size.height as i32);
+		}
+	}
+	pub fn size(&self) -> Vector2<f32> {
+		Vector2::new(self.size.width as f32, self.size.height as f32)
+	}
+	pub fn aspect(&self) -> f32 {
+		if self.size.height > 0.0 {
+			(self.size.width / self.size.height) as f32
+		} else {
+			// Minimized windows have a height of 0
+			1.0
+		}
+	}
+	pub fn toggle_mode(&mut self) {
+		self.mode = match self.mode {
+			ProjectionMode::Perspective => ProjectionMode::Orthographic,
+			ProjectionMode::Orthographic => ProjectionMode::Perspective,
+		};
+	}
+	pub fn toggle_reversed_z(&mut self) {
+		self.reversed_z = !self.reversed_z;
+		if self.reversed_z && !gl::ClipControl::is_loaded() {
+			println!("glClipControl isn't available, so reversed-Z can't be used.");
+			self.reversed_z = false;
+		}
+	}
+	fn uses_reversed_z(&self) -> bool {
+		self.reversed_z && self.mode == ProjectionMode::Perspective && gl::ClipControl::is_loaded()
+	}
+	pub fn matrix(&self) -> Matrix4<f32> {
+		let aspect = self.aspect();
+		match self.mode {
+			ProjectionMode::Perspective if self.uses_reversed_z() => {
+				let f = 1.0 / (Rad::from(self.fovy).0 / 2.0).tan();
+				// Same as a regular perspective matrix except that z_clip = near and w_clip = -z_eye,
+				// which means depth = near / -z_eye: 1 at the near plane and 0 at infinity.
+				Matrix4::from_cols(
+					Vector4::new(f / aspect, 0.0, 0.0, 0.0),
+					Vector4::new(0.0, f, 0.0, 0.0),
+					Vector4::new(0.0, 0.0, 0.0, -1.0),
+					Vector4::new(0.0, 0.0, self.near, 0.0),
+				)
+			}
+			ProjectionMode::Perspective => PerspectiveFov {
+				fovy: self.fovy.into(),
+				aspect,
+				near: self.near,
+				far: self.far,
+			}
+			.into(),
+			ProjectionMode::Orthographic => {
+				let half_height = self.ortho_height / 2.0;
+				let half_width = half_height * aspect;
+				ortho(-half_width, half_width, -half_height, half_height, self.near, self.far)
+			}
+		}
+	}
+	/// The normalized device depth of the near and the far plane
+	pub fn depth_range(&self) -> (f32, f32) {
+		if self.uses_reversed_z() {
+			(1.0, 0.0)
+		} else {
+			(-1.0, 1.0)
+		}
+	}
+	/// Set up the depth range, comparison and clear value to match the matrix.  Has to be called before clearing.
+	pub fn apply_depth_state(&self) {
+		unsafe {
+			if self.uses_reversed_z() {
+				gl::ClipControl(gl::LOWER_LEFT, gl::ZERO_TO_ONE);
+				gl::DepthFunc(gl::GREATER);
+				gl::ClearDepth(0.0);
+			} else {
+				if gl::ClipControl::is_loaded() {
+					gl::ClipControl(gl::LOWER_LEFT, gl::NEGATIVE_ONE_TO_ONE);
+				}
+				gl::DepthFunc(gl::LESS);
+				gl::ClearDepth(1.0);
+			}
+		}
+	}
+}
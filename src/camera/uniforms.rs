@@ -0,0 +1,160 @@
+use crate::gl;
+use crate::shader::ProgramReflection;
+use gl::types::*;
+
+use cgmath::{Matrix4, SquareMatrix, Vector2, Vector3};
+
+use std::{
+	ffi::c_void,
+	mem::{offset_of, size_of},
+	ptr,
+};
+
+/// The uniform buffer binding point of the `Camera` block.  Shaders declare it with
+/// `layout(std140, binding = 0) uniform Camera { ... } camera;`
+pub const CAMERA_BINDING: GLuint = 0;
+
+/// The std140 layout of the `Camera` uniform block.  The GLSL side of this lives in voxel.glslg
+/// (and any other shader that wants the camera) and has to declare the members in this order.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CameraUniforms {
+	pub view: Matrix4<f32>,
+	pub projection: Matrix4<f32>,
+	pub view_projection: Matrix4<f32>,
+	pub inverse_view: Matrix4<f32>,
+	pub inverse_projection: Matrix4<f32>,
+	pub inverse_view_projection: Matrix4<f32>,
+	/// A vec3 is 16 byte aligned but only 12 bytes long, so the float after it packs into the gap.
+	pub eye: Vector3<f32>,
+	pub time: f32,
+	pub viewport: Vector2<f32>,
+	_padding: [f32; 2],
+}
+
+/// (member, std140 offset) for every member of the block.
+const LAYOUT: [(&str, usize); 9] = [
+	("view", 0),
+	("projection", 64),
+	("view_projection", 128),
+	("inverse_view", 192),
+	("inverse_projection", 256),
+	("inverse_view_projection", 320),
+	("eye", 384),
+	("time", 396),
+	("viewport", 400),
+];
+const STD140_SIZE: usize = 416;
+
+// If any of these fail then the Rust struct no longer matches the std140 layout above.
+const _: () = assert!(offset_of!(CameraUniforms, view) == LAYOUT[0].1);
+const _: () = assert!(offset_of!(CameraUniforms, projection) == LAYOUT[1].1);
+const _: () = assert!(offset_of!(CameraUniforms, view_projection) == LAYOUT[2].1);
+const _: () = assert!(offset_of!(CameraUniforms, inverse_view) == LAYOUT[3].1);
+const _: () = assert!(offset_of!(CameraUniforms, inverse_projection) == LAYOUT[4].1);
+const _: () = assert!(offset_of!(CameraUniforms, inverse_view_projection) == LAYOUT[5].1);
+const _: () = assert!(offset_of!(CameraUniforms, eye) == LAYOUT[6].1);
+const _: () = assert!(offset_of!(CameraUniforms, time) == LAYOUT[7].1);
+const _: () = assert!(offset_of!(CameraUniforms, viewport) == LAYOUT[8].1);
+const _: () = assert!(size_of::<CameraUniforms>() == STD140_SIZE);
+
+impl CameraUniforms {
+	pub fn new(view: Matrix4<f32>, projection: Matrix4<f32>, viewport: Vector2<f32>, time: f32) -> CameraUniforms {
+		let view_projection = projection * view;
+		let inverse_view = view.invert().unwrap_or_else(Matrix4::identity);
+		CameraUniforms {
+			view,
+			projection,
+			view_projection,
+			inverse_view,
+			inverse_projection: projection.invert().unwrap_or_else(Matrix4::identity),
+			inverse_view_projection: view_projection.invert().unwrap_or_else(Matrix4::identity),
+			// The eye is wherever the view matrix takes the origin from
+			eye: inverse_view.w.truncate() / inverse_view.w.w,
+			time,
+			viewport,
+			_padding: [0.0; 2],
+		}
+	}
+}
+
+/// Check a program's `Camera` block against the Rust layout.  Programs without the block are fine.
+pub fn check_camera_block(reflection: &ProgramReflection) -> Result<(), String> {
+	let block = match reflection.uniform_block("Camera") {
+		Some(block) => block,
+		None => return Ok(()),
+	};
+	if block.binding as GLuint != CAMERA_BINDING {
+		return Err(format!(
+			"Camera block is bound to {} instead of {}",
+			block.binding, CAMERA_BINDING
+		));
+	}
+	if block.data_size as usize != STD140_SIZE {
+		return Err(format!(
+			"Camera block is {} bytes in GLSL but {} bytes in Rust",
+			block.data_size, STD140_SIZE
+		));
+	}
+	for (member, offset) in LAYOUT.iter() {
+		let uniform = match reflection.uniform(&format!("Camera.{}", member)) {
+			Some(uniform) => uniform,
+			// Unused members may be optimized out.
+			None => continue,
+		};
+		if let Some((_, glsl_offset)) = uniform.block {
+			if glsl_offset as usize != *offset {
+				return Err(format!(
+					"Camera.{} is at offset {} in GLSL but {} in Rust",
+					member, glsl_offset, offset
+				));
+			}
+		}
+	}
+	Ok(())
+}
+
+/// The uniform buffer holding the `Camera` block.  Upload once per frame and every program that
+/// declares the block sees the same camera.
+pub struct CameraBuffer {
+	buffer_id: GLuint,
+}
+impl CameraBuffer {
+	pub fn new() -> CameraBuffer {
+		let mut buffer_id = 0;
+		unsafe {
+			gl::GenBuffers(1, &mut buffer_id);
+			gl::BindBuffer(gl::UNIFORM_BUFFER, buffer_id);
+			gl::BufferData(
+				gl::UNIFORM_BUFFER,
+				size_of::<CameraUniforms>() as isize,
+				ptr::null(),
+				gl::DYNAMIC_DRAW,
+			);
+			gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+			gl::BindBufferBase(gl::UNIFORM_BUFFER, CAMERA_BINDING, buffer_id);
+		}
+		CameraBuffer { buffer_id }
+	}
+	pub fn upload(&self, uniforms: &CameraUniforms) {
+		unsafe {
+			gl::BindBuffer(gl::UNIFORM_BUFFER, self.buffer_id);
+			gl::BufferSubData(
+				gl::UNIFORM_BUFFER,
+				0,
+				size_of::<CameraUniforms>() as isize,
+				uniforms as *const CameraUniforms as *const c_void,
+			);
+			gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+			// Something else may have taken over the binding point in the meantime
+			gl::BindBufferBase(gl::UNIFORM_BUFFER, CAMERA_BINDING, self.buffer_id);
+		}
+	}
+}
+impl Drop for CameraBuffer {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteBuffers(1, &self.buffer_id);
+		}
+	}
+}
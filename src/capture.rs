@@ -0,0 +1,343 @@
+use crate::{framebuffer::flip_rows, gl};
+use gl::types::*;
+
+use image::RgbaImage;
+
+use std::{
+	collections::VecDeque,
+	fs,
+	path::{Path, PathBuf},
+	sync::{
+		mpsc::{self, SyncSender},
+		Arc, Mutex,
+	},
+	thread::{self, JoinHandle},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const SCREENSHOT_DIR: &str = "./screenshots";
+pub const RECORDING_DIR: &str = "./recordings";
+
+/// How many readbacks can be in flight before `request` waits for the oldest one
+const MAX_IN_FLIGHT: usize = 4;
+/// Threads encoding PNGs
+const WRITER_THREADS: usize = 3;
+/// How many images can wait for a writer before `PboReader` waits for one to free up
+const MAX_QUEUED: usize = 8;
+
+struct PendingRead {
+	pbo: GLuint,
+	/// Size of the buffer's storage in bytes
+	capacity: usize,
+	fence: GLsync,
+	width: u32,
+	height: u32,
+	path: PathBuf,
+}
+
+/// Reads the framebuffer into pixel buffer objects so that `glReadPixels` returns right away, and
+/// maps them a few frames later once the GPU is done.  The PNGs are encoded on background threads.
+#[derive(Default)]
+pub struct PboReader {
+	/// Buffers that aren't in use, with their capacity
+	free: Vec<(GLuint, usize)>,
+	pending: VecDeque<PendingRead>,
+	allocated: usize,
+	/// Started by the first image, and stopped by `flush`
+	writers: Option<ImageWriters>,
+}
+impl PboReader {
+	pub fn new() -> PboReader {
+		PboReader::default()
+	}
+	/// Start reading the bottom left `width` x `height` pixels of the current read buffer.  The
+	/// image is saved to `path` once it's available.
+	pub fn request(&mut self, width: u32, height: u32, path: PathBuf) {
+		let size = width as usize * height as usize * 4;
+		if self.free.is_empty() && self.allocated >= MAX_IN_FLIGHT {
+			self.finish_oldest(true);
+		}
+		let (pbo, capacity) = self.free.pop().unwrap_or_else(|| {
+			self.allocated += 1;
+			let mut pbo = 0;
+			unsafe {
+				gl::GenBuffers(1, &mut pbo);
+			}
+			(pbo, 0)
+		});
+		unsafe {
+			gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pbo);
+			if capacity < size {
+				gl::BufferData(gl::PIXEL_PACK_BUFFER, size as GLsizeiptr, std::ptr::null(), gl::STREAM_READ);
+			}
+			gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+			// With a pack buffer bound the pointer is an offset into it
+			gl::ReadPixels(
+				0,
+				0,
+				width as GLsizei,
+				height as GLsizei,
+				gl::RGBA,
+				gl::UNSIGNED_BYTE,
+				std::ptr::null_mut(),
+			);
+			gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+			let fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+			self.pending.push_back(PendingRead {
+				pbo,
+				capacity: capacity.max(size),
+				fence,
+				width,
+				height,
+				path,
+			});
+		}
+	}
+
+	/// Save every readback that the GPU has finished.  Call once per frame.
+	pub fn poll(&mut self) {
+		while self.finish_oldest(false) {}
+	}
+	/// Wait for everything that's still in flight and for the images to be written.
+	pub fn flush(&mut self) {
+		while self.finish_oldest(true) {}
+		if let Some(writers) = self.writers.take() {
+			writers.finish();
+		}
+	}
+	pub fn is_busy(&self) -> bool {
+		!self.pending.is_empty()
+	}
+
+	/// Map and save the oldest readback if it's done (or always, when `wait` is set).  Returns
+	/// whether one was saved.
+	fn finish_oldest(&mut self, wait: bool) -> bool {
+		let read = match self.pending.front() {
+			Some(read) => read,
+			None => return false,
+		};
+		unsafe {
+			let (flags, timeout) = if wait {
+				(gl::SYNC_FLUSH_COMMANDS_BIT, u64::MAX)
+			} else {
+				(0, 0)
+			};
+			let status = gl::ClientWaitSync(read.fence, flags, timeout);
+			if status != gl::ALREADY_SIGNALED && status != gl::CONDITION_SATISFIED {
+				return false;
+			}
+		}
+		let read = self.pending.pop_front().unwrap();
+		let size = read.width as usize * read.height as usize * 4;
+		let mut pixels = vec![0u8; size];
+		unsafe {
+			gl::DeleteSync(read.fence);
+			gl::BindBuffer(gl::PIXEL_PACK_BUFFER, read.pbo);
+			let mapped = gl::MapBufferRange(gl::PIXEL_PACK_BUFFER, 0, size as GLsizeiptr, gl::MAP_READ_BIT);
+			if !mapped.is_null() {
+				pixels.copy_from_slice(std::slice::from_raw_parts(mapped as *const u8, size));
+				gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+			}
+			gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+		}
+		self.free.push((read.pbo, read.capacity));
+
+		let PendingRead { width, height, path, .. } = read;
+		self.writers.get_or_insert_with(ImageWriters::start).write(WriteJob { pixels, width, height, path });
+		true
+	}
+}
+impl Drop for PboReader {
+	fn drop(&mut self) {
+		unsafe {
+			for read in self.pending.drain(..) {
+				gl::DeleteSync(read.fence);
+				gl::DeleteBuffers(1, &read.pbo);
+			}
+			for (pbo, _) in self.free.drain(..) {
+				gl::DeleteBuffers(1, &pbo);
+			}
+		}
+	}
+}
+
+/// A frame read back into memory, bottom row first
+struct WriteJob {
+	pixels: Vec<u8>,
+	width: u32,
+	height: u32,
+	path: PathBuf,
+}
+impl WriteJob {
+	fn save(mut self) {
+		flip_rows(&mut self.pixels, self.width as usize * 4);
+		let image = RgbaImage::from_raw(self.width, self.height, self.pixels).expect("The pixel buffer matches the image size");
+		if let Err(err) = image.save(&self.path) {
+			eprintln!("Couldn't save {}: {}", self.path.display(), err);
+		}
+	}
+}
+
+/// A fixed number of threads encoding and saving images, fed through a bounded queue so that
+/// recording can't pile up frames faster than they're written.
+struct ImageWriters {
+	sender: SyncSender<WriteJob>,
+	threads: Vec<JoinHandle<()>>,
+}
+impl ImageWriters {
+	fn start() -> ImageWriters {
+		let (sender, receiver) = mpsc::sync_channel::<WriteJob>(MAX_QUEUED);
+		let receiver = Arc::new(Mutex::new(receiver));
+		let threads = (0..WRITER_THREADS)
+			.map(|_| {
+				let receiver = Arc::clone(&receiver);
+				thread::spawn(move || loop {
+					// The lock is only held while waiting for the next job, not while saving it
+					let job = receiver.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).recv();
+					match job {
+						Ok(job) => job.save(),
+						// `finish` hung up
+						Err(_) => break,
+					}
+				})
+			})
+			.collect();
+		ImageWriters { sender, threads }
+	}
+	/// Queue `job`, waiting while the queue is full
+	fn write(&self, job: WriteJob) {
+		if let Err(mpsc::SendError(job)) = self.sender.send(job) {
+			// Only if every writer panicked
+			job.save();
+		}
+	}
+	/// Wait for the queued images to be saved.
+	fn finish(self) {
+		drop(self.sender);
+		for thread in self.threads {
+			let _ = thread.join();
+		}
+	}
+}
+
+struct Recording {
+	dir: PathBuf,
+	frame: u32,
+}
+
+/// Screenshots and numbered frame sequences of the running demo.
+#[derive(Default)]
+pub struct Capture {
+	reader: PboReader,
+	screenshot_requested: bool,
+	recording: Option<Recording>,
+}
+impl Capture {
+	pub fn new() -> Capture {
+		Capture::default()
+	}
+	/// Save the next frame that's drawn.
+	pub fn request_screenshot(&mut self) {
+		self.screenshot_requested = true;
+	}
+	/// Start or stop saving every frame into a new directory under `RECORDING_DIR`.
+	pub fn toggle_recording(&mut self) {
+		match self.recording.take() {
+			Some(recording) => println!("Recorded {} frames to {}", recording.frame, recording.dir.display()),
+			None => {
+				let dir = Path::new(RECORDING_DIR).join(timestamp());
+				match fs::create_dir_all(&dir) {
+					Ok(()) => {
+						println!("Recording to {}", dir.display());
+						self.recording = Some(Recording { dir, frame: 0 });
+					}
+					Err(err) => eprintln!("Couldn't create {}: {}", dir.display(), err),
+				}
+			}
+		}
+	}
+	pub fn is_recording(&self) -> bool {
+		self.recording.is_some()
+	}
+	/// Start reading back the frame if it should be saved.  Has to be called after drawing but before
+	/// the buffers are swapped, with the frame as the read buffer.
+	pub fn frame_drawn(&mut self, width: u32, height: u32) {
+		if self.screenshot_requested {
+			self.screenshot_requested = false;
+			match fs::create_dir_all(SCREENSHOT_DIR) {
+				Ok(()) => {
+					let path = Path::new(SCREENSHOT_DIR).join(format!("screenshot_{}.png", timestamp()));
+					println!("Screenshot: {}", path.display());
+					self.reader.request(width, height, path);
+				}
+				Err(err) => eprintln!("Couldn't create {}: {}", SCREENSHOT_DIR, err),
+			}
+		}
+		if let Some(recording) = &mut self.recording {
+			let path = recording.dir.join(format!("frame_{:05}.png", recording.frame));
+			recording.frame += 1;
+			self.reader.request(width, height, path);
+		}
+	}
+	/// Save the frames whose readback has finished.
+	pub fn poll(&mut self) {
+		self.reader.poll();
+	}
+	/// Save everything that's still in flight.
+	pub fn flush(&mut self) {
+		self.reader.flush();
+	}
+}
+
+/// The current UTC time as `2019-10-18_14-03-22.123`
+fn timestamp() -> String {
+	let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+	let seconds = since_epoch.as_secs();
+	let (days, time) = (seconds / 86400, seconds % 86400);
+	// Days to a date in the proleptic Gregorian calendar: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+	let z = days as i64 + 719_468;
+	let era = z.div_euclid(146_097);
+	let day_of_era = z.rem_euclid(146_097);
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let mp = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = year_of_era + era * 400 + (month <= 2) as i64;
+	format!(
+		"{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.{:03}",
+		year,
+		month,
+		day,
+		time / 3600,
+		time / 60 % 60,
+		time % 60,
+		since_epoch.subsec_millis()
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn writers_save_every_queued_image() {
+		let dir = std::env::temp_dir().join(format!("capture-test-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let writers = ImageWriters::start();
+		// More than the queue holds, so `write` has to wait for the writers
+		let frames = MAX_QUEUED * 2 + 1;
+		for frame in 0..frames {
+			// Two rows: red at the bottom, green at the top
+			let pixels = vec![255, 0, 0, 255, 0, 255, 0, 255];
+			writers.write(WriteJob { pixels, width: 1, height: 2, path: dir.join(format!("frame_{}.png", frame)) });
+		}
+		writers.finish();
+		for frame in 0..frames {
+			let image = image::open(dir.join(format!("frame_{}.png", frame))).unwrap().to_rgba();
+			assert_eq!(image.get_pixel(0, 0).0, [0, 255, 0, 255]);
+			assert_eq!(image.get_pixel(0, 1).0, [255, 0, 0, 255]);
+		}
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -1,19 +1,22 @@
 #![allow(bad_style)]
 #![allow(unused)]
 use glutin::{
-	dpi::{LogicalPosition, LogicalSize},
+	dpi::{LogicalPosition, LogicalSize, PhysicalPosition, PhysicalSize},
 	event::*,
 	event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
 	window::WindowBuilder,
 	Api::OpenGl,
-	ContextBuilder, GlRequest, PossiblyCurrent, WindowedContext,
+	Context, ContextBuilder, GlRequest, PossiblyCurrent, WindowedContext,
 };
 
-use cgmath::{Deg, Matrix, Matrix4, SquareMatrix, PerspectiveFov, Point3, Vector3};
+use image::RgbaImage;
+
+use cgmath::{prelude::*, Deg, Matrix, Matrix4, SquareMatrix, PerspectiveFov, Point3, Vector2, Vector3, Vector4};
 
 use std::{
 	cell::RefCell,
 	ffi::CStr,
+	path::Path,
 	rc::Rc,
 	thread,
 	time::{Duration, Instant},
@@ -21,47 +24,213 @@ use std::{
 
 use super::{
 	gl,
-	gl::types::*
+	gl::types::*,
+	capture::Capture,
+	framebuffer::{read_pixels, Framebuffer},
+	camera::{self, CameraBuffer, CameraController, CameraUniforms, FirstPerson, Fly, Orbit, Projection},
+	input::{ActionMap, Binding, Input, BINDINGS_FILE},
+	timestep::FixedTimestep,
 };
 // use crate::light::*;
 // use crate::material::*;
 // use super::mesh::*;
 
+/// Input that scene items get a chance to handle before the camera and the action map.
+/// Positions and sizes are in physical pixels, the same as the viewport.
+#[derive(Clone, Copy, Debug)]
+pub enum SceneEvent {
+	Key {
+		key: VirtualKeyCode,
+		state: ElementState,
+		modifiers: ModifiersState,
+	},
+	MouseButton {
+		button: MouseButton,
+		state: ElementState,
+		/// Where the cursor was when the button changed state
+		position: PhysicalPosition,
+		modifiers: ModifiersState,
+	},
+	CursorMoved {
+		position: PhysicalPosition,
+	},
+	/// Scrolling in lines: positive y scrolls away from the user.
+	Scroll {
+		delta_x: f32,
+		delta_y: f32,
+	},
+	/// Sent to every item right after `SceneItem::resize`.  Consuming it has no effect.
+	Resized(PhysicalSize),
+}
+
+/// Whether an event should keep going to the other scene items (and then the camera / actions).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventResponse {
+	Consumed,
+	Ignored,
+}
+
+/// Handed to scene items once the GL context has been created.  Having one means the context is
+/// current and the GL functions are loaded, so this is where items should create their GL objects.
+pub struct GlContext<'a> {
+	pub version: &'a str,
+	pub size: PhysicalSize,
+}
+
+/// Where the camera is for the coming frame.
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+	pub eye: Point3<f32>,
+	pub forward: Vector3<f32>,
+	pub view: Matrix4<f32>,
+	pub projection: Matrix4<f32>,
+	/// In physical pixels
+	pub viewport: Vector2<f32>,
+	/// The normalized device depth of the near and the far plane (see `Projection::depth_range`)
+	pub depth_range: (f32, f32),
+}
+impl View {
+	/// The ray from the near plane through the pixel at `cursor`, with a direction of length 1.
+	/// Works for both perspective and orthographic projections.
+	pub fn ray(&self, cursor: PhysicalPosition) -> (Point3<f32>, Vector3<f32>) {
+		let x = 2.0 * cursor.x as f32 / self.viewport.x - 1.0;
+		let y = 1.0 - 2.0 * cursor.y as f32 / self.viewport.y;
+		let inverse = (self.projection * self.view).invert().unwrap_or_else(Matrix4::identity);
+		let unproject = |depth: f32| Point3::from_homogeneous(inverse * Vector4::new(x, y, depth, 1.0));
+		let (near, far) = self.depth_range;
+		// Halfway in depth rather than the far plane, which may be infinitely far away
+		let start = unproject(near);
+		(start, (unproject((near + far) / 2.0) - start).normalize())
+	}
+}
+
 pub trait SceneItem {
+	/// Create GL resources.  Called once before the first `anim` / `draw`.
+	fn init(&mut self, _gl: &GlContext) {}
+	/// Advance the simulation by one fixed step.  `update` is always the same length (see `FixedTimestep`).
 	fn anim(&mut self, update: Duration);
-	fn draw(&self);
+	/// `alpha` is how far (0 to 1) the frame lies between the last simulation step and the next,
+	/// for items that want to interpolate their motion.  Since `anim` can run several times per
+	/// frame, this is also the place to upload what changed (once).
+	fn draw(&mut self, alpha: f32);
+	/// Called before every `draw`, for items that depend on where the camera is.
+	fn view(&mut self, _view: &View) {}
+	/// The framebuffer changed size.
+	fn resize(&mut self, _size: PhysicalSize) {}
+	/// Release GL resources.  The context is still current.
+	fn shutdown(&mut self) {}
+
+	/// Called for every input event, in scene order.  Returning `Consumed` stops the event from
+	/// reaching later items, the camera and the action map.
+	fn handle_event(&mut self, _event: &SceneEvent, _input: &Input) -> EventResponse {
+		EventResponse::Ignored
+	}
+	/// Register the actions this item responds to along with their default bindings.
+	fn register_actions(&self, _actions: &mut ActionMap) {}
+	/// Called when a key / button bound to an action is pressed.
+	fn action(&mut self, _name: &str) {}
 }
 
-pub struct ButtonStates {
-	pub left: ElementState,
-	pub middle: ElementState,
-	pub right: ElementState,
+/// What the demo draws into: a window, or an offscreen framebuffer when running headless.
+pub enum Surface {
+	Window(WindowedContext<PossiblyCurrent>),
+	Headless {
+		// Declared first so it's dropped while the context still exists
+		framebuffer: Framebuffer,
+		context: Context<PossiblyCurrent>,
+	},
+}
+impl Surface {
+	/// Load the GL functions (the context has to be current) and create the framebuffer to draw into.
+	pub fn headless(context: Context<PossiblyCurrent>, size: PhysicalSize) -> Result<Surface, String> {
+		gl::load_with(|ptr| context.get_proc_address(ptr) as *const _);
+		let framebuffer = Framebuffer::new(size.width as u32, size.height as u32)?;
+		Ok(Surface::Headless { framebuffer, context })
+	}
+	pub fn get_proc_address(&self, name: &str) -> *const () {
+		match self {
+			Surface::Window(context) => context.context().get_proc_address(name),
+			Surface::Headless { context, .. } => context.get_proc_address(name),
+		}
+	}
+	pub fn window(&self) -> Option<&glutin::window::Window> {
+		match self {
+			Surface::Window(context) => Some(context.window()),
+			Surface::Headless { .. } => None,
+		}
+	}
+	pub fn hidpi_factor(&self) -> f64 {
+		self.window().map_or(1.0, |window| window.hidpi_factor())
+	}
+	/// The size of the framebuffer in pixels
+	pub fn size(&self) -> PhysicalSize {
+		match self {
+			Surface::Window(context) => context.window().inner_size().to_physical(self.hidpi_factor()),
+			Surface::Headless { framebuffer, .. } => {
+				PhysicalSize::new(framebuffer.width() as f64, framebuffer.height() as f64)
+			}
+		}
+	}
+	/// Make this the target for drawing.
+	pub fn bind(&self) {
+		match self {
+			Surface::Window(_) => unsafe {
+				gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+				gl::DrawBuffer(gl::BACK);
+				gl::ReadBuffer(gl::BACK);
+			},
+			Surface::Headless { framebuffer, .. } => framebuffer.bind(),
+		}
+	}
+	/// Read back what has been drawn so far.  For a window this has to happen before `present`.
+	pub fn capture(&self) -> RgbaImage {
+		self.bind();
+		let size = self.size();
+		read_pixels(size.width as u32, size.height as u32)
+	}
+	/// Show the finished frame.
+	pub fn present(&self) {
+		match self {
+			Surface::Window(context) => context.swap_buffers().unwrap(),
+			Surface::Headless { .. } => unsafe {
+				gl::Flush();
+			},
+		}
+	}
 }
 
 pub struct Demo {
-	pub wrapped_context: WindowedContext<PossiblyCurrent>,
-
-	last_animate: Option<Instant>,
-	pub button_states: ButtonStates,
+	timestep: FixedTimestep,
+	last_frame: Instant,
+	/// When the next frame should start.  The event loop sleeps until then.
+	next_frame: Instant,
+	frame_interval: Duration,
+	camera_buffer: CameraBuffer,
+	pub projection: Projection,
+	pub input: Input,
 	paused: bool,
+	/// The last known cursor position, for mouse button events
+	cursor: PhysicalPosition,
+	capture: Capture,
 
-	yrot: Deg<f32>,
-	xrot: Deg<f32>,
-	scale: f32,
+	controllers: Vec<Box<dyn CameraController>>,
+	active_controller: usize,
 
 	scene_items: Vec<Box<dyn SceneItem>>,
+
+	// Last, so that everything holding GL objects is dropped before the context
+	pub surface: Surface,
 }
 
 impl Demo {
 	pub fn new(
-		wrapped_context: WindowedContext<PossiblyCurrent>,
-		scene_items: Vec<&mut dyn FnMut() -> Box<dyn SceneItem>>,
+		surface: Surface,
+		mut scene_items: Vec<Box<dyn SceneItem>>,
 	) -> Demo {
-		println!(
-			"Pixel format of the window's GL context: {:?}",
-			wrapped_context.get_pixel_format()
-		);
-		gl::load_with(|ptr| wrapped_context.context().get_proc_address(ptr) as *const _);
+		if let Surface::Window(context) = &surface {
+			println!("Pixel format of the window's GL context: {:?}", context.get_pixel_format());
+		}
+		gl::load_with(|ptr| surface.get_proc_address(ptr) as *const _);
 
 		let version = unsafe {
 			let data = CStr::from_ptr(gl::GetString(gl::VERSION) as *const _)
@@ -75,117 +244,252 @@ impl Demo {
 			gl::Enable(gl::DEPTH_TEST);
 		}
 
-		let scene_items = scene_items.into_iter().map(|item| item()).collect();
+		let mut input = Input::default();
+		input.actions.register("demo.pause", Binding::Key(VirtualKeyCode::F));
+		input.actions.register("demo.step", Binding::Key(VirtualKeyCode::Period));
+		input.actions.register("demo.slower", Binding::Key(VirtualKeyCode::LBracket));
+		input.actions.register("demo.faster", Binding::Key(VirtualKeyCode::RBracket));
+		input.actions.register("demo.cycle_camera", Binding::Key(VirtualKeyCode::V));
+		input.actions.register("capture.screenshot", Binding::Key(VirtualKeyCode::F12));
+		input.actions.register("capture.record", Binding::Key(VirtualKeyCode::F9));
+		input.actions.register("projection.toggle_mode", Binding::Key(VirtualKeyCode::O));
+		input.actions.register("projection.toggle_reversed_z", Binding::Key(VirtualKeyCode::Z));
+		camera::register_actions(&mut input.actions);
+		for item in scene_items.iter() {
+			item.register_actions(&mut input.actions);
+		}
+		if let Err(err) = input.actions.load(Path::new(BINDINGS_FILE)) {
+			println!("{}", err);
+		}
+
+		// Where the camera starts out: the scene used to be scaled by 0.5 and viewed from (2.5, 2.5, 4.0)
+		let eye = Point3::new(5.0, 5.0, 8.0);
+
+		let size = surface.size();
+		let projection = Projection::new(size);
+
+		let gl_context = GlContext { version: &version, size };
+		for item in scene_items.iter_mut() {
+			item.init(&gl_context);
+		}
 
 		Demo {
-			wrapped_context,
-			last_animate: None,
-			yrot: Deg(0.0),
-			xrot: Deg(0.0),
-			scale: 0.5,
+			surface,
+			timestep: FixedTimestep::new(Duration::from_secs(1) / 60),
+			last_frame: Instant::now(),
+			next_frame: Instant::now(),
+			frame_interval: Duration::from_secs(1) / 60,
+			camera_buffer: CameraBuffer::new(),
+			projection,
+			controllers: vec![
+				Box::new(Orbit::new(eye, Point3::new(0.0, 0.0, 0.0))),
+				Box::new(Fly::new(eye, -eye.to_vec())),
+				Box::new(FirstPerson::new(eye, 2.0)),
+			],
+			active_controller: 0,
 			// fog_on: false
-			button_states: ButtonStates {
-				left: ElementState::Released,
-				middle: ElementState::Released,
-				right: ElementState::Released,
-			},
+			input,
 			scene_items,
 			paused: false,
+			cursor: PhysicalPosition::new(0.0, 0.0),
+			capture: Capture::new(),
 		}
 	}
 	pub fn draw(&mut self) {
 		unsafe {
-			gl::DrawBuffer(gl::BACK);
+			self.surface.bind();
+			self.projection.apply_depth_state();
 			gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
-			// Calculate the projection matrix:
-			// This is essentially a gluPerspective call
-			let projection: Matrix4<f32> = PerspectiveFov {
-				fovy: Deg(90.0).into(),
-				aspect: 1.0,
-				near: 0.1,
-				far: 1000.0,
-			}.into();
+			let projection = self.projection.matrix();
 
-			// Calculate the view matrix:
-			let mut view_matrix: Matrix4<f32> = Matrix4::look_at(
-				Point3::new(2.5, 2.5, 4.0), // Eye location
-				Point3::new(0.0, 0.0, 0.0), // Center Point / Point of interest
-				Vector3::new(0.0, 1.0, 0.0), // Up vector
-			);
-			// let mut view_matrix: Matrix4<f32> = Matrix4::identity();
-			view_matrix = view_matrix * Matrix4::from_angle_x(self.xrot);
-			view_matrix = view_matrix * Matrix4::from_angle_y(self.yrot);
-			view_matrix = view_matrix * Matrix4::from_scale(self.scale);
+			let controller = &self.controllers[self.active_controller];
+			let view_matrix = controller.view_matrix();
+			let view = View {
+				eye: controller.eye(),
+				forward: controller.forward(),
+				view: view_matrix,
+				projection,
+				viewport: self.projection.size(),
+				depth_range: self.projection.depth_range(),
+			};
 
-			// Setup the make view_matrix + projection available to shaders that want them.
-			let mut program = 0;
-			gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut program);
-			if program != 0 {
-				let location = gl::GetUniformLocation(program as u32, b"view_matrix\0".as_ptr() as *const _);
-				if location != -1 {
-					gl::UniformMatrix4fv(
-						location,
-						1,
-						gl::FALSE,
-						view_matrix.as_ptr()
-					);
-				}
-				let location = gl::GetUniformLocation(program as u32, b"projection\0".as_ptr() as *const _);
-				if (location != -1) {
-					gl::UniformMatrix4fv(
-						location,
-						1,
-						gl::FALSE,
-						projection.as_ptr()
-					);
-				}
-			}
+			// Make the camera available to every shader that declares the Camera block.
+			self.camera_buffer.upload(&CameraUniforms::new(
+				view_matrix,
+				projection,
+				self.projection.size(),
+				self.timestep.time().as_secs_f32(),
+			));
+
+			// The display list meshes in src/mesh/ still use the fixed function matrices
+			gl::MatrixMode(gl::PROJECTION);
+			gl::LoadMatrixf(projection.as_ptr());
+			gl::MatrixMode(gl::MODELVIEW);
+			gl::LoadMatrixf(view_matrix.as_ptr());
 
 			// Draw all the scene items:
+			let alpha = self.timestep.alpha();
 			for item in &mut self.scene_items {
-				item.draw();
+				item.view(&view);
+				item.draw(alpha);
 			}
 
+			// Screenshots / recording read the back buffer, so it has to happen before presenting
+			self.surface.bind();
+			let size = self.projection.size();
+			self.capture.frame_drawn(size.x as u32, size.y as u32);
+
 			gl::Flush();
 		}
-		self.wrapped_context.swap_buffers().unwrap();
+		self.surface.present();
+		self.capture.poll();
 	}
+	/// Run the simulation steps that are due and request a redraw.
 	pub fn animate(&mut self) {
 		let now = Instant::now();
-		if !self.paused {
-			match self.last_animate {
-				None => {
-					// We haven't animated yet.
-				}
-				Some(last_inst) => {
-					// Diff is the # of miliseconds since the last animate call.
-					let diff = now.duration_since(last_inst);
-					for item in &mut self.scene_items {
-						item.anim(diff);
-					}
-				}
+		let mut elapsed = now.duration_since(self.last_frame);
+		self.last_frame = now;
+		if self.capture.is_recording() {
+			// Every recorded frame is exactly one frame apart in simulated time, however long it took to
+			// draw and save, so the sequence plays back at the right speed.
+			elapsed = self.frame_interval;
+		}
+		self.advance(elapsed);
+		if let Some(window) = self.surface.window() {
+			window.request_redraw();
+		}
+	}
+	/// Move the camera and run the simulation steps that fit into `elapsed`.
+	pub fn advance(&mut self, elapsed: Duration) {
+		// The camera moves in real time, even while the scene is paused or slowed down
+		self.controllers[self.active_controller].update(elapsed, &self.input);
+
+		for _ in 0..self.timestep.advance(elapsed, self.paused) {
+			for item in &mut self.scene_items {
+				item.anim(self.timestep.step);
 			}
 		}
-		self.last_animate = Some(now);
-		self.wrapped_context.window().request_redraw();
+	}
+	/// How often frames are drawn
+	pub fn frame_interval(&self) -> Duration {
+		self.frame_interval
 	}
 	pub fn resized(&mut self, logical_size: &LogicalSize) {
-		let dpi_factor = self.wrapped_context.window().hidpi_factor();
-		self.wrapped_context
-			.resize(logical_size.to_physical(dpi_factor));
+		let dpi_factor = self.surface.hidpi_factor();
+		let physical_size = logical_size.to_physical(dpi_factor);
+		if let Surface::Window(context) = &self.surface {
+			context.resize(physical_size);
+		}
+		self.projection.resize(physical_size);
+		for item in self.scene_items.iter_mut() {
+			item.resize(physical_size);
+			item.handle_event(&SceneEvent::Resized(physical_size), &self.input);
+		}
 	}
-	pub fn mouse_move(&mut self, diff_x: f32, diff_y: f32) {
-		if self.button_states.left == ElementState::Pressed {
-			self.yrot += Deg(diff_x / 10.0);
-			self.xrot += Deg(diff_y / 10.0);
+	/// Let each scene item release its GL resources.  Called once when the event loop ends.
+	pub fn shutdown(&mut self) {
+		self.capture.flush();
+		for item in self.scene_items.iter_mut() {
+			item.shutdown();
+		}
+	}
+	/// Offer an event to the scene items in order.  Returns `Consumed` if one of them took it.
+	pub fn dispatch(&mut self, event: &SceneEvent) -> EventResponse {
+		for item in self.scene_items.iter_mut() {
+			if item.handle_event(event, &self.input) == EventResponse::Consumed {
+				return EventResponse::Consumed;
+			}
 		}
+		EventResponse::Ignored
+	}
+	/// Turn a window event into a scene event (if it's one that scene items care about).
+	fn scene_event(&mut self, event: &WindowEvent) -> Option<SceneEvent> {
+		let dpi_factor = self.surface.hidpi_factor();
+		match event {
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput {
+					virtual_keycode: Some(key),
+					state,
+					modifiers,
+					..
+				},
+				..
+			} => Some(SceneEvent::Key {
+				key: *key,
+				state: *state,
+				modifiers: *modifiers,
+			}),
+			WindowEvent::MouseInput { button, state, modifiers, .. } => Some(SceneEvent::MouseButton {
+				button: *button,
+				state: *state,
+				position: self.cursor,
+				modifiers: *modifiers,
+			}),
+			WindowEvent::CursorMoved { position, .. } => {
+				self.cursor = position.to_physical(dpi_factor);
+				Some(SceneEvent::CursorMoved { position: self.cursor })
+			}
+			WindowEvent::MouseWheel { delta, .. } => {
+				let (delta_x, delta_y) = match delta {
+					MouseScrollDelta::LineDelta(x, y) => (*x, *y),
+					// Treat 20 pixels as a line
+					MouseScrollDelta::PixelDelta(LogicalPosition { x, y }) => (*x as f32 / 20.0, *y as f32 / 20.0),
+				};
+				Some(SceneEvent::Scroll { delta_x, delta_y })
+			}
+			_ => None,
+		}
+	}
+	pub fn mouse_move(&mut self, diff_x: f32, diff_y: f32) {
+		self.controllers[self.active_controller].mouse_move(diff_x, diff_y, &self.input);
+	}
+	/// Switch to the next camera controller, starting it out where the current one is.
+	pub fn cycle_controller(&mut self) {
+		let (eye, forward) = {
+			let current = &self.controllers[self.active_controller];
+			(current.eye(), current.forward())
+		};
+		self.active_controller = (self.active_controller + 1) % self.controllers.len();
+		let next = &mut self.controllers[self.active_controller];
+		next.set_pose(eye, forward);
+		println!("Camera: {}", next.name());
 	}
 	pub fn toggle_paused(&mut self) {
 		self.paused = !self.paused;
 	}
+	/// Advance the simulation by a single step.  Only does anything while paused.
+	pub fn single_step(&mut self) {
+		if self.paused {
+			self.timestep.single_step();
+		}
+	}
+	pub fn scale_time(&mut self, factor: f32) {
+		self.timestep.scale_time(factor);
+		println!("Time scale: {}x", self.timestep.time_scale);
+	}
 	pub fn scroll_delta(&mut self, diff_y: f32) {
-		self.scale -= diff_y / 20.0;
+		self.controllers[self.active_controller].scroll(diff_y);
+	}
+	/// Run every action bound to a key / button that was just pressed.  Scene items get a look at all of them.
+	pub fn trigger(&mut self, binding: Binding) {
+		for action in self.input.actions.actions_for(binding) {
+			match action.as_str() {
+				"demo.pause" => self.toggle_paused(),
+				"demo.step" => self.single_step(),
+				"demo.slower" => self.scale_time(0.5),
+				"demo.faster" => self.scale_time(2.0),
+				"demo.cycle_camera" => self.cycle_controller(),
+				"capture.screenshot" => self.capture.request_screenshot(),
+				"capture.record" => self.capture.toggle_recording(),
+				"projection.toggle_mode" => self.projection.toggle_mode(),
+				"projection.toggle_reversed_z" => self.projection.toggle_reversed_z(),
+				_ => {}
+			}
+			for item in self.scene_items.iter_mut() {
+				item.action(&action);
+			}
+		}
 	}
 	pub fn handle_event(
 		&mut self,
@@ -194,7 +498,6 @@ impl Demo {
 		control_flow: &mut ControlFlow,
 	) {
 		match event {
-			// Event::LoopDestroyed => {},
 			Event::WindowEvent {
 				ref event,
 				window_id,
@@ -203,57 +506,60 @@ impl Demo {
 					WindowEvent::Resized(logical_size) => {
 						self.resized(logical_size);
 					}
+					// Moving to a monitor with a different DPI changes the physical size without a resize.
+					WindowEvent::HiDpiFactorChanged(_) => {
+						if let Some(window) = self.surface.window() {
+							let logical_size = window.inner_size();
+							self.resized(&logical_size);
+						}
+					}
 					WindowEvent::RedrawRequested => {
 						self.draw();
 					}
 					WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-					WindowEvent::ReceivedCharacter(ch) => match ch {
-						// Handle Magic Keys
-						// 'c' | 'C' => self.toggle_cockpit(),
-						'f' | 'F' => self.toggle_paused(),
-						// 'm' | 'M' => self.ocean.toggle_morph(),
-						// 't' | 'T' => self.ocean.toggle_texture(),
-						_ => {}
-					},
-					_ => {}
-				}
-			}
-			Event::DeviceEvent { ref event, .. } => match event {
-				// Pass mouse movement to the demo:
-				DeviceEvent::MouseMotion { delta: (x, y) } => self.mouse_move(*x as f32, *y as f32),
-				// Pass the mouse scroll to the demo:
-				DeviceEvent::MouseWheel { delta } => match delta {
-					MouseScrollDelta::LineDelta(_, forward) => {
-						let y = -forward;
-						self.scroll_delta(y);
+					// Scene items get first pick, then keys and mouse buttons go through the action map
+					// (see bindings.cfg).  Presses that a scene item consumed don't count as held, so that
+					// they don't also drive held actions like `camera.rotate`.
+					_ => {
+						let changed = self.input.state.handle_event(event);
+						let response = match self.scene_event(event) {
+							Some(scene_event) => self.dispatch(&scene_event),
+							None => EventResponse::Ignored,
+						};
+						if let (EventResponse::Consumed, Some((binding, ElementState::Pressed))) = (response, changed) {
+							self.input.state.forget(binding);
+						}
+						if response == EventResponse::Ignored {
+							match (changed, event) {
+								(Some((binding, ElementState::Pressed)), _) => self.trigger(binding),
+								(_, WindowEvent::MouseWheel { delta, .. }) => match delta {
+									MouseScrollDelta::LineDelta(_, forward) => self.scroll_delta(-forward),
+									MouseScrollDelta::PixelDelta(LogicalPosition { y, .. }) => {
+										self.scroll_delta(*y as f32)
+									}
+								},
+								_ => {}
+							}
+						}
 					}
-					MouseScrollDelta::PixelDelta(LogicalPosition { y, .. }) => {
-						self.scroll_delta(*y as f32);
-					}
-				},
-				// Keep the button states up to date:
-				DeviceEvent::Button { button: 1, state } => {
-					self.button_states.left = *state;
-
-					// This was part of my attempt at a context menu:
-					/* match state {
-						ElementState::Pressed => {
-							proxy.send_event(CustomEvents::Nothing).unwrap();
-						},
-						_ => ()
-					};
-					*/
 				}
-				DeviceEvent::Button { button: 2, state } => self.button_states.middle = *state,
-				DeviceEvent::Button { button: 3, state } => {
-					self.button_states.right = *state;
-				}
-				_ => {}
-			},
-			Event::NewEvents(StartCause::Poll) => {
+			}
+			// Pass mouse movement to the demo:
+			Event::DeviceEvent {
+				event: DeviceEvent::MouseMotion { delta: (x, y) },
+				..
+			} => self.mouse_move(x as f32, y as f32),
+			Event::LoopDestroyed => self.shutdown(),
+			// Frames are driven by a timer instead of polling so an idle scene doesn't spin a core
+			Event::NewEvents(StartCause::Init) | Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
 				self.animate();
+				// Don't try to make up for frames that were missed
+				self.next_frame = (self.next_frame + self.frame_interval).max(Instant::now());
 			}
 			_ => {}
 		}
+		if *control_flow != ControlFlow::Exit {
+			*control_flow = ControlFlow::WaitUntil(self.next_frame);
+		}
 	}
 }
@@ -0,0 +1,103 @@
+use crate::gl;
+use gl::types::*;
+
+use image::RgbaImage;
+
+/// An offscreen render target: an RGBA8 color renderbuffer and a 24 bit depth renderbuffer.
+pub struct Framebuffer {
+	fbo: GLuint,
+	color: GLuint,
+	depth: GLuint,
+	width: u32,
+	height: u32,
+}
+impl Framebuffer {
+	pub fn new(width: u32, height: u32) -> Result<Framebuffer, String> {
+		let mut framebuffer = Framebuffer {
+			fbo: 0,
+			color: 0,
+			depth: 0,
+			width,
+			height,
+		};
+		unsafe {
+			gl::GenFramebuffers(1, &mut framebuffer.fbo);
+			gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer.fbo);
+
+			gl::GenRenderbuffers(1, &mut framebuffer.color);
+			gl::BindRenderbuffer(gl::RENDERBUFFER, framebuffer.color);
+			gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, width as GLsizei, height as GLsizei);
+			gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, framebuffer.color);
+
+			gl::GenRenderbuffers(1, &mut framebuffer.depth);
+			gl::BindRenderbuffer(gl::RENDERBUFFER, framebuffer.depth);
+			gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT24, width as GLsizei, height as GLsizei);
+			gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, framebuffer.depth);
+			gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+			let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+			gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+			if status != gl::FRAMEBUFFER_COMPLETE {
+				return Err(format!("Framebuffer is incomplete: 0x{:X}", status));
+			}
+		}
+		Ok(framebuffer)
+	}
+	pub fn width(&self) -> u32 {
+		self.width
+	}
+	pub fn height(&self) -> u32 {
+		self.height
+	}
+	/// Draw into (and read from) this framebuffer.
+	pub fn bind(&self) {
+		unsafe {
+			gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+			gl::DrawBuffer(gl::COLOR_ATTACHMENT0);
+			gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+		}
+	}
+	/// Copy the color buffer back to the CPU.
+	pub fn read(&self) -> RgbaImage {
+		self.bind();
+		read_pixels(self.width, self.height)
+	}
+}
+impl Drop for Framebuffer {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteFramebuffers(1, &self.fbo);
+			gl::DeleteRenderbuffers(1, &self.color);
+			gl::DeleteRenderbuffers(1, &self.depth);
+		}
+	}
+}
+
+/// Read the bottom left `width` x `height` pixels of the current read buffer.  GL's rows go
+/// bottom to top, so they are flipped to match image files.
+pub fn read_pixels(width: u32, height: u32) -> RgbaImage {
+	let mut pixels = vec![0u8; width as usize * height as usize * 4];
+	unsafe {
+		gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+		gl::ReadPixels(
+			0,
+			0,
+			width as GLsizei,
+			height as GLsizei,
+			gl::RGBA,
+			gl::UNSIGNED_BYTE,
+			pixels.as_mut_ptr() as *mut _,
+		);
+	}
+	flip_rows(&mut pixels, width as usize * 4);
+	RgbaImage::from_raw(width, height, pixels).expect("The pixel buffer matches the image size")
+}
+
+/// Reverse the order of the rows in a tightly packed image.
+pub fn flip_rows(pixels: &mut [u8], row_length: usize) {
+	let rows = pixels.len() / row_length;
+	for row in 0..rows / 2 {
+		let (top, bottom) = pixels.split_at_mut((rows - row - 1) * row_length);
+		top[row * row_length..(row + 1) * row_length].swap_with_slice(&mut bottom[..row_length]);
+	}
+}
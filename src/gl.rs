@@ -1 +1,2 @@
+#![allow(clippy::all)]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
@@ -0,0 +1,352 @@
+// Golden image tests: render a scene through OSMesa (see headless.rs) and compare it against the
+// reference PNGs in tests/golden/.  The rendering tests are ignored by default, since they need a
+// software GL context: run them with `cargo test golden_ -- --ignored`.
+//
+// Set UPDATE_GOLDEN=1 to (re)write the references; without it a missing reference fails the test.
+// On a mismatch the actual and diff images are written to target/golden/.
+use crate::{
+	demo::{Demo, SceneItem},
+	headless,
+	mesh::*,
+	voxel::{BlockId, ChunkCoord, ChunkGenerator, ChunkSize, Lighting, Transparency, Voxel, VoxelProject},
+};
+
+use glutin::dpi::PhysicalSize;
+use image::{Rgba, RgbaImage};
+
+use std::{env, fs, path::PathBuf, sync::Arc, time::Duration};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+/// Pixels whose colors are closer than this (see `color_delta`) count as equal
+const PIXEL_THRESHOLD: f32 = 0.1;
+/// The fraction of pixels that may differ before the test fails.  Rasterization can change a
+/// little between Mesa versions.
+const MAX_DIFFERENT_FRACTION: f32 = 0.002;
+
+#[derive(Debug)]
+pub struct Comparison {
+	pub different_pixels: usize,
+	pub total_pixels: usize,
+	pub max_delta: f32,
+	pub mean_delta: f32,
+}
+impl Comparison {
+	pub fn different_fraction(&self) -> f32 {
+		self.different_pixels as f32 / self.total_pixels as f32
+	}
+	pub fn passes(&self) -> bool {
+		self.different_fraction() <= MAX_DIFFERENT_FRACTION
+	}
+}
+
+/// Perceptual color difference from 0 to 1 (black vs. white is about 0.93), measured in YIQ space with the weights used by
+/// pixelmatch (Kotsarenko & Ramos, "Measuring perceived color difference using YIQ NTSC transmission
+/// color space").  Transparent pixels are blended onto white first.
+pub fn color_delta(a: Rgba<u8>, b: Rgba<u8>) -> f32 {
+	fn yiq(pixel: Rgba<u8>) -> (f32, f32, f32) {
+		let alpha = pixel[3] as f32 / 255.0;
+		let blend = |channel: u8| 255.0 + (channel as f32 - 255.0) * alpha;
+		let (r, g, b) = (blend(pixel[0]), blend(pixel[1]), blend(pixel[2]));
+		(
+			r * 0.298_895_3 + g * 0.586_622_5 + b * 0.114_482_23,
+			r * 0.595_977_97 - g * 0.274_176_1 - b * 0.321_801_9,
+			r * 0.211_470_17 - g * 0.522_617_1 + b * 0.311_146_94,
+		)
+	}
+	// The largest possible difference (black vs. white)
+	const MAX_DELTA: f32 = 35215.0;
+	let (y1, i1, q1) = yiq(a);
+	let (y2, i2, q2) = yiq(b);
+	let delta = 0.5053 * (y1 - y2).powi(2) + 0.299 * (i1 - i2).powi(2) + 0.1957 * (q1 - q2).powi(2);
+	(delta / MAX_DELTA).min(1.0)
+}
+
+/// Compare two images pixel by pixel.  `None` if they aren't the same size.
+pub fn compare(actual: &RgbaImage, expected: &RgbaImage) -> Option<Comparison> {
+	if actual.dimensions() != expected.dimensions() {
+		return None;
+	}
+	let mut comparison = Comparison {
+		different_pixels: 0,
+		total_pixels: (actual.width() * actual.height()) as usize,
+		max_delta: 0.0,
+		mean_delta: 0.0,
+	};
+	for (a, b) in actual.pixels().zip(expected.pixels()) {
+		let delta = color_delta(*a, *b);
+		if delta > PIXEL_THRESHOLD {
+			comparison.different_pixels += 1;
+		}
+		comparison.max_delta = comparison.max_delta.max(delta);
+		comparison.mean_delta += delta;
+	}
+	comparison.mean_delta /= comparison.total_pixels.max(1) as f32;
+	Some(comparison)
+}
+
+/// A faded grayscale copy of `expected` with the pixels that differ from `actual` in red.
+pub fn diff_image(actual: &RgbaImage, expected: &RgbaImage) -> RgbaImage {
+	RgbaImage::from_fn(expected.width(), expected.height(), |x, y| {
+		let b = *expected.get_pixel(x, y);
+		let different = x >= actual.width()
+			|| y >= actual.height()
+			|| color_delta(*actual.get_pixel(x, y), b) > PIXEL_THRESHOLD;
+		if different {
+			Rgba([255, 0, 0, 255])
+		} else {
+			let gray = (b[0] as f32 * 0.299 + b[1] as f32 * 0.587 + b[2] as f32 * 0.114) as u8;
+			let faded = 255 - (255 - gray) / 4;
+			Rgba([faded, faded, faded, 255])
+		}
+	})
+}
+
+fn reference_path(name: &str) -> PathBuf {
+	PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.png", name))
+}
+fn output_path(name: &str, kind: &str) -> PathBuf {
+	PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+		.join("target/golden")
+		.join(format!("{}.{}.png", name, kind))
+}
+
+/// Compare `actual` against the reference image called `name`.
+pub fn check(name: &str, actual: &RgbaImage) -> Result<(), String> {
+	let reference = reference_path(name);
+	if env::var_os("UPDATE_GOLDEN").is_some() {
+		fs::create_dir_all(reference.parent().unwrap()).map_err(|err| err.to_string())?;
+		actual.save(&reference).map_err(|err| err.to_string())?;
+		println!("Recorded reference image {}", reference.display());
+		return Ok(());
+	}
+	if !reference.exists() {
+		return Err(format!("{}: there's no reference image (record it with UPDATE_GOLDEN=1)", reference.display()));
+	}
+	let expected = image::open(&reference)
+		.map_err(|err| format!("Couldn't read {}: {}", reference.display(), err))?
+		.to_rgba();
+
+	let comparison = compare(actual, &expected);
+	if comparison.as_ref().is_some_and(Comparison::passes) {
+		return Ok(());
+	}
+
+	let actual_path = output_path(name, "actual");
+	let diff_path = output_path(name, "diff");
+	fs::create_dir_all(actual_path.parent().unwrap()).map_err(|err| err.to_string())?;
+	actual.save(&actual_path).map_err(|err| err.to_string())?;
+	diff_image(actual, &expected)
+		.save(&diff_path)
+		.map_err(|err| err.to_string())?;
+	Err(match comparison {
+		None => format!(
+			"{}: rendered {:?} but the reference is {:?} (see {})",
+			name,
+			actual.dimensions(),
+			expected.dimensions(),
+			actual_path.display()
+		),
+		Some(comparison) => format!(
+			"{}: {} of {} pixels differ ({:.3}%, max delta {:.3}, mean delta {:.5}).  See {} and {}",
+			name,
+			comparison.different_pixels,
+			comparison.total_pixels,
+			comparison.different_fraction() * 100.0,
+			comparison.max_delta,
+			comparison.mean_delta,
+			actual_path.display(),
+			diff_path.display()
+		),
+	})
+}
+
+/// Lets the plain `Mesh`es be drawn as scene items.
+struct MeshItem<M: Mesh>(M);
+impl<M: Mesh> SceneItem for MeshItem<M> {
+	fn init(&mut self, _gl: &crate::demo::GlContext) {
+		self.0.init();
+	}
+	fn anim(&mut self, _update: Duration) {}
+	fn draw(&mut self, _alpha: f32) {
+		self.0.draw();
+	}
+}
+
+/// Run `steps` fixed timesteps of the scene from the default camera and return the final frame.
+fn render(scene_items: Vec<Box<dyn SceneItem>>, steps: u32) -> RgbaImage {
+	let surface = headless::create_surface(PhysicalSize::new(WIDTH as f64, HEIGHT as f64)).unwrap_or_else(|err| panic!("{}", err));
+	let mut demo = Demo::new(surface, scene_items);
+	for _ in 0..steps {
+		demo.advance(demo.frame_interval());
+	}
+	demo.draw();
+	let image = demo.surface.capture();
+	demo.shutdown();
+	image
+}
+
+fn golden_test(name: &str, scene_items: Vec<Box<dyn SceneItem>>, steps: u32) {
+	let _lock = headless::TEST_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+	if let Err(err) = check(name, &render(scene_items, steps)) {
+		panic!("{}", err);
+	}
+}
+
+#[test]
+fn identical_images_match() {
+	let image = RgbaImage::from_fn(8, 8, |x, y| Rgba([x as u8 * 30, y as u8 * 30, 100, 255]));
+	let comparison = compare(&image, &image).unwrap();
+	assert_eq!(comparison.different_pixels, 0);
+	assert_eq!(comparison.max_delta, 0.0);
+	assert!(comparison.passes());
+}
+
+#[test]
+fn differences_are_counted_and_marked() {
+	let expected = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 255]));
+	let mut actual = expected.clone();
+	actual.put_pixel(3, 4, Rgba([255, 255, 255, 255]));
+	// Too small to notice
+	actual.put_pixel(5, 5, Rgba([2, 2, 2, 255]));
+
+	let comparison = compare(&actual, &expected).unwrap();
+	assert_eq!(comparison.different_pixels, 1);
+	assert!(comparison.max_delta > 0.9);
+	assert!(!comparison.passes());
+
+	let diff = diff_image(&actual, &expected);
+	assert_eq!(*diff.get_pixel(3, 4), Rgba([255, 0, 0, 255]));
+	assert_ne!(*diff.get_pixel(5, 5), Rgba([255, 0, 0, 255]));
+}
+
+#[test]
+fn different_sizes_dont_compare() {
+	let a = RgbaImage::new(4, 4);
+	let b = RgbaImage::new(4, 5);
+	assert!(compare(&a, &b).is_none());
+}
+
+#[test]
+fn color_delta_is_perceptual() {
+	let black = Rgba([0, 0, 0, 255]);
+	let white = Rgba([255, 255, 255, 255]);
+	assert!(color_delta(black, white) > 0.9);
+	// The eye is more sensitive to green than blue
+	let green = color_delta(black, Rgba([0, 64, 0, 255]));
+	let blue = color_delta(black, Rgba([0, 0, 64, 255]));
+	assert!(green > blue);
+	// Fully transparent is white
+	assert!(color_delta(Rgba([0, 0, 0, 0]), white) < 1e-6);
+}
+
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn golden_voxel_chunk() {
+	golden_test("voxel_chunk", vec![Box::new(VoxelProject::new(1))], 30);
+}
+
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn golden_voxel_terrain() {
+	golden_test("voxel_terrain", vec![Box::new(VoxelProject::terrain(1))], 0);
+}
+
+/// An opaque floor in chunk (0, 0, 0), half covered by a slab of glass of a single color
+struct GlassGenerator;
+impl ChunkGenerator for GlassGenerator {
+	fn generate(&self, coord: ChunkCoord, size: ChunkSize) -> Vec<Voxel> {
+		(0..size.len())
+			.map(|index| {
+				let (x, y, _) = size.position(index);
+				// Just below the origin, where the camera looks
+				let floor = size.height / 2 - 2;
+				match y {
+					_ if coord != ChunkCoord::new(0, 0, 0) => Voxel::EMPTY,
+					_ if y == floor => Voxel::solid(BlockId::SOLID, (0.8, 0.4, 0.2)),
+					_ if y > floor && y <= floor + 2 && x < size.width / 2 => Voxel::solid(BlockId::SOLID, (0.2, 0.5, 0.9)).with_opacity(0.4),
+					_ => Voxel::EMPTY
+				}
+			})
+			.collect()
+	}
+}
+
+/// Weighted blended transparency against sorted blending as the reference.  With one color of glass
+/// the order doesn't matter, so the two have to agree.
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn weighted_blended_matches_sorted() {
+	let _lock = headless::TEST_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+	let glass = |transparency| -> Vec<Box<dyn SceneItem>> {
+		let mut project = VoxelProject::with_generator(0, Arc::new(GlassGenerator), false);
+		project.set_lighting(Lighting::Flat);
+		project.set_transparency(transparency);
+		vec![Box::new(project)]
+	};
+	let sorted = render(glass(Transparency::Sorted), 0);
+	let weighted = render(glass(Transparency::WeightedBlended), 0);
+	let comparison = compare(&weighted, &sorted).unwrap();
+	assert!(comparison.passes(), "{:?}", comparison);
+	// The glass has to show up at all
+	assert!(compare(&weighted, &render(glass(Transparency::Opaque), 0)).is_some_and(|opaque| !opaque.passes()));
+}
+
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn golden_axis() {
+	golden_test("axis", vec![Box::new(MeshItem(Axis::new()))], 0);
+}
+
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn golden_cone() {
+	golden_test("cone", vec![Box::new(MeshItem(Cone::new()))], 0);
+}
+
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn golden_helicopter() {
+	golden_test("helicopter", vec![Box::new(Helicopter::new())], 0);
+}
+
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn golden_helicopter_wireframe() {
+	let mut helicopter = Helicopter::new();
+	helicopter.toggle_wireframe();
+	golden_test("helicopter_wireframe", vec![Box::new(helicopter)], 0);
+}
+
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn golden_propeller() {
+	golden_test("propeller", vec![Box::new(MeshItem(Propeller::new()))], 0);
+}
+
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn golden_ship() {
+	golden_test("ship", vec![Box::new(MeshItem(Ship::new()))], 0);
+}
+
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn golden_torus() {
+	golden_test("torus", vec![Box::new(MeshItem(Torus::new()))], 0);
+}
+
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn golden_ocean() {
+	golden_test("ocean", vec![Box::new(Ocean::with_seed(1))], 30);
+}
+
+#[test]
+#[ignore = "needs libOSMesa, run with --ignored"]
+fn golden_ocean_textured() {
+	let mut ocean = Ocean::with_seed(1);
+	ocean.toggle_texture();
+	ocean.toggle_morph();
+	golden_test("ocean_textured", vec![Box::new(ocean)], 30);
+}
@@ -0,0 +1,139 @@
+use crate::demo::{Demo, SceneItem, Surface};
+
+use glutin::{dpi::PhysicalSize, platform::unix::HeadlessContextExt, ContextBuilder, GlRequest};
+
+use std::{error::Error, fs, path::PathBuf, sync::Mutex};
+
+pub const USAGE: &str = "--headless [--frames N] [--size WIDTHxHEIGHT] [--output DIR]";
+
+/// Render without a window (through OSMesa, so Mesa's llvmpipe works on machines without a GPU or
+/// display) and save every frame as a PNG.
+#[derive(Clone, Debug)]
+pub struct HeadlessOptions {
+	pub frames: u32,
+	pub size: PhysicalSize,
+	pub output: PathBuf,
+}
+impl Default for HeadlessOptions {
+	fn default() -> HeadlessOptions {
+		HeadlessOptions {
+			frames: 1,
+			size: PhysicalSize::new(800.0, 600.0),
+			output: PathBuf::from("./frames"),
+		}
+	}
+}
+impl HeadlessOptions {
+	/// Returns `None` unless `--headless` is one of the arguments, and the arguments that aren't
+	/// about headless rendering.
+	pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<(Option<HeadlessOptions>, Vec<String>), String> {
+		let mut options = HeadlessOptions::default();
+		let mut headless = false;
+		let mut options_given = false;
+		let mut others = Vec::new();
+		let mut args = args.into_iter();
+		while let Some(arg) = args.next() {
+			options_given |= ["--frames", "--size", "--output"].contains(&arg.as_str());
+			let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+			match arg.as_str() {
+				"--headless" => headless = true,
+				"--frames" => {
+					options.frames = value("--frames")?
+						.parse()
+						.map_err(|err| format!("Bad frame count: {}", err))?
+				}
+				"--size" => {
+					let size = value("--size")?;
+					let (width, height) = size
+						.split_once('x')
+						.and_then(|(width, height)| Some((width.parse::<u32>().ok()?, height.parse::<u32>().ok()?)))
+						.filter(|&(width, height)| width > 0 && height > 0)
+						.ok_or_else(|| format!("Bad size (expected WIDTHxHEIGHT): {}", size))?;
+					options.size = PhysicalSize::new(width as f64, height as f64);
+				}
+				"--output" => options.output = PathBuf::from(value("--output")?),
+				_ => others.push(arg),
+			}
+		}
+		if options_given && !headless {
+			return Err("--frames, --size and --output only go with --headless".to_string());
+		}
+		Ok((if headless { Some(options) } else { None }, others))
+	}
+}
+
+/// Render `options.frames` frames of the scene, one fixed timestep apart, into
+/// `<output>/frame_0000.png`, `<output>/frame_0001.png`, ...
+pub fn run(options: &HeadlessOptions, scene_items: Vec<Box<dyn SceneItem>>) -> Result<(), Box<dyn Error>> {
+	let surface = create_surface(options.size)?;
+	let mut demo = Demo::new(surface, scene_items);
+	fs::create_dir_all(&options.output)?;
+	for frame in 0..options.frames {
+		if frame > 0 {
+			// Step by exactly one frame instead of the wall clock so the output doesn't depend on how fast we render
+			demo.advance(demo.frame_interval());
+		}
+		demo.draw();
+		let path = options.output.join(format!("frame_{:04}.png", frame));
+		demo.surface.capture().save(&path)?;
+		println!("Wrote {}", path.display());
+	}
+	demo.shutdown();
+	Ok(())
+}
+
+/// Create a software rendering context (and make it current on this thread) with an offscreen
+/// framebuffer of the given size to draw into.
+pub fn create_surface(size: PhysicalSize) -> Result<Surface, Box<dyn Error>> {
+	let context = ContextBuilder::new()
+		.with_gl(GlRequest::Latest)
+		.build_osmesa(size)
+		.map_err(|err| format!("Couldn't create an OSMesa context ({}).  Is libOSMesa installed?", err))?;
+	let context = unsafe { context.make_current().map_err(|(_, err)| err)? };
+	Ok(Surface::headless(context, size)?)
+}
+
+/// The GL function pointers are global, so tests that use GL take turns.
+#[cfg(test)]
+pub static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Run `test` with a current software GL context.  The tests that need one are `#[ignore]`d, so that
+/// they're reported as skipped where there's no libOSMesa; run them with `cargo test -- --ignored`.
+#[cfg(test)]
+pub fn with_test_context(test: impl FnOnce()) {
+	let _lock = TEST_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+	match create_surface(PhysicalSize::new(64.0, 64.0)) {
+		Ok(_surface) => test(),
+		Err(err) => panic!("{}", err),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn args(args: &str) -> Vec<String> {
+		args.split_whitespace().map(String::from).collect()
+	}
+
+	#[test]
+	fn headless_options_are_parsed() {
+		let (options, others) = HeadlessOptions::from_args(args("--seed 4 --headless --frames 10 --size 320x200")).unwrap();
+		let options = options.unwrap();
+		assert_eq!(options.frames, 10);
+		assert_eq!(options.size, PhysicalSize::new(320.0, 200.0));
+		assert_eq!(options.output, PathBuf::from("./frames"));
+		// The rest is left for `main`
+		assert_eq!(others, args("--seed 4"));
+		assert_eq!(HeadlessOptions::from_args(args("--terrain")).unwrap().0.map(|options| options.frames), None);
+	}
+
+	#[test]
+	fn bad_headless_options_are_errors() {
+		assert!(HeadlessOptions::from_args(args("--headless --frames")).is_err());
+		assert!(HeadlessOptions::from_args(args("--headless --frames ten")).is_err());
+		assert!(HeadlessOptions::from_args(args("--headless --size 0x10")).is_err());
+		// Without --headless they'd do nothing
+		assert!(HeadlessOptions::from_args(args("--frames 10")).is_err());
+	}
+}
@@ -0,0 +1,296 @@
+use glutin::event::{ElementState, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode, WindowEvent};
+
+use std::{
+	collections::{HashMap, HashSet},
+	error::Error,
+	fmt, fs, io,
+	path::Path,
+	str::FromStr,
+};
+
+/// Where the key bindings are loaded from (relative to the working directory, like the textures)
+pub const BINDINGS_FILE: &str = "./bindings.cfg";
+
+/// Every key that can be named in the bindings file.
+#[rustfmt::skip]
+const KEYS: &[VirtualKeyCode] = {
+	use VirtualKeyCode::*;
+	&[
+		Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+		A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+		F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+		Escape, Tab, Space, Return, Back, Insert, Delete, Home, End, PageUp, PageDown,
+		Left, Up, Right, Down, Snapshot, Pause,
+		Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+		Add, Subtract, Multiply, Divide, Decimal, NumpadEnter,
+		LShift, RShift, LControl, RControl, LAlt, RAlt,
+		Minus, Equals, LBracket, RBracket, Backslash, Semicolon, Apostrophe, Comma, Period, Slash, Grave,
+	]
+};
+
+/// A physical key or mouse button that can trigger an action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+	Key(VirtualKeyCode),
+	Mouse(MouseButton),
+}
+impl FromStr for Binding {
+	type Err = String;
+	fn from_str(name: &str) -> Result<Binding, String> {
+		let name = name.trim();
+		match name.to_lowercase().as_str() {
+			"mouseleft" => return Ok(Binding::Mouse(MouseButton::Left)),
+			"mousemiddle" => return Ok(Binding::Mouse(MouseButton::Middle)),
+			"mouseright" => return Ok(Binding::Mouse(MouseButton::Right)),
+			_ => {}
+		}
+		if let Some(number) = name.to_lowercase().strip_prefix("mouse") {
+			if let Ok(number) = number.parse() {
+				return Ok(Binding::Mouse(MouseButton::Other(number)));
+			}
+		}
+		KEYS.iter()
+			.find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+			// Allow plain digits for the number row
+			.or_else(|| KEYS.iter().find(|key| format!("{:?}", key).eq_ignore_ascii_case(&format!("Key{}", name))))
+			.map(|key| Binding::Key(*key))
+			.ok_or_else(|| format!("Unknown key or mouse button: {}", name))
+	}
+}
+impl fmt::Display for Binding {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Binding::Key(key) => write!(f, "{:?}", key),
+			Binding::Mouse(MouseButton::Other(number)) => write!(f, "Mouse{}", number),
+			Binding::Mouse(button) => write!(f, "Mouse{:?}", button),
+		}
+	}
+}
+
+/// Which keys and mouse buttons are currently held down.
+#[derive(Default)]
+pub struct InputState {
+	pressed: HashSet<Binding>,
+	pub modifiers: ModifiersState,
+}
+impl InputState {
+	pub fn is_down(&self, binding: Binding) -> bool {
+		self.pressed.contains(&binding)
+	}
+	pub fn key_down(&self, key: VirtualKeyCode) -> bool {
+		self.is_down(Binding::Key(key))
+	}
+	pub fn button_down(&self, button: MouseButton) -> bool {
+		self.is_down(Binding::Mouse(button))
+	}
+	/// Forget that `binding` is held, for presses that a scene item consumed.  The release that
+	/// follows is then ignored too.
+	pub fn forget(&mut self, binding: Binding) {
+		self.pressed.remove(&binding);
+	}
+	/// Keep the pressed state up to date.  Returns the binding if it changed state (key repeats
+	/// don't count as a change).
+	pub fn handle_event(&mut self, event: &WindowEvent) -> Option<(Binding, ElementState)> {
+		let (binding, state) = match event {
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput {
+					virtual_keycode: Some(key),
+					state,
+					modifiers,
+					..
+				},
+				..
+			} => {
+				self.modifiers = *modifiers;
+				(Binding::Key(*key), *state)
+			}
+			WindowEvent::MouseInput { button, state, modifiers, .. } => {
+				self.modifiers = *modifiers;
+				(Binding::Mouse(*button), *state)
+			}
+			// We won't hear about releases while we don't have focus
+			WindowEvent::Focused(false) => {
+				self.pressed.clear();
+				return None;
+			}
+			_ => return None,
+		};
+		let changed = match state {
+			ElementState::Pressed => self.pressed.insert(binding),
+			ElementState::Released => self.pressed.remove(&binding),
+		};
+		if changed {
+			Some((binding, state))
+		} else {
+			None
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+	Io(io::Error),
+	Parse { line: usize, message: String },
+}
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ConfigError::Io(err) => write!(f, "Couldn't read {}: {}", BINDINGS_FILE, err),
+			ConfigError::Parse { line, message } => write!(f, "{}:{}: {}", BINDINGS_FILE, line, message),
+		}
+	}
+}
+impl Error for ConfigError {}
+
+/// Maps named actions (like "demo.pause") to the keys / buttons that trigger them.  Actions are
+/// registered with a default binding, which the bindings file can override.
+#[derive(Default)]
+pub struct ActionMap {
+	/// Registered action names, in registration order
+	actions: Vec<String>,
+	bindings: HashMap<String, Vec<Binding>>,
+	/// Bindings from the bindings file.  These may name actions of scene items that aren't part
+	/// of the current scene, so they're kept around until (if ever) the action is registered.
+	overrides: HashMap<String, Vec<Binding>>,
+}
+impl ActionMap {
+	pub fn new() -> ActionMap {
+		ActionMap::default()
+	}
+	pub fn register(&mut self, name: &str, default: Binding) {
+		if !self.actions.iter().any(|action| action == name) {
+			self.actions.push(name.to_string());
+			let bindings = self.overrides.get(name).cloned().unwrap_or_else(|| vec![default]);
+			self.bindings.insert(name.to_string(), bindings);
+		}
+	}
+	pub fn bindings(&self, name: &str) -> &[Binding] {
+		self.bindings.get(name).map_or(&[], |bindings| bindings.as_slice())
+	}
+	/// All the actions bound to a key / button
+	pub fn actions_for(&self, binding: Binding) -> Vec<String> {
+		self.actions
+			.iter()
+			.filter(|action| self.bindings(action).contains(&binding))
+			.cloned()
+			.collect()
+	}
+	/// Is any of the action's bindings held down?
+	pub fn is_down(&self, name: &str, input: &InputState) -> bool {
+		self.bindings(name).iter().any(|binding| input.is_down(*binding))
+	}
+
+	/// Load bindings from the bindings file.  A missing file isn't an error: the defaults are kept.
+	pub fn load(&mut self, path: &Path) -> Result<(), ConfigError> {
+		match fs::read_to_string(path) {
+			Ok(text) => self.parse(&text),
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+			Err(err) => Err(ConfigError::Io(err)),
+		}
+	}
+	/// Each line is `action = Binding[, Binding...]`.  Anything after a # is a comment.  An empty
+	/// list of bindings unbinds the action.
+	pub fn parse(&mut self, text: &str) -> Result<(), ConfigError> {
+		for (number, line) in text.lines().enumerate() {
+			let line = line.split('#').next().unwrap_or("").trim();
+			if line.is_empty() {
+				continue;
+			}
+			let error = |message: String| ConfigError::Parse { line: number + 1, message };
+			let (name, bindings) = match line.find('=') {
+				Some(i) => (line[..i].trim(), &line[i + 1..]),
+				None => return Err(error(format!("Expected `action = key`, found: {}", line))),
+			};
+			let bindings = bindings
+				.split(',')
+				.filter(|binding| !binding.trim().is_empty())
+				.map(|binding| binding.parse().map_err(error))
+				.collect::<Result<Vec<Binding>, _>>()?;
+			if self.actions.iter().any(|action| action == name) {
+				self.bindings.insert(name.to_string(), bindings.clone());
+			}
+			self.overrides.insert(name.to_string(), bindings);
+		}
+		Ok(())
+	}
+}
+
+/// The input state together with the action map so that both can be handed around at once.
+#[derive(Default)]
+pub struct Input {
+	pub state: InputState,
+	pub actions: ActionMap,
+}
+impl Input {
+	pub fn action_down(&self, name: &str) -> bool {
+		self.actions.is_down(name, &self.state)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn bindings_parse_and_print() {
+		assert_eq!("Space".parse(), Ok(Binding::Key(VirtualKeyCode::Space)));
+		assert_eq!(" f5 ".parse(), Ok(Binding::Key(VirtualKeyCode::F5)));
+		assert_eq!("3".parse(), Ok(Binding::Key(VirtualKeyCode::Key3)));
+		assert_eq!("MouseRight".parse(), Ok(Binding::Mouse(MouseButton::Right)));
+		assert_eq!("mouse4".parse(), Ok(Binding::Mouse(MouseButton::Other(4))));
+		assert!("Hyper".parse::<Binding>().is_err());
+		for binding in ["LControl", "Key0", "MouseLeft", "Mouse7"].iter() {
+			assert_eq!(binding.parse::<Binding>().unwrap().to_string(), *binding);
+		}
+	}
+
+	#[test]
+	fn the_file_overrides_the_defaults() {
+		let mut actions = ActionMap::new();
+		actions.register("demo.pause", Binding::Key(VirtualKeyCode::P));
+		actions.register("demo.quit", Binding::Key(VirtualKeyCode::Escape));
+		actions
+			.parse("# Comment\n\ndemo.pause = Space, MouseMiddle  # trailing comment\ndemo.quit =\n")
+			.unwrap();
+		assert_eq!(actions.bindings("demo.pause"), &[Binding::Key(VirtualKeyCode::Space), Binding::Mouse(MouseButton::Middle)]);
+		// An empty list unbinds
+		assert!(actions.bindings("demo.quit").is_empty());
+		assert!(actions.bindings("demo.unknown").is_empty());
+		assert_eq!(actions.actions_for(Binding::Key(VirtualKeyCode::Space)), vec!["demo.pause".to_string()]);
+		assert!(actions.actions_for(Binding::Key(VirtualKeyCode::P)).is_empty());
+	}
+
+	#[test]
+	fn overrides_wait_for_their_action() {
+		let mut actions = ActionMap::new();
+		actions.parse("voxel.save = F9").unwrap();
+		assert!(actions.bindings("voxel.save").is_empty());
+		actions.register("voxel.save", Binding::Key(VirtualKeyCode::F5));
+		actions.register("voxel.load", Binding::Key(VirtualKeyCode::F6));
+		assert_eq!(actions.bindings("voxel.save"), &[Binding::Key(VirtualKeyCode::F9)]);
+		assert_eq!(actions.bindings("voxel.load"), &[Binding::Key(VirtualKeyCode::F6)]);
+		// Registering again keeps the bindings
+		actions.register("voxel.save", Binding::Key(VirtualKeyCode::F1));
+		assert_eq!(actions.bindings("voxel.save"), &[Binding::Key(VirtualKeyCode::F9)]);
+	}
+
+	#[test]
+	fn errors_name_the_line() {
+		let mut actions = ActionMap::new();
+		match actions.parse("demo.pause = P\n\ndemo.quit Escape") {
+			Err(ConfigError::Parse { line: 3, .. }) => {}
+			other => panic!("{:?}", other),
+		}
+		match actions.parse("demo.pause = P, Hyper") {
+			Err(ConfigError::Parse { line: 1, message }) => assert!(message.contains("Hyper")),
+			other => panic!("{:?}", other),
+		}
+	}
+
+	#[test]
+	fn the_shipped_bindings_parse() {
+		let mut actions = ActionMap::new();
+		actions.load(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/bindings.cfg"))).unwrap();
+		assert!(actions.load(Path::new("does/not/exist.cfg")).is_ok());
+	}
+}
@@ -6,14 +6,13 @@ use glutin::dpi::LogicalSize;
 
 use std::ffi::CStr;
 
-pub mod bindings {
-    pub use self::Gles2 as Gl;
-    include!(concat!(env!("OUT_DIR"), "/gl_bindings.rs"));
-}
+mod gl;
+mod shader;
+use shader::{ShaderProgram, Stage};
 
 struct Demo {
 	wrapped_context: ContextWrapper<PossiblyCurrent, winit::window::Window>,
-	gl: bindings::Gl
+	program: Option<ShaderProgram>
 }
 
 impl Demo {
@@ -23,13 +22,13 @@ impl Demo {
 			wrapped_context.get_pixel_format()
 		);
 
-		let gl = bindings::Gl::load_with(|ptr| wrapped_context.context().get_proc_address(ptr) as *const _);
+		gl::load_with(|ptr| wrapped_context.context().get_proc_address(ptr) as *const _);
 
-		Demo { wrapped_context, gl }
+		Demo { wrapped_context, program: None }
 	}
 	fn init(&mut self) {
 		let version = unsafe {
-			let data = CStr::from_ptr(self.gl.GetString(bindings::VERSION) as *const _)
+			let data = CStr::from_ptr(gl::GetString(gl::VERSION) as *const _)
 				.to_bytes()
 				.to_vec();
 			String::from_utf8(data).unwrap()
@@ -37,35 +36,14 @@ impl Demo {
 
 		println!("OpenGL version {}", version);
 
+		let program = ShaderProgram::builder()
+			.stage(Stage::Vertex, "main.glslv", include_str!("shaders/main.glslv"))
+			.stage(Stage::Fragment, "main.glslf", include_str!("shaders/main.glslf"))
+			.build()
+			.unwrap_or_else(|err| panic!("{}", err));
+		program.bind();
+
 		unsafe {
-			let vs = self.gl.CreateShader(bindings::VERTEX_SHADER);
-			let vs_src = include_bytes!("shaders/main.glslv");
-			self.gl.ShaderSource(
-				vs,
-				1,
-				// [include_bytes!("shaders/main.glslv").as_ptr() as *const _].as_ptr(),
-				[vs_src.as_ptr() as *const _].as_ptr(),
-				[vs_src.len() as i32].as_ptr(),
-			);
-			self.gl.CompileShader(vs);
-
-			let fs = self.gl.CreateShader(bindings::FRAGMENT_SHADER);
-			let fs_src = include_bytes!("shaders/main.glslf");
-			self.gl.ShaderSource(
-				fs,
-				1,
-				// [include_bytes!("shaders/main.glslf").as_ptr() as *const _].as_ptr(),
-				[fs_src.as_ptr() as *const _].as_ptr(),
-				[fs_src.len() as i32].as_ptr(),
-			);
-			self.gl.CompileShader(fs);
-
-			let program = self.gl.CreateProgram();
-			self.gl.AttachShader(program, vs);
-			self.gl.AttachShader(program, fs);
-			self.gl.LinkProgram(program);
-			self.gl.UseProgram(program);
-			
 			#[rustfmt::skip]
 			static VERTEX_DATA: [f32; 15] = [
 				-0.5, -0.5,  1.0,  0.0,  0.0,
@@ -74,52 +52,53 @@ impl Demo {
 			];
 
 			let mut vb = std::mem::zeroed();
-			self.gl.GenBuffers(1, &mut vb);
-			self.gl.BindBuffer(bindings::ARRAY_BUFFER, vb);
-			self.gl.BufferData(
-				bindings::ARRAY_BUFFER,
+			gl::GenBuffers(1, &mut vb);
+			gl::BindBuffer(gl::ARRAY_BUFFER, vb);
+			gl::BufferData(
+				gl::ARRAY_BUFFER,
 				(VERTEX_DATA.len() * std::mem::size_of::<f32>())
-					as bindings::types::GLsizeiptr,
+					as gl::types::GLsizeiptr,
 				VERTEX_DATA.as_ptr() as *const _,
-				bindings::DYNAMIC_DRAW,
+				gl::DYNAMIC_DRAW,
 			);
 
-			if self.gl.BindVertexArray.is_loaded() {
+			if gl::BindVertexArray::is_loaded() {
 				let mut vao = std::mem::zeroed();
-				self.gl.GenVertexArrays(1, &mut vao);
-				self.gl.BindVertexArray(vao);
+				gl::GenVertexArrays(1, &mut vao);
+				gl::BindVertexArray(vao);
 			}
 
 			let pos_attrib =
-				self.gl.GetAttribLocation(program, b"position\0".as_ptr() as *const _);
+				gl::GetAttribLocation(program.id(), b"position\0".as_ptr() as *const _);
 			let color_attrib =
-				self.gl.GetAttribLocation(program, b"color\0".as_ptr() as *const _);
-			self.gl.VertexAttribPointer(
-				pos_attrib as bindings::types::GLuint,
+				gl::GetAttribLocation(program.id(), b"color\0".as_ptr() as *const _);
+			gl::VertexAttribPointer(
+				pos_attrib as gl::types::GLuint,
 				2,
-				bindings::FLOAT,
+				gl::FLOAT,
 				0,
-				5 * std::mem::size_of::<f32>() as bindings::types::GLsizei,
+				5 * std::mem::size_of::<f32>() as gl::types::GLsizei,
 				std::ptr::null(),
 			);
-			self.gl.VertexAttribPointer(
-				color_attrib as bindings::types::GLuint,
+			gl::VertexAttribPointer(
+				color_attrib as gl::types::GLuint,
 				3,
-				bindings::FLOAT,
+				gl::FLOAT,
 				0,
-				5 * std::mem::size_of::<f32>() as bindings::types::GLsizei,
+				5 * std::mem::size_of::<f32>() as gl::types::GLsizei,
 				(2 * std::mem::size_of::<f32>()) as *const () as *const _,
 			);
-			self.gl.EnableVertexAttribArray(pos_attrib as bindings::types::GLuint);
-			self.gl.EnableVertexAttribArray(color_attrib as bindings::types::GLuint);
+			gl::EnableVertexAttribArray(pos_attrib as gl::types::GLuint);
+			gl::EnableVertexAttribArray(color_attrib as gl::types::GLuint);
 		}
+		self.program = Some(program);
 	}
 	fn draw(&mut self) {
 		println!("Drawing.");
 		unsafe {
-            self.gl.ClearColor(1.0, 0.5, 0.7, 1.0);
-            self.gl.Clear(bindings::COLOR_BUFFER_BIT);
-            self.gl.DrawArrays(bindings::TRIANGLES, 0, 3);
+            gl::ClearColor(1.0, 0.5, 0.7, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
         }
 		self.wrapped_context.swap_buffers().unwrap();
 	}
@@ -32,7 +32,7 @@ impl Mesh for Cone {
 		for i in 0..self.segments {
 			// println!("Segment: {:?}", i);
 			let angle_1 = (i as f32) * segment_angle;
-			let angle_3 = (1 as f32 + 0.5) * segment_angle;
+			let angle_3 = (i as f32 + 0.5) * segment_angle;
 			let angle_2 = (i as f32 + 1.0) * segment_angle;
 			let v1_x = self.radius * angle_1.sin();
 			let v1_z = self.radius * angle_1.cos();
@@ -1,9 +1,14 @@
+use crate::demo::{GlContext, SceneItem};
+use crate::input::{ActionMap, Binding};
 use crate::mesh::mesh::Mesh;
 
 use crate::gl;
 use cgmath::prelude::*;
 use cgmath::Vector3;
 use gl::types::*;
+use glutin::event::VirtualKeyCode;
+
+use std::time::Duration;
 
 pub struct Helicopter {
 	display_list: GLuint,
@@ -18,7 +23,10 @@ impl Helicopter {
 	}
 	pub fn toggle_wireframe(&mut self) {
 		self.wireframe = !self.wireframe;
-		self.build_list();
+		// Before init there's no display list to rebuild yet
+		if self.display_list != 0 {
+			self.build_list();
+		}
 	}
 	fn build_list(&mut self) {
 		unsafe {
@@ -74,8 +82,25 @@ impl Mesh for Helicopter {
 		}
 	}
 }
+impl SceneItem for Helicopter {
+	fn anim(&mut self, _update: Duration) {}
+	fn init(&mut self, _gl: &GlContext) {
+		Mesh::init(self);
+	}
+	fn draw(&mut self, _alpha: f32) {
+		Mesh::draw(self);
+	}
+	fn register_actions(&self, actions: &mut ActionMap) {
+		actions.register("helicopter.wireframe", Binding::Key(VirtualKeyCode::H));
+	}
+	fn action(&mut self, name: &str) {
+		if name == "helicopter.wireframe" {
+			self.toggle_wireframe();
+		}
+	}
+}
 
-const HELITRIS: [[usize; 3]; 1900] = [
+static HELITRIS: [[usize; 3]; 1900] = [
 	[459, 443, 441],
 	[459, 441, 457],
 	[457, 472, 475],
@@ -1978,7 +2003,7 @@ const HELITRIS: [[usize; 3]; 1900] = [
 	[447, 468, 461],
 ];
 
-const HELIPOINTS: [[f32; 3]; 493] = [
+static HELIPOINTS: [[f32; 3]; 493] = [
 	[-10.49, 1.25, -0.93],
 	[-10.21, 1.33, -0.89],
 	[-9.73, 1.45, -0.74],
@@ -2474,7 +2499,7 @@ const HELIPOINTS: [[f32; 3]; 493] = [
 	[7.06, 2.40, 0.81],
 ];
 
-const HELIEDGES: [[usize; 2]; 1216] = [
+static HELIEDGES: [[usize; 2]; 1216] = [
 	[459, 443],
 	[443, 441],
 	[441, 457],
@@ -1,6 +1,7 @@
 mod axis;
 mod cone;
 mod helicopter;
+#[allow(clippy::module_inception)]
 mod mesh;
 mod ocean;
 mod propeller;
@@ -1,3 +1,5 @@
+use crate::demo::{GlContext, SceneItem};
+use crate::input::{ActionMap, Binding};
 use crate::mesh::mesh::Mesh;
 
 use image::{jpeg::JPEGDecoder, ImageDecoder};
@@ -7,8 +9,11 @@ use gl::types::*;
 
 use rand::prelude::*;
 
+use glutin::event::VirtualKeyCode;
+
 use std::fs::File;
 use std::path::Path;
+use std::time::Duration;
 
 const WATER_SIZE: usize = 20;
 
@@ -16,14 +21,20 @@ pub struct Ocean {
 	display_list: GLuint,
 	offsets: [[f32; WATER_SIZE]; WATER_SIZE],
 	time: f32,
+	/// The time before the last animation step, for interpolating between steps
+	previous_time: f32,
 	texture: GLuint,
 	morph_on: bool,
 	texture_on: bool,
 }
 impl Ocean {
 	pub fn new() -> Ocean {
+		Ocean::with_seed(rand::thread_rng().gen())
+	}
+	/// The wave offsets are random: use the same seed to get the same waves.
+	pub fn with_seed(seed: u64) -> Ocean {
 		let mut offsets = [[0 as f32; WATER_SIZE]; WATER_SIZE];
-		let mut rng = rand::thread_rng();
+		let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
 		for row in offsets.iter_mut() {
 			for item in row.iter_mut() {
 				*item = rng.gen_range(0.0, 1000.0);
@@ -32,6 +43,7 @@ impl Ocean {
 		Ocean {
 			display_list: 0,
 			time: 0.0,
+			previous_time: 0.0,
 			offsets,
 			texture: 0,
 			morph_on: false,
@@ -39,6 +51,7 @@ impl Ocean {
 		}
 	}
 	pub fn animate(&mut self, time_diff: f32) {
+		self.previous_time = self.time;
 		self.time += time_diff / 500.0;
 	}
 	pub fn toggle_morph(&mut self) {
@@ -47,45 +60,8 @@ impl Ocean {
 	pub fn toggle_texture(&mut self) {
 		self.texture_on = !self.texture_on;
 	}
-}
-impl Mesh for Ocean {
-	fn init(&mut self) {
-		let path = Path::new("./src/img/piex2.jpg");
-		println!("Attempting to load image from: {:?}", path);
-		let texture = File::open(&path).unwrap();
-		let decoder = JPEGDecoder::new(texture).unwrap();
-		let (width, height) = decoder.dimensions();
-		println!("Texture Dimensions: {:?}", decoder.dimensions());
-		println!("Texture Color Type: {:?}", decoder.colortype());
-		let image_data = decoder
-			.read_image_with_progress(|progress| {
-				println!("{:?}", progress);
-			})
-			.unwrap();
-		unsafe {
-			gl::GenTextures(1, &mut self.texture);
-			gl::BindTexture(gl::TEXTURE_2D, self.texture);
-			gl::TexEnvf(gl::TEXTURE_ENV, gl::TEXTURE_ENV_MODE, gl::REPLACE as f32);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-			gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
-			gl::TexImage2D(
-				gl::TEXTURE_2D,
-				0,
-				3,
-				width as i32,
-				height as i32,
-				0,
-				gl::RGB,
-				gl::UNSIGNED_BYTE,
-				image_data.as_ptr() as *const std::ffi::c_void,
-			);
-			println!("{:?}", gl::GetError());
-		}
-	}
-	fn draw(&self) {
+	/// Draw the waves as they are at `time`
+	pub fn draw_at(&self, time: f32) {
 		let total_width = 15.0;
 		let total_height = total_width;
 		let tex_width = 1.0 / self.offsets.len() as f32;
@@ -114,28 +90,28 @@ impl Mesh for Ocean {
 			gl::Begin(gl::QUADS);
 			for r in 0..(self.offsets.len() - 1) {
 				for c in 0..(self.offsets.len() - 1) {
-					let tl = self.time + self.offsets[r][c];
+					let tl = time + self.offsets[r][c];
 					gl::TexCoord2f(
 						c as f32 * tex_width + rotation_radius * tl.cos(),
 						r as f32 * tex_width + rotation_radius * tl.sin(),
 					);
 					gl::Vertex2f(r as f32, c as f32);
 
-					let tr = self.time + self.offsets[r][c + 1];
+					let tr = time + self.offsets[r][c + 1];
 					gl::TexCoord2f(
 						(c + 1) as f32 * tex_width + rotation_radius * tr.cos(),
 						r as f32 * tex_width + rotation_radius * tr.sin(),
 					);
 					gl::Vertex2f(r as f32, (c + 1) as f32);
 
-					let br = self.time + self.offsets[r + 1][c + 1];
+					let br = time + self.offsets[r + 1][c + 1];
 					gl::TexCoord2f(
 						(c + 1) as f32 * tex_width + rotation_radius * br.cos(),
 						(r + 1) as f32 * tex_width + rotation_radius * br.sin(),
 					);
 					gl::Vertex2f((r + 1) as f32, (c + 1) as f32);
 
-					let bl = self.time + self.offsets[r + 1][c];
+					let bl = time + self.offsets[r + 1][c];
 					gl::TexCoord2f(
 						c as f32 * tex_width + rotation_radius * bl.cos(),
 						(r + 1) as f32 * tex_width + rotation_radius * bl.sin(),
@@ -149,3 +125,67 @@ impl Mesh for Ocean {
 		}
 	}
 }
+impl Mesh for Ocean {
+	fn init(&mut self) {
+		let path = Path::new("./src/img/piex2.jpg");
+		println!("Attempting to load image from: {:?}", path);
+		let texture = File::open(path).unwrap();
+		let decoder = JPEGDecoder::new(texture).unwrap();
+		let (width, height) = decoder.dimensions();
+		println!("Texture Dimensions: {:?}", decoder.dimensions());
+		println!("Texture Color Type: {:?}", decoder.colortype());
+		let image_data = decoder
+			.read_image_with_progress(|progress| {
+				println!("{:?}", progress);
+			})
+			.unwrap();
+		unsafe {
+			gl::GenTextures(1, &mut self.texture);
+			gl::BindTexture(gl::TEXTURE_2D, self.texture);
+			gl::TexEnvf(gl::TEXTURE_ENV, gl::TEXTURE_ENV_MODE, gl::REPLACE as f32);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+			gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+			gl::TexImage2D(
+				gl::TEXTURE_2D,
+				0,
+				3,
+				width as i32,
+				height as i32,
+				0,
+				gl::RGB,
+				gl::UNSIGNED_BYTE,
+				image_data.as_ptr() as *const std::ffi::c_void,
+			);
+			println!("{:?}", gl::GetError());
+		}
+	}
+	fn draw(&self) {
+		self.draw_at(self.time);
+	}
+}
+impl SceneItem for Ocean {
+	fn anim(&mut self, update: Duration) {
+		// animate wants milliseconds
+		self.animate(update.as_secs_f32() * 1000.0);
+	}
+	fn init(&mut self, _gl: &GlContext) {
+		Mesh::init(self);
+	}
+	fn draw(&mut self, alpha: f32) {
+		self.draw_at(self.previous_time + (self.time - self.previous_time) * alpha);
+	}
+	fn register_actions(&self, actions: &mut ActionMap) {
+		actions.register("ocean.morph", Binding::Key(VirtualKeyCode::M));
+		actions.register("ocean.texture", Binding::Key(VirtualKeyCode::T));
+	}
+	fn action(&mut self, name: &str) {
+		match name {
+			"ocean.morph" => self.toggle_morph(),
+			"ocean.texture" => self.toggle_texture(),
+			_ => {}
+		}
+	}
+}
@@ -0,0 +1,9 @@
+mod program;
+mod reflection;
+mod reload;
+mod uniform;
+
+pub use program::{InfoLog, LogEntry, ProgramBuilder, ShaderError, ShaderProgram, Stage};
+pub use reflection::{reflect, Attribute, Block, GlslType, ProgramReflection, Uniform};
+pub use reload::{ReloadableProgram, ShaderFile, SHADER_DIR};
+pub use uniform::{set_uniform, UniformElement, UniformError, UniformValue};
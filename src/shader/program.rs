@@ -0,0 +1,477 @@
+use super::reflection::{self, ProgramReflection};
+use super::uniform::{self, UniformError, UniformValue};
+use crate::gl;
+use gl::types::*;
+
+use std::{error::Error, fmt, rc::Rc};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+	Vertex,
+	TessControl,
+	TessEvaluation,
+	Geometry,
+	Fragment,
+	Compute,
+}
+impl Stage {
+	pub fn gl_enum(self) -> GLenum {
+		match self {
+			Stage::Vertex => gl::VERTEX_SHADER,
+			Stage::TessControl => gl::TESS_CONTROL_SHADER,
+			Stage::TessEvaluation => gl::TESS_EVALUATION_SHADER,
+			Stage::Geometry => gl::GEOMETRY_SHADER,
+			Stage::Fragment => gl::FRAGMENT_SHADER,
+			Stage::Compute => gl::COMPUTE_SHADER,
+		}
+	}
+}
+impl fmt::Display for Stage {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Stage::Vertex => "vertex",
+			Stage::TessControl => "tessellation control",
+			Stage::TessEvaluation => "tessellation evaluation",
+			Stage::Geometry => "geometry",
+			Stage::Fragment => "fragment",
+			Stage::Compute => "compute",
+		})
+	}
+}
+
+/// A single line out of a driver's info log.  If the driver told us which line of the source it
+/// was complaining about then `line` is set and `source_line` holds the text of that line.
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+	/// The included file the line is in, if it's not in the stage's own file
+	pub file: Option<String>,
+	pub line: Option<usize>,
+	pub message: String,
+	pub source_line: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct InfoLog {
+	pub name: String,
+	pub entries: Vec<LogEntry>,
+}
+impl InfoLog {
+	/// Every vendor formats their logs a little differently:
+	///  - Mesa:   `0:12(5): error: ...`
+	///  - NVIDIA: `0(12) : error C0000: ...`
+	///  - AMD / Intel / Apple: `ERROR: 0:12: ...`
+	///
+	/// All of them lead with `<source string>` followed by the line number though, so that's what we look for.
+	/// Returns both numbers and the message.
+	fn parse_line(line: &str) -> Option<(usize, usize, String)> {
+		let trimmed = line.trim_start();
+		let (prefix, rest) = match trimmed.find(|c: char| c.is_ascii_digit()) {
+			Some(i) => trimmed.split_at(i),
+			None => return None,
+		};
+		// Only allow a severity (ERROR: / WARNING:) before the source string number.
+		if !prefix.trim_end_matches(|c: char| c == ':' || c.is_whitespace()).chars().all(|c| c.is_ascii_alphabetic()) {
+			return None;
+		}
+		let string_digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+		let string = rest[..string_digits].parse().ok()?;
+		let rest = &rest[string_digits..];
+		let rest = rest.strip_prefix(':').or_else(|| rest.strip_prefix('('))?;
+		let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+		let number = rest[..digits].parse().ok()?;
+		let message = rest[digits..].trim_start_matches(|c: char| c.is_ascii_digit() || "():".contains(c) || c.is_whitespace());
+		let severity = prefix.trim().trim_end_matches(':').to_lowercase();
+		if severity.is_empty() {
+			Some((string, number, message.to_string()))
+		} else {
+			Some((string, number, format!("{}: {}", severity, message)))
+		}
+	}
+	/// `files` are the stage's own file and then the ones it includes, by source string number:
+	/// their names and sources.
+	fn new(name: &str, raw: &str, files: &[(String, String)]) -> InfoLog {
+		let entries = raw
+			.lines()
+			.filter(|line| !line.trim().is_empty())
+			.map(|line| match InfoLog::parse_line(line) {
+				Some((string, number, message)) => LogEntry {
+					file: if string > 0 { files.get(string).map(|(name, _)| name.clone()) } else { None },
+					line: Some(number),
+					message,
+					// GLSL line numbers start at 1
+					source_line: files
+						.get(string)
+						.and_then(|(_, source)| source.lines().nth(number.wrapping_sub(1)))
+						.map(|text| text.to_string()),
+				},
+				None => LogEntry {
+					file: None,
+					line: None,
+					message: line.trim().to_string(),
+					source_line: None,
+				},
+			})
+			.collect();
+		InfoLog {
+			name: name.to_string(),
+			entries,
+		}
+	}
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+}
+impl fmt::Display for InfoLog {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for entry in self.entries.iter() {
+			let name = entry.file.as_deref().unwrap_or(&self.name);
+			match entry.line {
+				Some(line) => writeln!(f, "{}:{}: {}", name, line, entry.message)?,
+				None => writeln!(f, "{}: {}", self.name, entry.message)?,
+			}
+			if let (Some(line), Some(text)) = (entry.line, &entry.source_line) {
+				writeln!(f, "{:>5} | {}", line, text)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+	Compile { stage: Stage, name: String, log: InfoLog },
+	Link { log: InfoLog },
+}
+impl fmt::Display for ShaderError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ShaderError::Compile { stage, name, log } => {
+				writeln!(f, "Failed to compile {} ({} shader):", name, stage)?;
+				write!(f, "{}", log)
+			}
+			ShaderError::Link { log } => {
+				writeln!(f, "Failed to link {}:", log.name)?;
+				write!(f, "{}", log)
+			}
+		}
+	}
+}
+impl Error for ShaderError {}
+
+struct StageSource {
+	stage: Stage,
+	name: String,
+	/// What's compiled, with the includes spliced in
+	source: String,
+	/// The stage's own file and the ones it includes, for `InfoLog::new`
+	files: Vec<(String, String)>,
+}
+
+/// The name in an `#include "name"` line
+pub(super) fn include_name(line: &str) -> Option<&str> {
+	line.trim().strip_prefix("#include")?.trim().strip_prefix('"')?.strip_suffix('"')
+}
+
+/// Replace every `#include "name"` line with the file of that name from `includes`.  `#line`
+/// directives give each included file its own source string number (its index in `includes` plus
+/// one) and restore the line numbers after it, so that errors point at the right line of the right
+/// file.  Lines naming something that isn't in `includes` are kept for the compiler to complain about.
+fn expand_includes(source: &str, includes: &[(String, String)]) -> String {
+	let mut expanded = String::with_capacity(source.len());
+	for (number, line) in source.lines().enumerate() {
+		let included = include_name(line).and_then(|name| includes.iter().position(|(include, _)| include == name));
+		match included {
+			Some(index) => {
+				expanded.push_str(&format!("#line 1 {}\n", index + 1));
+				expanded.push_str(includes[index].1.trim_end());
+				// GLSL line numbers start at 1, and this is the line after the include
+				expanded.push_str(&format!("\n#line {} 0\n", number + 2));
+			}
+			None => {
+				expanded.push_str(line);
+				expanded.push('\n');
+			}
+		}
+	}
+	expanded
+}
+
+pub struct ProgramBuilder {
+	stages: Vec<StageSource>,
+}
+impl ProgramBuilder {
+	/// Add a stage to the program.  The name is only used for error reporting, so the file name is a good choice.
+	pub fn stage(self, stage: Stage, name: &str, source: &str) -> Self {
+		self.stage_with_includes(stage, name, source, Vec::new())
+	}
+	/// `stage`, with each `#include "name"` line replaced by the file of that name in `includes`
+	/// (names and sources).
+	pub fn stage_with_includes(mut self, stage: Stage, name: &str, source: &str, includes: Vec<(String, String)>) -> Self {
+		let source_text = if includes.is_empty() {
+			source.to_string()
+		} else {
+			expand_includes(source, &includes)
+		};
+		let mut files = vec![(name.to_string(), source.to_string())];
+		files.extend(includes);
+		self.stages.push(StageSource {
+			stage,
+			name: name.to_string(),
+			source: source_text,
+			files,
+		});
+		self
+	}
+	pub fn build(self) -> Result<ShaderProgram, ShaderError> {
+		let mut shaders = Vec::new();
+		let result = self.compile_and_link(&mut shaders);
+		// The shader objects aren't needed anymore once they've been linked (or failed).  A program
+		// that failed to link has already been deleted, which takes care of detaching for us.
+		unsafe {
+			for (program, shader) in shaders {
+				if program != 0 && result.is_ok() {
+					gl::DetachShader(program, shader);
+				}
+				gl::DeleteShader(shader);
+			}
+		}
+		result
+	}
+	fn compile_and_link(&self, shaders: &mut Vec<(GLuint, GLuint)>) -> Result<ShaderProgram, ShaderError> {
+		let mut compiled = Vec::new();
+		for stage in self.stages.iter() {
+			let id = unsafe { gl::CreateShader(stage.stage.gl_enum()) };
+			shaders.push((0, id));
+			unsafe {
+				gl::ShaderSource(
+					id,
+					1,
+					[stage.source.as_ptr() as *const _].as_ptr(),
+					[stage.source.len() as i32].as_ptr(),
+				);
+				gl::CompileShader(id);
+			}
+			let mut status = gl::FALSE as GLint;
+			unsafe {
+				gl::GetShaderiv(id, gl::COMPILE_STATUS, &mut status);
+			}
+			let raw = shader_info_log(id);
+			if status != gl::TRUE as GLint {
+				return Err(ShaderError::Compile {
+					stage: stage.stage,
+					name: stage.name.clone(),
+					log: InfoLog::new(&stage.name, &raw, &stage.files),
+				});
+			}
+			// Warnings don't stop us, but they're still worth seeing.
+			let log = InfoLog::new(&stage.name, &raw, &stage.files);
+			if !log.is_empty() {
+				eprint!("{}", log);
+			}
+			compiled.push(id);
+		}
+
+		let program = ShaderProgram {
+			id: unsafe { gl::CreateProgram() },
+			name: self.name(),
+		};
+		shaders.clear();
+		for id in compiled {
+			unsafe {
+				gl::AttachShader(program.id, id);
+			}
+			shaders.push((program.id, id));
+		}
+		let mut status = gl::FALSE as GLint;
+		unsafe {
+			gl::LinkProgram(program.id);
+			gl::GetProgramiv(program.id, gl::LINK_STATUS, &mut status);
+		}
+		if status != gl::TRUE as GLint {
+			return Err(ShaderError::Link {
+				log: InfoLog::new(&program.name, &program_info_log(program.id), &[]),
+			});
+		}
+		Ok(program)
+	}
+	fn name(&self) -> String {
+		self.stages
+			.iter()
+			.map(|stage| stage.name.as_str())
+			.collect::<Vec<_>>()
+			.join(" + ")
+	}
+}
+
+fn shader_info_log(id: GLuint) -> String {
+	let mut length = 0;
+	unsafe {
+		gl::GetShaderiv(id, gl::INFO_LOG_LENGTH, &mut length);
+	}
+	let mut buffer = vec![0u8; length.max(1) as usize];
+	let mut written = 0;
+	unsafe {
+		gl::GetShaderInfoLog(id, buffer.len() as i32, &mut written, buffer.as_mut_ptr() as *mut _);
+	}
+	buffer.truncate(written as usize);
+	String::from_utf8_lossy(&buffer).into_owned()
+}
+fn program_info_log(id: GLuint) -> String {
+	let mut length = 0;
+	unsafe {
+		gl::GetProgramiv(id, gl::INFO_LOG_LENGTH, &mut length);
+	}
+	let mut buffer = vec![0u8; length.max(1) as usize];
+	let mut written = 0;
+	unsafe {
+		gl::GetProgramInfoLog(id, buffer.len() as i32, &mut written, buffer.as_mut_ptr() as *mut _);
+	}
+	buffer.truncate(written as usize);
+	String::from_utf8_lossy(&buffer).into_owned()
+}
+
+/// A linked GL program.  The program is deleted when this is dropped.
+pub struct ShaderProgram {
+	id: GLuint,
+	name: String,
+}
+impl ShaderProgram {
+	pub fn builder() -> ProgramBuilder {
+		ProgramBuilder { stages: Vec::new() }
+	}
+	pub fn id(&self) -> GLuint {
+		self.id
+	}
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+	pub fn bind(&self) {
+		unsafe {
+			gl::UseProgram(self.id);
+		}
+	}
+	/// The program's active attributes, uniforms and blocks.  Only queried once per program.
+	pub fn reflection(&self) -> Rc<ProgramReflection> {
+		reflection::reflect(self.id)
+	}
+	/// Set a uniform, checking it against the type declared in GLSL.  Works whether or not the program is bound.
+	pub fn set<T: UniformValue + ?Sized>(&self, name: &str, value: &T) -> Result<(), UniformError> {
+		uniform::set_uniform(self.id, name, value)
+	}
+}
+impl Drop for ShaderProgram {
+	fn drop(&mut self) {
+		reflection::forget(self.id);
+		unsafe {
+			gl::DeleteProgram(self.id);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn vendor_log_lines_are_parsed() {
+		assert_eq!(
+			InfoLog::parse_line("0:12(5): error: `foo' undeclared"),
+			Some((0, 12, "error: `foo' undeclared".to_string()))
+		);
+		assert_eq!(
+			InfoLog::parse_line("0(7) : error C0000: syntax error, unexpected ';'"),
+			Some((0, 7, "error C0000: syntax error, unexpected ';'".to_string()))
+		);
+		assert_eq!(
+			InfoLog::parse_line("ERROR: 0:3: 'x' : undeclared identifier"),
+			Some((0, 3, "error: 'x' : undeclared identifier".to_string()))
+		);
+		assert_eq!(
+			InfoLog::parse_line("  WARNING: 0:40: extension not supported"),
+			Some((0, 40, "warning: extension not supported".to_string()))
+		);
+	}
+
+	#[test]
+	fn lines_without_a_location_are_not_parsed() {
+		assert_eq!(InfoLog::parse_line("ERROR: 2 compilation errors.  No code generated."), None);
+		assert_eq!(InfoLog::parse_line("error: vertex shader lacks `main'"), None);
+		assert_eq!(InfoLog::parse_line("Vertex info"), None);
+		assert_eq!(InfoLog::parse_line("(0) : error C5145: must write to gl_Position"), None);
+		assert_eq!(InfoLog::parse_line(""), None);
+	}
+
+	#[test]
+	fn entries_point_at_the_source() {
+		let source = "#version 430\nvoid main() {\n\tfoo();\n}";
+		let raw = "0:3(2): error: no function with name 'foo'\n\nERROR: 0:9: past the end\nlink failed";
+		let log = InfoLog::new("test.glslv", raw, &[("test.glslv".to_string(), source.to_string())]);
+		assert_eq!(log.entries.len(), 3);
+		assert_eq!(log.entries[0].line, Some(3));
+		assert_eq!(log.entries[0].source_line.as_deref(), Some("\tfoo();"));
+		// A line the source doesn't have
+		assert_eq!(log.entries[1].line, Some(9));
+		assert_eq!(log.entries[1].source_line, None);
+		assert_eq!(log.entries[2].line, None);
+		assert_eq!(log.entries[2].message, "link failed");
+		assert_eq!(
+			log.to_string(),
+			"test.glslv:3: error: no function with name 'foo'\n    3 | \tfoo();\ntest.glslv:9: error: past the end\ntest.glslv: link failed\n"
+		);
+		assert!(InfoLog::new("empty", "\n  \n", &[]).is_empty());
+	}
+
+	fn includes() -> Vec<(String, String)> {
+		vec![
+			("a.glsl".to_string(), "float a() { return 1.0; }\n\n".to_string()),
+			("b.glsl".to_string(), "float b() {\n\treturn bar;\n}".to_string()),
+		]
+	}
+
+	#[test]
+	fn includes_are_spliced_in_with_their_own_line_numbers() {
+		let source = "#version 430\n#include \"a.glsl\"\n  #include   \"b.glsl\"\n#include \"missing.glsl\"\nvoid main() {}\n";
+		assert_eq!(
+			expand_includes(source, &includes()),
+			"#version 430\n#line 1 1\nfloat a() { return 1.0; }\n#line 3 0\n#line 1 2\nfloat b() {\n\treturn bar;\n}\n#line 4 0\n#include \"missing.glsl\"\nvoid main() {}\n"
+		);
+	}
+
+	#[test]
+	fn errors_after_an_include_point_at_the_right_line() {
+		let source = "#version 430\n#include \"a.glsl\"\n#include \"b.glsl\"\nvoid main() {\n\tfoo();\n}\n";
+		let stages = ShaderProgram::builder().stage_with_includes(Stage::Vertex, "test.glslv", source, includes()).stages;
+		// What a driver says about the expanded source, given the `#line`s
+		let raw = "0:5(2): error: no function with name 'foo'\n2:2(9): error: `bar' undeclared";
+		let log = InfoLog::new("test.glslv", raw, &stages[0].files);
+		assert_eq!(log.entries[0].file, None);
+		assert_eq!(log.entries[0].source_line.as_deref(), Some("\tfoo();"));
+		assert_eq!(log.entries[1].file.as_deref(), Some("b.glsl"));
+		assert_eq!(log.entries[1].source_line.as_deref(), Some("\treturn bar;"));
+		assert_eq!(
+			log.to_string(),
+			"test.glslv:5: error: no function with name 'foo'\n    5 | \tfoo();\nb.glsl:2: error: `bar' undeclared\n    2 | \treturn bar;\n"
+		);
+	}
+
+	#[test]
+	#[ignore = "needs libOSMesa, run with --ignored"]
+	fn compile_errors_after_an_include_point_at_the_right_line() {
+		crate::headless::with_test_context(|| {
+			let source = "#version 330\n#include \"a.glsl\"\nvoid main() {\n\tgl_Position = vec4(foo());\n}\n";
+			let err = ShaderProgram::builder()
+				.stage_with_includes(Stage::Vertex, "test.glslv", source, includes())
+				.build()
+				.err()
+				.expect("the shader shouldn't compile");
+			match err {
+				ShaderError::Compile { log, .. } => {
+					let entry = log.entries.iter().find(|entry| entry.line.is_some()).expect("no located error");
+					assert_eq!(entry.file, None);
+					assert_eq!(entry.line, Some(4));
+				}
+				err => panic!("unexpected error: {}", err),
+			}
+		});
+	}
+}
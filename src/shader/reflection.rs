@@ -0,0 +1,407 @@
+use crate::gl;
+use gl::types::*;
+
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+/// The GLSL types that GL can report for an active attribute or uniform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlslType {
+	Float,
+	Vec2,
+	Vec3,
+	Vec4,
+	Double,
+	DVec2,
+	DVec3,
+	DVec4,
+	Int,
+	IVec2,
+	IVec3,
+	IVec4,
+	UInt,
+	UVec2,
+	UVec3,
+	UVec4,
+	Bool,
+	BVec2,
+	BVec3,
+	BVec4,
+	Mat2,
+	Mat3,
+	Mat4,
+	Mat2x3,
+	Mat2x4,
+	Mat3x2,
+	Mat3x4,
+	Mat4x2,
+	Mat4x3,
+	Sampler1D,
+	Sampler2D,
+	Sampler3D,
+	SamplerCube,
+	Sampler2DShadow,
+	Sampler2DArray,
+	SamplerBuffer,
+	ISampler2D,
+	ISampler3D,
+	USampler2D,
+	USampler3D,
+	Image2D,
+	Image3D,
+	IImage3D,
+	UImage3D,
+	/// Anything we don't have a name for yet, with the raw GL enum.
+	Other(GLenum),
+}
+impl GlslType {
+	pub fn from_gl(kind: GLenum) -> GlslType {
+		match kind {
+			gl::FLOAT => GlslType::Float,
+			gl::FLOAT_VEC2 => GlslType::Vec2,
+			gl::FLOAT_VEC3 => GlslType::Vec3,
+			gl::FLOAT_VEC4 => GlslType::Vec4,
+			gl::DOUBLE => GlslType::Double,
+			gl::DOUBLE_VEC2 => GlslType::DVec2,
+			gl::DOUBLE_VEC3 => GlslType::DVec3,
+			gl::DOUBLE_VEC4 => GlslType::DVec4,
+			gl::INT => GlslType::Int,
+			gl::INT_VEC2 => GlslType::IVec2,
+			gl::INT_VEC3 => GlslType::IVec3,
+			gl::INT_VEC4 => GlslType::IVec4,
+			gl::UNSIGNED_INT => GlslType::UInt,
+			gl::UNSIGNED_INT_VEC2 => GlslType::UVec2,
+			gl::UNSIGNED_INT_VEC3 => GlslType::UVec3,
+			gl::UNSIGNED_INT_VEC4 => GlslType::UVec4,
+			gl::BOOL => GlslType::Bool,
+			gl::BOOL_VEC2 => GlslType::BVec2,
+			gl::BOOL_VEC3 => GlslType::BVec3,
+			gl::BOOL_VEC4 => GlslType::BVec4,
+			gl::FLOAT_MAT2 => GlslType::Mat2,
+			gl::FLOAT_MAT3 => GlslType::Mat3,
+			gl::FLOAT_MAT4 => GlslType::Mat4,
+			gl::FLOAT_MAT2x3 => GlslType::Mat2x3,
+			gl::FLOAT_MAT2x4 => GlslType::Mat2x4,
+			gl::FLOAT_MAT3x2 => GlslType::Mat3x2,
+			gl::FLOAT_MAT3x4 => GlslType::Mat3x4,
+			gl::FLOAT_MAT4x2 => GlslType::Mat4x2,
+			gl::FLOAT_MAT4x3 => GlslType::Mat4x3,
+			gl::SAMPLER_1D => GlslType::Sampler1D,
+			gl::SAMPLER_2D => GlslType::Sampler2D,
+			gl::SAMPLER_3D => GlslType::Sampler3D,
+			gl::SAMPLER_CUBE => GlslType::SamplerCube,
+			gl::SAMPLER_2D_SHADOW => GlslType::Sampler2DShadow,
+			gl::SAMPLER_2D_ARRAY => GlslType::Sampler2DArray,
+			gl::SAMPLER_BUFFER => GlslType::SamplerBuffer,
+			gl::INT_SAMPLER_2D => GlslType::ISampler2D,
+			gl::INT_SAMPLER_3D => GlslType::ISampler3D,
+			gl::UNSIGNED_INT_SAMPLER_2D => GlslType::USampler2D,
+			gl::UNSIGNED_INT_SAMPLER_3D => GlslType::USampler3D,
+			gl::IMAGE_2D => GlslType::Image2D,
+			gl::IMAGE_3D => GlslType::Image3D,
+			gl::INT_IMAGE_3D => GlslType::IImage3D,
+			gl::UNSIGNED_INT_IMAGE_3D => GlslType::UImage3D,
+			other => GlslType::Other(other),
+		}
+	}
+}
+impl fmt::Display for GlslType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let name = match self {
+			GlslType::Float => "float",
+			GlslType::Vec2 => "vec2",
+			GlslType::Vec3 => "vec3",
+			GlslType::Vec4 => "vec4",
+			GlslType::Double => "double",
+			GlslType::DVec2 => "dvec2",
+			GlslType::DVec3 => "dvec3",
+			GlslType::DVec4 => "dvec4",
+			GlslType::Int => "int",
+			GlslType::IVec2 => "ivec2",
+			GlslType::IVec3 => "ivec3",
+			GlslType::IVec4 => "ivec4",
+			GlslType::UInt => "uint",
+			GlslType::UVec2 => "uvec2",
+			GlslType::UVec3 => "uvec3",
+			GlslType::UVec4 => "uvec4",
+			GlslType::Bool => "bool",
+			GlslType::BVec2 => "bvec2",
+			GlslType::BVec3 => "bvec3",
+			GlslType::BVec4 => "bvec4",
+			GlslType::Mat2 => "mat2",
+			GlslType::Mat3 => "mat3",
+			GlslType::Mat4 => "mat4",
+			GlslType::Mat2x3 => "mat2x3",
+			GlslType::Mat2x4 => "mat2x4",
+			GlslType::Mat3x2 => "mat3x2",
+			GlslType::Mat3x4 => "mat3x4",
+			GlslType::Mat4x2 => "mat4x2",
+			GlslType::Mat4x3 => "mat4x3",
+			GlslType::Sampler1D => "sampler1D",
+			GlslType::Sampler2D => "sampler2D",
+			GlslType::Sampler3D => "sampler3D",
+			GlslType::SamplerCube => "samplerCube",
+			GlslType::Sampler2DShadow => "sampler2DShadow",
+			GlslType::Sampler2DArray => "sampler2DArray",
+			GlslType::SamplerBuffer => "samplerBuffer",
+			GlslType::ISampler2D => "isampler2D",
+			GlslType::ISampler3D => "isampler3D",
+			GlslType::USampler2D => "usampler2D",
+			GlslType::USampler3D => "usampler3D",
+			GlslType::Image2D => "image2D",
+			GlslType::Image3D => "image3D",
+			GlslType::IImage3D => "iimage3D",
+			GlslType::UImage3D => "uimage3D",
+			GlslType::Other(kind) => return write!(f, "<unknown type 0x{:X}>", kind),
+		};
+		f.write_str(name)
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct Attribute {
+	pub name: String,
+	pub kind: GlslType,
+	/// Number of array elements (1 if it isn't an array)
+	pub size: i32,
+	pub location: GLint,
+}
+
+#[derive(Clone, Debug)]
+pub struct Uniform {
+	pub name: String,
+	pub kind: GlslType,
+	/// Number of array elements (1 if it isn't an array)
+	pub size: i32,
+	/// -1 for uniforms that live inside a uniform block
+	pub location: GLint,
+	/// Which uniform block this is a member of, and at what offset.
+	pub block: Option<(GLuint, GLint)>,
+}
+
+/// A uniform block or shader storage block.
+#[derive(Clone, Debug)]
+pub struct Block {
+	pub name: String,
+	pub index: GLuint,
+	pub binding: GLint,
+	pub data_size: GLint,
+}
+
+/// Everything GL will tell us about a linked program's interface.  Query it with `reflect` which
+/// caches the result for each program.
+#[derive(Clone, Debug, Default)]
+pub struct ProgramReflection {
+	pub attributes: Vec<Attribute>,
+	pub uniforms: Vec<Uniform>,
+	pub uniform_blocks: Vec<Block>,
+	pub storage_blocks: Vec<Block>,
+}
+
+thread_local! {
+	static CACHE: RefCell<HashMap<GLuint, Rc<ProgramReflection>>> = RefCell::new(HashMap::new());
+}
+
+/// Get the reflection for a program, querying GL the first time a program is seen.
+pub fn reflect(program: GLuint) -> Rc<ProgramReflection> {
+	CACHE.with(|cache| {
+		cache
+			.borrow_mut()
+			.entry(program)
+			.or_insert_with(|| Rc::new(ProgramReflection::query(program)))
+			.clone()
+	})
+}
+/// Drop the cached reflection.  Must be called when a program is deleted since GL will reuse the id.
+pub fn forget(program: GLuint) {
+	CACHE.with(|cache| {
+		cache.borrow_mut().remove(&program);
+	});
+}
+
+/// Array uniforms / attributes are reported as `name[0]`, but we want to be able to look them up by `name`.
+fn base_name(name: &str) -> &str {
+	name.strip_suffix("[0]").unwrap_or(name)
+}
+
+fn read_name(buffer: &[u8], length: GLsizei) -> String {
+	String::from_utf8_lossy(&buffer[..length.max(0) as usize]).into_owned()
+}
+
+impl ProgramReflection {
+	pub fn query(program: GLuint) -> ProgramReflection {
+		let mut reflection = ProgramReflection::default();
+		unsafe {
+			reflection.query_attributes(program);
+			reflection.query_uniforms(program);
+			reflection.query_uniform_blocks(program);
+			// Shader storage blocks need the 4.3 program interface query
+			if gl::GetProgramInterfaceiv::is_loaded() && gl::GetProgramResourceiv::is_loaded() {
+				reflection.query_storage_blocks(program);
+			}
+		}
+		reflection
+	}
+	unsafe fn query_attributes(&mut self, program: GLuint) {
+		let (mut count, mut max_length) = (0, 0);
+		gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTES, &mut count);
+		gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, &mut max_length);
+		let mut buffer = vec![0u8; max_length.max(1) as usize];
+		for i in 0..count as GLuint {
+			let (mut length, mut size, mut kind) = (0, 0, 0);
+			gl::GetActiveAttrib(
+				program,
+				i,
+				buffer.len() as GLsizei,
+				&mut length,
+				&mut size,
+				&mut kind,
+				buffer.as_mut_ptr() as *mut _,
+			);
+			let location = gl::GetAttribLocation(program, buffer.as_ptr() as *const _);
+			self.attributes.push(Attribute {
+				name: base_name(&read_name(&buffer, length)).to_string(),
+				kind: GlslType::from_gl(kind),
+				size,
+				location,
+			});
+		}
+	}
+	unsafe fn query_uniforms(&mut self, program: GLuint) {
+		let (mut count, mut max_length) = (0, 0);
+		gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+		gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_length);
+		let mut buffer = vec![0u8; max_length.max(1) as usize];
+		for i in 0..count as GLuint {
+			let (mut length, mut size, mut kind) = (0, 0, 0);
+			gl::GetActiveUniform(
+				program,
+				i,
+				buffer.len() as GLsizei,
+				&mut length,
+				&mut size,
+				&mut kind,
+				buffer.as_mut_ptr() as *mut _,
+			);
+			let (mut block_index, mut offset) = (-1, -1);
+			gl::GetActiveUniformsiv(program, 1, &i, gl::UNIFORM_BLOCK_INDEX, &mut block_index);
+			gl::GetActiveUniformsiv(program, 1, &i, gl::UNIFORM_OFFSET, &mut offset);
+			let location = gl::GetUniformLocation(program, buffer.as_ptr() as *const _);
+			self.uniforms.push(Uniform {
+				name: base_name(&read_name(&buffer, length)).to_string(),
+				kind: GlslType::from_gl(kind),
+				size,
+				location,
+				block: if block_index < 0 {
+					None
+				} else {
+					Some((block_index as GLuint, offset))
+				},
+			});
+		}
+	}
+	unsafe fn query_uniform_blocks(&mut self, program: GLuint) {
+		let (mut count, mut max_length) = (0, 0);
+		gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_BLOCKS, &mut count);
+		gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_BLOCK_MAX_NAME_LENGTH, &mut max_length);
+		let mut buffer = vec![0u8; max_length.max(1) as usize];
+		for index in 0..count as GLuint {
+			let (mut length, mut binding, mut data_size) = (0, 0, 0);
+			gl::GetActiveUniformBlockName(program, index, buffer.len() as GLsizei, &mut length, buffer.as_mut_ptr() as *mut _);
+			gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_BINDING, &mut binding);
+			gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_DATA_SIZE, &mut data_size);
+			self.uniform_blocks.push(Block {
+				name: read_name(&buffer, length),
+				index,
+				binding,
+				data_size,
+			});
+		}
+	}
+	unsafe fn query_storage_blocks(&mut self, program: GLuint) {
+		let (mut count, mut max_length) = (0, 0);
+		gl::GetProgramInterfaceiv(program, gl::SHADER_STORAGE_BLOCK, gl::ACTIVE_RESOURCES, &mut count);
+		gl::GetProgramInterfaceiv(program, gl::SHADER_STORAGE_BLOCK, gl::MAX_NAME_LENGTH, &mut max_length);
+		let mut buffer = vec![0u8; max_length.max(1) as usize];
+		let properties = [gl::BUFFER_BINDING, gl::BUFFER_DATA_SIZE];
+		for index in 0..count as GLuint {
+			let mut length = 0;
+			gl::GetProgramResourceName(
+				program,
+				gl::SHADER_STORAGE_BLOCK,
+				index,
+				buffer.len() as GLsizei,
+				&mut length,
+				buffer.as_mut_ptr() as *mut _,
+			);
+			let mut values = [0; 2];
+			gl::GetProgramResourceiv(
+				program,
+				gl::SHADER_STORAGE_BLOCK,
+				index,
+				properties.len() as GLsizei,
+				properties.as_ptr(),
+				values.len() as GLsizei,
+				std::ptr::null_mut(),
+				values.as_mut_ptr(),
+			);
+			self.storage_blocks.push(Block {
+				name: read_name(&buffer, length),
+				index,
+				binding: values[0],
+				data_size: values[1],
+			});
+		}
+	}
+
+	pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+		self.attributes.iter().find(|attribute| attribute.name == name)
+	}
+	pub fn uniform(&self, name: &str) -> Option<&Uniform> {
+		self.uniforms.iter().find(|uniform| uniform.name == name)
+	}
+	pub fn uniform_block(&self, name: &str) -> Option<&Block> {
+		self.uniform_blocks.iter().find(|block| block.name == name)
+	}
+	pub fn storage_block(&self, name: &str) -> Option<&Block> {
+		self.storage_blocks.iter().find(|block| block.name == name)
+	}
+	/// Location of a uniform, or -1 if it isn't active (just like `glGetUniformLocation`)
+	pub fn uniform_location(&self, name: &str) -> GLint {
+		self.uniform(name).map_or(-1, |uniform| uniform.location)
+	}
+	/// Location of an attribute, or -1 if it isn't active (just like `glGetAttribLocation`)
+	pub fn attribute_location(&self, name: &str) -> GLint {
+		self.attribute(name).map_or(-1, |attribute| attribute.location)
+	}
+}
+impl fmt::Display for ProgramReflection {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fn array(size: i32) -> String {
+			if size > 1 {
+				format!("[{}]", size)
+			} else {
+				String::new()
+			}
+		}
+		writeln!(f, "Active Attributes: {}", self.attributes.len())?;
+		for attribute in self.attributes.iter() {
+			writeln!(f, "  layout(location = {}) in {} {}{};", attribute.location, attribute.kind, attribute.name, array(attribute.size))?;
+		}
+		writeln!(f, "Active Uniforms: {}", self.uniforms.len())?;
+		for uniform in self.uniforms.iter() {
+			match uniform.block {
+				Some((block, offset)) => writeln!(f, "  (block {} + {}) {} {}{};", block, offset, uniform.kind, uniform.name, array(uniform.size))?,
+				None => writeln!(f, "  layout(location = {}) uniform {} {}{};", uniform.location, uniform.kind, uniform.name, array(uniform.size))?,
+			}
+		}
+		writeln!(f, "Active Uniform Blocks: {}", self.uniform_blocks.len())?;
+		for block in self.uniform_blocks.iter() {
+			writeln!(f, "  {} {}: binding = {}, {} bytes", block.index, block.name, block.binding, block.data_size)?;
+		}
+		writeln!(f, "Active Shader Storage Blocks: {}", self.storage_blocks.len())?;
+		for block in self.storage_blocks.iter() {
+			writeln!(f, "  {} {}: binding = {}, {} bytes", block.index, block.name, block.binding, block.data_size)?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,191 @@
+use super::{program::include_name, ShaderError, ShaderProgram, Stage};
+
+use std::{
+	cell::{Cell, Ref, RefCell},
+	fs,
+	path::{Path, PathBuf},
+	time::{Duration, Instant, SystemTime},
+};
+
+/// Where the shader sources live on disk.  Only used by debug builds, release builds always use
+/// the sources that were embedded when they were compiled.
+pub const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+
+/// How often to look at the file system for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Snippets that shader files can pull in with an `#include "name"` line, embedded like the files.
+const INCLUDES: &[(&str, &str)] = &[
+	(
+		"voxel_occlusion.glsl",
+		include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/voxel_occlusion.glsl")),
+	),
+	(
+		"voxel_shading.glsl",
+		include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/voxel_shading.glsl")),
+	),
+];
+
+/// Builds a `ShaderFile` for a file in `src/shaders/`, embedding its contents.
+#[macro_export]
+macro_rules! shader_file {
+	($stage:expr, $name:literal) => {
+		$crate::shader::ShaderFile::new(
+			$stage,
+			$name,
+			include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/", $name)),
+		)
+	};
+}
+
+pub struct ShaderFile {
+	pub stage: Stage,
+	pub name: &'static str,
+	embedded: &'static str,
+}
+impl ShaderFile {
+	pub fn new(stage: Stage, name: &'static str, embedded: &'static str) -> ShaderFile {
+		ShaderFile { stage, name, embedded }
+	}
+	fn path(&self) -> PathBuf {
+		PathBuf::from(SHADER_DIR).join(self.name)
+	}
+	/// The latest change to the file or to the snippets it includes
+	fn modified(&self) -> Option<SystemTime> {
+		let source = self.source();
+		include_names(&source)
+			.map(|name| PathBuf::from(SHADER_DIR).join(name))
+			.chain(Some(self.path()))
+			.map(|path| modified(&path))
+			.max()
+			.flatten()
+	}
+	/// In debug builds, prefer what's on disk so that edits are picked up.
+	fn source(&self) -> String {
+		if cfg!(debug_assertions) {
+			if let Ok(source) = fs::read_to_string(self.path()) {
+				return source;
+			}
+		}
+		self.embedded.to_string()
+	}
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+	fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// The snippet `name` from `INCLUDES`, from disk in debug builds like the shader files
+fn include_source(name: &str) -> Option<String> {
+	let (_, embedded) = INCLUDES.iter().find(|(include, _)| *include == name)?;
+	if cfg!(debug_assertions) {
+		if let Ok(source) = fs::read_to_string(PathBuf::from(SHADER_DIR).join(name)) {
+			return Some(source);
+		}
+	}
+	Some(embedded.to_string())
+}
+
+fn include_names(source: &str) -> impl Iterator<Item = &str> {
+	source.lines().filter_map(include_name)
+}
+
+/// The names and sources of the snippets `source` includes, for `ProgramBuilder::stage_with_includes`
+fn includes(source: &str) -> Vec<(String, String)> {
+	let mut includes: Vec<(String, String)> = Vec::new();
+	for name in include_names(source) {
+		if let (false, Some(included)) = (includes.iter().any(|(include, _)| include == name), include_source(name)) {
+			includes.push((name.to_string(), included));
+		}
+	}
+	includes
+}
+
+/// A program which, in debug builds, rebuilds itself when any of its source files change.  If the
+/// new sources fail to build then the error is printed and the last working program is kept.
+pub struct ReloadableProgram {
+	files: Vec<ShaderFile>,
+	program: RefCell<ShaderProgram>,
+	modified: RefCell<Vec<Option<SystemTime>>>,
+	last_poll: Cell<Instant>,
+}
+impl ReloadableProgram {
+	pub fn new(files: Vec<ShaderFile>) -> Result<ReloadableProgram, ShaderError> {
+		let modified = files.iter().map(ShaderFile::modified).collect();
+		let program = ReloadableProgram::build(&files)?;
+		Ok(ReloadableProgram {
+			files,
+			program: RefCell::new(program),
+			modified: RefCell::new(modified),
+			last_poll: Cell::new(Instant::now()),
+		})
+	}
+	fn build(files: &[ShaderFile]) -> Result<ShaderProgram, ShaderError> {
+		files
+			.iter()
+			.fold(ShaderProgram::builder(), |builder, file| {
+				let source = file.source();
+				let includes = includes(&source);
+				builder.stage_with_includes(file.stage, file.name, &source, includes)
+			})
+			.build()
+	}
+	pub fn program(&self) -> Ref<'_, ShaderProgram> {
+		self.program.borrow()
+	}
+	/// Check whether any of the source files have changed and, if so, swap in a freshly built
+	/// program.  Returns true if the program was replaced.  Does nothing in release builds.
+	pub fn refresh(&self) -> bool {
+		if !cfg!(debug_assertions) || self.last_poll.get().elapsed() < POLL_INTERVAL {
+			return false;
+		}
+		self.last_poll.set(Instant::now());
+
+		let modified: Vec<_> = self.files.iter().map(ShaderFile::modified).collect();
+		if modified == *self.modified.borrow() {
+			return false;
+		}
+		// Remember the new times even if the build fails so we don't spam the same error every poll.
+		*self.modified.borrow_mut() = modified;
+
+		match ReloadableProgram::build(&self.files) {
+			Ok(program) => {
+				println!("Reloaded shader program {}", program.name());
+				*self.program.borrow_mut() = program;
+				true
+			}
+			Err(err) => {
+				eprintln!("{}", err);
+				eprintln!("Keeping the previous shader program.");
+				false
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn includes_are_found() {
+		let source = "#version 430\n#include \"voxel_shading.glsl\"\n  #include   \"voxel_occlusion.glsl\"\n#include \"voxel_shading.glsl\"\n#include \"missing.glsl\"\n";
+		assert_eq!(
+			include_names(source).collect::<Vec<_>>(),
+			["voxel_shading.glsl", "voxel_occlusion.glsl", "voxel_shading.glsl", "missing.glsl"]
+		);
+		// Each once, and only the ones that exist
+		let names: Vec<_> = includes(source).into_iter().map(|(name, _)| name).collect();
+		assert_eq!(names, ["voxel_shading.glsl", "voxel_occlusion.glsl"]);
+	}
+
+	#[test]
+	fn the_included_snippets_exist() {
+		for entry in fs::read_dir(SHADER_DIR).unwrap() {
+			let source = fs::read_to_string(entry.unwrap().path()).unwrap();
+			for name in include_names(&source) {
+				assert!(include_source(name).is_some(), "{} isn't in INCLUDES", name);
+			}
+		}
+	}
+}
@@ -0,0 +1,273 @@
+use super::{
+	reflection::{reflect, GlslType, Uniform},
+	ShaderProgram,
+};
+use crate::gl;
+use gl::types::*;
+
+use cgmath::{Matrix2, Matrix3, Matrix4, Vector2, Vector3, Vector4};
+
+use std::{error::Error, fmt};
+
+/// A single (non-array) value that can be stored in a uniform.
+///
+/// The upload uses the `glProgramUniform*` family, so it doesn't matter which program is bound.
+pub trait UniformElement: Sized {
+	/// The GLSL type this maps to.  Used for error messages.
+	const GLSL_TYPE: GlslType;
+	/// Can this be assigned to a uniform of the given GLSL type?
+	fn accepts(kind: GlslType) -> bool {
+		kind == Self::GLSL_TYPE
+	}
+	unsafe fn upload(values: &[Self], program: GLuint, location: GLint);
+}
+
+/// Anything that can be passed to `ShaderProgram::set`: single values as well as arrays of them.
+pub trait UniformValue {
+	type Element: UniformElement;
+	fn as_elements(&self) -> &[Self::Element];
+}
+impl<T: UniformElement> UniformValue for T {
+	type Element = T;
+	fn as_elements(&self) -> &[T] {
+		std::slice::from_ref(self)
+	}
+}
+impl<T: UniformElement> UniformValue for [T] {
+	type Element = T;
+	fn as_elements(&self) -> &[T] {
+		self
+	}
+}
+impl<T: UniformElement, const N: usize> UniformValue for [T; N] {
+	type Element = T;
+	fn as_elements(&self) -> &[T] {
+		self
+	}
+}
+impl<T: UniformElement> UniformValue for Vec<T> {
+	type Element = T;
+	fn as_elements(&self) -> &[T] {
+		self
+	}
+}
+
+impl UniformElement for f32 {
+	const GLSL_TYPE: GlslType = GlslType::Float;
+	unsafe fn upload(values: &[Self], program: GLuint, location: GLint) {
+		gl::ProgramUniform1fv(program, location, values.len() as GLsizei, values.as_ptr());
+	}
+}
+impl UniformElement for i32 {
+	const GLSL_TYPE: GlslType = GlslType::Int;
+	/// Samplers and images are set with the texture unit they should read from.
+	fn accepts(kind: GlslType) -> bool {
+		matches!(
+			kind,
+			GlslType::Int
+				| GlslType::Bool
+				| GlslType::Sampler1D
+				| GlslType::Sampler2D
+				| GlslType::Sampler3D
+				| GlslType::SamplerCube
+				| GlslType::Sampler2DShadow
+				| GlslType::Sampler2DArray
+				| GlslType::SamplerBuffer
+				| GlslType::ISampler2D
+				| GlslType::ISampler3D
+				| GlslType::USampler2D
+				| GlslType::USampler3D
+				| GlslType::Image2D
+				| GlslType::Image3D
+				| GlslType::IImage3D
+				| GlslType::UImage3D
+		)
+	}
+	unsafe fn upload(values: &[Self], program: GLuint, location: GLint) {
+		gl::ProgramUniform1iv(program, location, values.len() as GLsizei, values.as_ptr());
+	}
+}
+impl UniformElement for u32 {
+	const GLSL_TYPE: GlslType = GlslType::UInt;
+	unsafe fn upload(values: &[Self], program: GLuint, location: GLint) {
+		gl::ProgramUniform1uiv(program, location, values.len() as GLsizei, values.as_ptr());
+	}
+}
+impl UniformElement for bool {
+	const GLSL_TYPE: GlslType = GlslType::Bool;
+	unsafe fn upload(values: &[Self], program: GLuint, location: GLint) {
+		// GL wants bools as ints
+		let values: Vec<i32> = values.iter().map(|&value| value as i32).collect();
+		gl::ProgramUniform1iv(program, location, values.len() as GLsizei, values.as_ptr());
+	}
+}
+
+// cgmath's vectors and matrices are #[repr(C)], so a slice of them is a flat, column-major array of their scalars.
+macro_rules! uniform_element {
+	($rust:ty, $glsl:ident, $upload:ident) => {
+		impl UniformElement for $rust {
+			const GLSL_TYPE: GlslType = GlslType::$glsl;
+			unsafe fn upload(values: &[Self], program: GLuint, location: GLint) {
+				gl::$upload(program, location, values.len() as GLsizei, values.as_ptr() as *const _);
+			}
+		}
+	};
+	($rust:ty, $glsl:ident, $upload:ident, matrix) => {
+		impl UniformElement for $rust {
+			const GLSL_TYPE: GlslType = GlslType::$glsl;
+			unsafe fn upload(values: &[Self], program: GLuint, location: GLint) {
+				gl::$upload(program, location, values.len() as GLsizei, gl::FALSE, values.as_ptr() as *const _);
+			}
+		}
+	};
+}
+uniform_element!(Vector2<f32>, Vec2, ProgramUniform2fv);
+uniform_element!(Vector3<f32>, Vec3, ProgramUniform3fv);
+uniform_element!(Vector4<f32>, Vec4, ProgramUniform4fv);
+uniform_element!(Vector2<i32>, IVec2, ProgramUniform2iv);
+uniform_element!(Vector3<i32>, IVec3, ProgramUniform3iv);
+uniform_element!(Vector4<i32>, IVec4, ProgramUniform4iv);
+uniform_element!(Vector2<u32>, UVec2, ProgramUniform2uiv);
+uniform_element!(Vector3<u32>, UVec3, ProgramUniform3uiv);
+uniform_element!(Vector4<u32>, UVec4, ProgramUniform4uiv);
+uniform_element!(Matrix2<f32>, Mat2, ProgramUniformMatrix2fv, matrix);
+uniform_element!(Matrix3<f32>, Mat3, ProgramUniformMatrix3fv, matrix);
+uniform_element!(Matrix4<f32>, Mat4, ProgramUniformMatrix4fv, matrix);
+
+#[derive(Debug)]
+pub enum UniformError {
+	/// The uniform isn't active in the program (it may have been optimized out).
+	NotFound { name: String },
+	/// The uniform lives in a uniform block and has to be set through a buffer.
+	InBlock { name: String },
+	TypeMismatch { name: String, glsl: GlslType, rust: GlslType },
+	TooManyElements { name: String, size: usize, given: usize },
+}
+impl fmt::Display for UniformError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			UniformError::NotFound { name } => write!(f, "Uniform {} isn't active in the program", name),
+			UniformError::InBlock { name } => write!(f, "Uniform {} is part of a uniform block", name),
+			UniformError::TypeMismatch { name, glsl, rust } => write!(
+				f,
+				"Uniform {} is declared as a {} in GLSL but was given a {}",
+				name, glsl, rust
+			),
+			UniformError::TooManyElements { name, size, given } => write!(
+				f,
+				"Uniform {} has {} elements but was given {}",
+				name, size, given
+			),
+		}
+	}
+}
+impl Error for UniformError {}
+
+/// Set a uniform on any program (bound or not) using its cached reflection.
+pub fn set_uniform<T: UniformValue + ?Sized>(program: GLuint, name: &str, value: &T) -> Result<(), UniformError> {
+	let reflection = reflect(program);
+	let uniform = reflection.uniform(name).ok_or_else(|| UniformError::NotFound {
+		name: name.to_string(),
+	})?;
+	check(uniform, value)?;
+	unsafe {
+		T::Element::upload(value.as_elements(), program, uniform.location);
+	}
+	Ok(())
+}
+
+/// Whether `value` can be stored in `uniform`.
+fn check<T: UniformValue + ?Sized>(uniform: &Uniform, value: &T) -> Result<(), UniformError> {
+	let name = &uniform.name;
+	if uniform.block.is_some() {
+		return Err(UniformError::InBlock { name: name.clone() });
+	}
+	if !T::Element::accepts(uniform.kind) {
+		return Err(UniformError::TypeMismatch {
+			name: name.clone(),
+			glsl: uniform.kind,
+			rust: T::Element::GLSL_TYPE,
+		});
+	}
+	let elements = value.as_elements();
+	if elements.len() > uniform.size as usize {
+		return Err(UniformError::TooManyElements {
+			name: name.clone(),
+			size: uniform.size as usize,
+			given: elements.len(),
+		});
+	}
+	Ok(())
+}
+
+impl ShaderProgram {
+	/// Set a uniform that the program may not use, like one only some of the shaders sharing the
+	/// code read.  Any other error is printed.
+	pub fn set_optional<T: UniformValue + ?Sized>(&self, name: &str, value: &T) {
+		match self.set(name, value) {
+			Ok(()) | Err(UniformError::NotFound { .. }) => {}
+			Err(err) => eprintln!("{}", err),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use cgmath::SquareMatrix;
+
+	fn uniform(kind: GlslType, size: i32) -> Uniform {
+		Uniform {
+			name: "test".to_string(),
+			kind,
+			size,
+			location: 0,
+			block: None,
+		}
+	}
+
+	#[test]
+	fn matching_values_are_accepted() {
+		assert!(check(&uniform(GlslType::Float, 1), &1.0f32).is_ok());
+		assert!(check(&uniform(GlslType::Vec3, 1), &Vector3::new(0.0f32, 1.0, 0.0)).is_ok());
+		assert!(check(&uniform(GlslType::Mat4, 1), &Matrix4::<f32>::identity()).is_ok());
+		// Samplers take the texture unit
+		assert!(check(&uniform(GlslType::Sampler2D, 1), &0i32).is_ok());
+		// Arrays can be set partially
+		assert!(check(&uniform(GlslType::Float, 4), &[1.0f32, 2.0]).is_ok());
+	}
+
+	#[test]
+	fn scalars_and_vectors_dont_mix() {
+		let err = check(&uniform(GlslType::Vec3, 1), &1.0f32).unwrap_err();
+		assert!(matches!(err, UniformError::TypeMismatch { glsl: GlslType::Vec3, rust: GlslType::Float, .. }));
+		let err = check(&uniform(GlslType::Float, 1), &Vector3::new(0.0f32, 1.0, 0.0)).unwrap_err();
+		assert!(matches!(err, UniformError::TypeMismatch { glsl: GlslType::Float, rust: GlslType::Vec3, .. }));
+		// Nor do floats and ints
+		assert!(check(&uniform(GlslType::Vec3, 1), &Vector3::new(0, 1, 0)).is_err());
+		assert!(check(&uniform(GlslType::Float, 1), &1i32).is_err());
+	}
+
+	#[test]
+	fn matrices_have_to_be_the_declared_size() {
+		let err = check(&uniform(GlslType::Mat4, 1), &Matrix3::<f32>::identity()).unwrap_err();
+		assert!(matches!(err, UniformError::TypeMismatch { glsl: GlslType::Mat4, rust: GlslType::Mat3, .. }));
+		assert!(check(&uniform(GlslType::Mat2, 1), &Matrix4::<f32>::identity()).is_err());
+		assert!(check(&uniform(GlslType::Vec4, 1), &Matrix2::<f32>::identity()).is_err());
+	}
+
+	#[test]
+	fn arrays_cant_be_overfilled() {
+		let err = check(&uniform(GlslType::Float, 2), &[1.0f32, 2.0, 3.0]).unwrap_err();
+		assert!(matches!(err, UniformError::TooManyElements { size: 2, given: 3, .. }));
+		assert_eq!(err.to_string(), "Uniform test has 2 elements but was given 3");
+		assert!(check(&uniform(GlslType::Vec3, 1), &vec![Vector3::new(0.0f32, 0.0, 0.0); 2]).is_err());
+	}
+
+	#[test]
+	fn uniforms_in_blocks_are_refused() {
+		let mut in_block = uniform(GlslType::Float, 1);
+		in_block.block = Some((0, 16));
+		assert!(matches!(check(&in_block, &1.0f32), Err(UniformError::InBlock { .. })));
+	}
+}
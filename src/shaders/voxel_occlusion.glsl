@@ -0,0 +1,29 @@
+// Ambient occlusion from the occupancy texture, included by voxel.glslg and voxel_mesh.glslf
+
+// Which cells of this chunk and of the border around it have a voxel (see OccupancyGrid in
+// voxel/lighting.rs)
+uniform sampler3D occupancy;
+
+// Whether `cell` of this chunk, or of the neighbours right next to it, has a voxel.  Further out
+// counts as empty.
+bool occupied(ivec3 cell) {
+	// The texture starts one cell before the chunk, for the voxels of the neighbours around it
+	ivec3 texel = cell + 1;
+	if (any(lessThan(texel, ivec3(0))) || any(greaterThanEqual(texel, textureSize(occupancy, 0)))) {
+		return false;
+	}
+	return texelFetch(occupancy, texel, 0).r > 0.5;
+}
+
+// How much ambient light reaches a corner of a face, from 0 to 1.  `air` is the cell in front of
+// the face, `u` and `v` point along it and `corner` picks the corner (-1 or 1 each).  This has to
+// match corner_occlusion in voxel/lighting.rs, whose tests run this function.
+float corner_occlusion(ivec3 air, ivec3 u, ivec3 v, ivec2 corner) {
+	bool side1 = occupied(air + corner.x * u);
+	bool side2 = occupied(air + corner.y * v);
+	bool diagonal = occupied(air + corner.x * u + corner.y * v);
+	if (side1 && side2) {
+		return 0.0;
+	}
+	return (3.0 - float(side1) - float(side2) - float(diagonal)) / 3.0;
+}
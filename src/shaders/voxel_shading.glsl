@@ -0,0 +1,20 @@
+// The sun and sky lighting, included by voxel.glslf and voxel_mesh.glslf
+
+// See Lighting in voxel/lighting.rs: 0 is unlit, 2 is with ambient occlusion
+uniform int lighting;
+// Towards the sun
+uniform vec3 sun_direction;
+
+const vec3 SUN_COLOR = vec3(1.0, 0.95, 0.85);
+const vec3 SKY_COLOR = vec3(0.45, 0.55, 0.7);
+const vec3 GROUND_COLOR = vec3(0.25, 0.22, 0.2);
+
+vec3 shade(vec3 color, vec3 normal, float occlusion) {
+	if (lighting == 0) {
+		return color;
+	}
+	float sun = max(dot(normal, sun_direction), 0.0);
+	// Hemisphere ambient: the sky from above, light bounced off the ground from below
+	vec3 ambient = mix(GROUND_COLOR, SKY_COLOR, 0.5 + 0.5 * normal.y) * mix(0.3, 1.0, occlusion);
+	return color * (ambient + SUN_COLOR * 0.75 * sun);
+}
@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+/// Turns wall clock time into a whole number of fixed simulation steps so that animation speed
+/// doesn't depend on the frame rate.  Whatever is left over becomes the interpolation alpha for
+/// drawing between the last two simulation states.
+pub struct FixedTimestep {
+	/// Simulated time per step
+	pub step: Duration,
+	/// The most steps a single frame will run.  If the simulation falls further behind than this
+	/// (a breakpoint, dragging the window, ...) the extra time is dropped instead of catching up.
+	pub max_steps: u32,
+	/// How fast simulated time passes relative to the wall clock
+	pub time_scale: f32,
+	accumulator: Duration,
+	/// Steps requested with `single_step` while paused
+	pending_steps: u32,
+	/// Total simulated time
+	elapsed: Duration,
+}
+impl FixedTimestep {
+	pub fn new(step: Duration) -> FixedTimestep {
+		FixedTimestep {
+			step,
+			max_steps: 8,
+			time_scale: 1.0,
+			accumulator: Duration::from_secs(0),
+			pending_steps: 0,
+			elapsed: Duration::from_secs(0),
+		}
+	}
+	/// Add `real_elapsed` of wall clock time and return how many steps to simulate.  While paused
+	/// no time accumulates, but steps requested through `single_step` are still returned.
+	pub fn advance(&mut self, real_elapsed: Duration, paused: bool) -> u32 {
+		let steps = if paused {
+			std::mem::replace(&mut self.pending_steps, 0)
+		} else {
+			self.pending_steps = 0;
+			// Skip the float round trip at normal speed so that stepping by exactly `step` gives exactly one step
+			self.accumulator += if self.time_scale == 1.0 {
+				real_elapsed
+			} else {
+				real_elapsed.mul_f64(self.time_scale as f64)
+			};
+			let mut steps = (self.accumulator.as_nanos() / self.step.as_nanos()) as u32;
+			if steps > self.max_steps {
+				steps = self.max_steps;
+				self.accumulator = self.step * steps;
+			}
+			self.accumulator -= self.step * steps;
+			steps
+		};
+		self.elapsed += self.step * steps;
+		steps
+	}
+	/// Run one step the next time `advance` is called while paused.
+	pub fn single_step(&mut self) {
+		self.pending_steps += 1;
+	}
+	/// How far (0 to 1) the current moment is between the last simulation step and the next one.
+	pub fn alpha(&self) -> f32 {
+		self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+	}
+	/// Total simulated time, including the partial step
+	pub fn time(&self) -> Duration {
+		self.elapsed + self.accumulator
+	}
+	/// Multiply the time scale, keeping it between 1/16x and 4x.
+	pub fn scale_time(&mut self, factor: f32) {
+		self.time_scale = (self.time_scale * factor).clamp(1.0 / 16.0, 4.0);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ms(millis: u64) -> Duration {
+		Duration::from_millis(millis)
+	}
+
+	#[test]
+	fn leftover_time_carries_over() {
+		let mut timestep = FixedTimestep::new(ms(10));
+		assert_eq!(timestep.advance(ms(10), false), 1);
+		assert_eq!(timestep.advance(ms(25), false), 2);
+		assert!((timestep.alpha() - 0.5).abs() < 1e-6);
+		// The half step left over completes the next one
+		assert_eq!(timestep.advance(ms(5), false), 1);
+		assert_eq!(timestep.alpha(), 0.0);
+		assert_eq!(timestep.time(), ms(40));
+		assert_eq!(timestep.advance(ms(3), false), 0);
+		assert_eq!(timestep.time(), ms(43));
+	}
+
+	#[test]
+	fn falling_behind_drops_time() {
+		let mut timestep = FixedTimestep::new(ms(10));
+		timestep.max_steps = 4;
+		assert_eq!(timestep.advance(ms(1000), false), 4);
+		// Nothing is owed from the dropped time
+		assert_eq!(timestep.alpha(), 0.0);
+		assert_eq!(timestep.advance(ms(10), false), 1);
+		assert_eq!(timestep.time(), ms(50));
+	}
+
+	#[test]
+	fn time_scale_slows_and_speeds_up() {
+		let mut timestep = FixedTimestep::new(ms(10));
+		timestep.time_scale = 0.5;
+		assert_eq!(timestep.advance(ms(40), false), 2);
+		timestep.time_scale = 2.0;
+		assert_eq!(timestep.advance(ms(10), false), 2);
+		assert_eq!(timestep.time(), ms(40));
+
+		timestep.scale_time(100.0);
+		assert_eq!(timestep.time_scale, 4.0);
+		timestep.scale_time(0.0);
+		assert_eq!(timestep.time_scale, 1.0 / 16.0);
+	}
+
+	#[test]
+	fn paused_only_runs_single_steps() {
+		let mut timestep = FixedTimestep::new(ms(10));
+		assert_eq!(timestep.advance(ms(15), false), 1);
+		assert_eq!(timestep.advance(ms(100), true), 0);
+		timestep.single_step();
+		timestep.single_step();
+		assert_eq!(timestep.advance(ms(100), true), 2);
+		assert_eq!(timestep.advance(ms(100), true), 0);
+		// The partial step from before the pause is kept
+		assert!((timestep.alpha() - 0.5).abs() < 1e-6);
+		assert_eq!(timestep.time(), ms(35));
+		// Steps requested while running are dropped
+		timestep.single_step();
+		assert_eq!(timestep.advance(ms(0), false), 0);
+		assert_eq!(timestep.advance(ms(0), true), 0);
+	}
+}
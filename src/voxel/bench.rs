@@ -0,0 +1,95 @@
+use super::{drift, BlockId, Chunk, ChunkItem, ChunkSize, ShadowBuffer, Voxel, VoxelStorage};
+use crate::{gl, headless};
+
+use glutin::dpi::PhysicalSize;
+use rand::{rngs::StdRng, SeedableRng};
+
+use std::{
+	error::Error,
+	ffi::c_void,
+	mem::size_of,
+	time::{Duration, Instant},
+};
+
+pub const FLAG: &str = "--bench-upload";
+/// How many frames to time each way
+pub const FRAMES_FLAG: &str = "--bench-frames";
+
+const STEP: Duration = Duration::from_millis(16);
+
+/// Compare how long a frame of `VoxelProject::anim` + upload takes with the old per item
+/// `glGetBufferSubData` / `glBufferSubData` round trips against the shadow copy.  Runs headless.
+pub fn run(frames: u32) -> Result<(), Box<dyn Error>> {
+	let _surface = headless::create_surface(PhysicalSize::new(64.0, 64.0))?;
+	let shader = Chunk::build_chunk_shader()?;
+	let mut rng = StdRng::seed_from_u64(0);
+	let size = ChunkSize::DEFAULT;
+	let voxel = Voxel::new(BlockId::SOLID, 0.5, (0.5, 0.5, 0.5));
+	let voxels = VoxelStorage::from_voxels(size, &vec![voxel; size.len()]);
+	let initial: Vec<ChunkItem> = (0..size.len()).map(|index| ChunkItem::from_voxel(index, voxel)).collect();
+
+	println!("{} items, {} frames each", size.len(), frames);
+
+	// What anim used to do: read and write every item separately
+	let mut buffer = 0;
+	unsafe {
+		gl::GenBuffers(1, &mut buffer);
+		gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+		gl::BufferData(gl::ARRAY_BUFFER, (size_of::<ChunkItem>() * size.len()) as isize, initial.as_ptr() as *const c_void, gl::DYNAMIC_DRAW);
+		gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+	}
+	report("per item round trips", frames, || unsafe {
+		gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+		for i in 0..size.len() {
+			let offset = (i * size_of::<ChunkItem>()) as isize;
+			let mut item = initial[0];
+			gl::GetBufferSubData(gl::ARRAY_BUFFER, offset, size_of::<ChunkItem>() as isize, &mut item as *mut _ as *mut c_void);
+			drift(std::slice::from_mut(&mut item), &mut rng, STEP);
+			gl::BufferSubData(gl::ARRAY_BUFFER, offset, size_of::<ChunkItem>() as isize, &item as *const _ as *const c_void);
+		}
+		gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+	});
+	unsafe {
+		gl::DeleteBuffers(1, &buffer);
+	}
+
+	let mut chunk = Chunk::with_buffer(&shader.program(), voxels.clone(), ShadowBuffer::new_orphaning(initial.clone()), 0.0, 0.0, 0.0);
+	report("shadow copy, orphaning", frames, || {
+		chunk.update(|items| drift(items, &mut rng, STEP));
+		chunk.items.flush();
+	});
+
+	let mut chunk = Chunk::with_buffer(&shader.program(), voxels, ShadowBuffer::new(initial), 0.0, 0.0, 0.0);
+	if chunk.items.is_persistent() {
+		report("shadow copy, persistent mapping", frames, || {
+			chunk.update(|items| drift(items, &mut rng, STEP));
+			chunk.items.flush();
+			chunk.items.fence();
+		});
+	} else {
+		println!("ARB_buffer_storage isn't available: skipping the persistently mapped buffer");
+	}
+	Ok(())
+}
+
+/// Time `frame` (plus waiting for the GPU to catch up) and print the mean and worst times.
+fn report(name: &str, frames: u32, mut frame: impl FnMut()) {
+	let mut total = Duration::from_secs(0);
+	let mut worst = Duration::from_secs(0);
+	for _ in 0..frames {
+		let start = Instant::now();
+		frame();
+		unsafe {
+			gl::Finish();
+		}
+		let elapsed = start.elapsed();
+		total += elapsed;
+		worst = worst.max(elapsed);
+	}
+	println!(
+		"{:<34} mean {:>8.3} ms   worst {:>8.3} ms",
+		name,
+		total.as_secs_f64() * 1000.0 / frames.max(1) as f64,
+		worst.as_secs_f64() * 1000.0
+	);
+}
@@ -0,0 +1,261 @@
+use crate::gl;
+use gl::types::*;
+
+use std::{mem::size_of, ops::Range, ptr};
+
+/// How many copies of the data the persistent buffer cycles through.  While the GPU draws from
+/// one, the next frame's changes go into another.
+const REGIONS: usize = 3;
+
+enum Storage {
+	/// `ARB_buffer_storage`: the buffer is `REGIONS` times as big as the data and stays mapped.
+	Persistent {
+		mapping: *mut u8,
+		/// Changes that haven't been written to each region yet
+		dirty: [Option<Range<usize>>; REGIONS],
+		/// Set after drawing from a region, so we know when it's safe to write to it again
+		fences: [GLsync; REGIONS],
+		current: usize
+	},
+	/// Plain `glBufferSubData`, or orphaning with `glBufferData` when most of the data changed.
+	Orphaning
+}
+
+/// A GPU buffer of `T`s with a copy on the CPU.  Reads come from the copy, writes mark a dirty range
+/// that `flush` uploads (once per frame) without waiting on the GPU.
+pub struct ShadowBuffer<T: Copy> {
+	items: Vec<T>,
+	dirty: Option<Range<usize>>,
+	/// Goes up whenever the items change, so that anything built from them knows to rebuild
+	generation: u64,
+	buffer: GLuint,
+	storage: Storage
+}
+impl<T: Copy> ShadowBuffer<T> {
+	pub fn new(items: Vec<T>) -> ShadowBuffer<T> {
+		let persistent = gl::BufferStorage::is_loaded() && !items.is_empty();
+		ShadowBuffer::with_storage(items, persistent)
+	}
+	/// Like `new`, but never use a persistently mapped buffer.
+	pub fn new_orphaning(items: Vec<T>) -> ShadowBuffer<T> {
+		ShadowBuffer::with_storage(items, false)
+	}
+	fn with_storage(items: Vec<T>, persistent: bool) -> ShadowBuffer<T> {
+		let bytes = items.len() * size_of::<T>();
+		let mut buffer = 0;
+		let storage = unsafe {
+			gl::GenBuffers(1, &mut buffer);
+			gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+			let storage = if persistent {
+				let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+				gl::BufferStorage(gl::ARRAY_BUFFER, (bytes * REGIONS) as GLsizeiptr, ptr::null(), flags);
+				let mapping = gl::MapBufferRange(gl::ARRAY_BUFFER, 0, (bytes * REGIONS) as GLsizeiptr, flags) as *mut u8;
+				if mapping.is_null() {
+					None
+				} else {
+					Some(Storage::Persistent {
+						mapping,
+						// Every region starts out empty
+						dirty: [Some(0..items.len()), Some(0..items.len()), Some(0..items.len())],
+						fences: [ptr::null(); REGIONS],
+						current: 0
+					})
+				}
+			} else {
+				None
+			};
+			let storage = storage.unwrap_or_else(|| {
+				if persistent {
+					// Immutable storage can't be reallocated, so start over with a new buffer
+					gl::DeleteBuffers(1, &buffer);
+					gl::GenBuffers(1, &mut buffer);
+					gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+				}
+				gl::BufferData(gl::ARRAY_BUFFER, bytes as GLsizeiptr, items.as_ptr() as *const _, gl::STREAM_DRAW);
+				Storage::Orphaning
+			});
+			gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+			storage
+		};
+		// The persistent regions still have to be filled in
+		let dirty = if matches!(storage, Storage::Persistent { .. }) {
+			Some(0..items.len())
+		} else {
+			None
+		};
+		let mut shadow = ShadowBuffer {
+			items,
+			dirty,
+			generation: 0,
+			buffer,
+			storage
+		};
+		shadow.flush();
+		shadow
+	}
+
+	pub fn len(&self) -> usize {
+		self.items.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.items.is_empty()
+	}
+	pub fn is_persistent(&self) -> bool {
+		matches!(self.storage, Storage::Persistent { .. })
+	}
+	pub fn items(&self) -> &[T] {
+		&self.items
+	}
+	pub fn get(&self, index: usize) -> T {
+		self.items[index]
+	}
+	pub fn set(&mut self, index: usize, item: T) {
+		self.items[index] = item;
+		self.mark_dirty(index..index + 1);
+	}
+	/// Change all of the items at once.
+	pub fn update<R>(&mut self, f: impl FnOnce(&mut [T]) -> R) -> R {
+		self.update_range(0..self.items.len(), f)
+	}
+	/// Change some of the items: only `range` gets uploaded.
+	pub fn update_range<R>(&mut self, range: Range<usize>, f: impl FnOnce(&mut [T]) -> R) -> R {
+		self.mark_dirty(range.clone());
+		f(&mut self.items[range])
+	}
+	/// The range that `flush` would upload
+	pub fn dirty(&self) -> Option<Range<usize>> {
+		self.dirty.clone()
+	}
+	/// Changes whenever the CPU copy does
+	pub fn generation(&self) -> u64 {
+		self.generation
+	}
+	fn mark_dirty(&mut self, range: Range<usize>) {
+		self.generation += 1;
+		self.dirty = Some(union(self.dirty.take(), range));
+	}
+
+	/// Send the changes made since the last flush to the GPU.
+	pub fn flush(&mut self) {
+		let dirty = match self.dirty.take() {
+			Some(dirty) => dirty,
+			None => return
+		};
+		let item_bytes = size_of::<T>();
+		let data_bytes = self.items.len() * item_bytes;
+		match &mut self.storage {
+			Storage::Persistent { mapping, dirty: region_dirty, fences, current } => {
+				for region in region_dirty.iter_mut() {
+					*region = Some(union(region.take(), dirty.clone()));
+				}
+				*current = (*current + 1) % REGIONS;
+				unsafe {
+					// Wait for the GPU to finish drawing from the region.  It was last used
+					// REGIONS - 1 frames ago so this should hardly ever block.
+					let fence = std::mem::replace(&mut fences[*current], ptr::null());
+					if !fence.is_null() {
+						gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, u64::MAX);
+						gl::DeleteSync(fence);
+					}
+					if let Some(range) = region_dirty[*current].take() {
+						ptr::copy_nonoverlapping(
+							self.items[range.clone()].as_ptr() as *const u8,
+							mapping.add(*current * data_bytes + range.start * item_bytes),
+							range.len() * item_bytes
+						);
+					}
+				}
+			}
+			Storage::Orphaning => unsafe {
+				gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer);
+				if dirty.len() * 2 > self.items.len() {
+					// Give the driver a fresh block of memory instead of waiting for the GPU to
+					// finish with the old contents
+					gl::BufferData(gl::ARRAY_BUFFER, data_bytes as GLsizeiptr, ptr::null(), gl::STREAM_DRAW);
+					gl::BufferSubData(gl::ARRAY_BUFFER, 0, data_bytes as GLsizeiptr, self.items.as_ptr() as *const _);
+				} else {
+					gl::BufferSubData(
+						gl::ARRAY_BUFFER,
+						(dirty.start * item_bytes) as GLintptr,
+						(dirty.len() * item_bytes) as GLsizeiptr,
+						self.items[dirty].as_ptr() as *const _
+					);
+				}
+				gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+			}
+		}
+	}
+	/// Call after the last draw that reads from the buffer this frame.
+	pub fn fence(&mut self) {
+		if let Storage::Persistent { fences, current, .. } = &mut self.storage {
+			unsafe {
+				if !fences[*current].is_null() {
+					gl::DeleteSync(fences[*current]);
+				}
+				fences[*current] = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+			}
+		}
+	}
+
+	/// Something on the GPU (a compute shader) wrote to the buffer: copy the current data back into
+	/// the shadow.  Any changes that weren't flushed yet are lost.
+	pub fn read_back(&mut self) {
+		let offset = self.offset();
+		let bytes = self.items.len() * size_of::<T>();
+		unsafe {
+			gl::MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+			gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer);
+			gl::GetBufferSubData(gl::ARRAY_BUFFER, offset as GLintptr, bytes as GLsizeiptr, self.items.as_mut_ptr() as *mut _);
+			gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+		}
+		self.dirty = None;
+		self.generation += 1;
+		if let Storage::Persistent { dirty, current, .. } = &mut self.storage {
+			// The other regions are out of date now
+			for (region, dirty) in dirty.iter_mut().enumerate() {
+				*dirty = if region == *current { None } else { Some(0..self.items.len()) };
+			}
+		}
+	}
+
+	/// How many copies of the data the GPU buffer holds
+	pub fn copies(&self) -> usize {
+		match self.storage {
+			Storage::Persistent { .. } => REGIONS,
+			Storage::Orphaning => 1
+		}
+	}
+	pub fn buffer_id(&self) -> GLuint {
+		self.buffer
+	}
+	/// Where the up to date copy of the data starts in the buffer, in bytes
+	pub fn offset(&self) -> usize {
+		match &self.storage {
+			Storage::Persistent { current, .. } => current * self.items.len() * size_of::<T>(),
+			Storage::Orphaning => 0
+		}
+	}
+}
+impl<T: Copy> Drop for ShadowBuffer<T> {
+	fn drop(&mut self) {
+		unsafe {
+			if let Storage::Persistent { fences, .. } = &self.storage {
+				for fence in fences.iter().filter(|fence| !fence.is_null()) {
+					gl::DeleteSync(*fence);
+				}
+				gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer);
+				gl::UnmapBuffer(gl::ARRAY_BUFFER);
+				gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+			}
+			gl::DeleteBuffers(1, &self.buffer);
+		}
+	}
+}
+
+/// The smallest range covering both
+fn union(a: Option<Range<usize>>, b: Range<usize>) -> Range<usize> {
+	match a {
+		Some(a) => a.start.min(b.start)..a.end.max(b.end),
+		None => b
+	}
+}
@@ -0,0 +1,68 @@
+//! Reading the little endian binary formats of `save` and `vox`.
+
+/// The bytes ended before what was being read.  Each format turns it into its own error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Truncated;
+
+/// Little endian reading that fails instead of panicking at the end of the bytes
+pub struct Reader<'a> {
+	bytes: &'a [u8],
+	position: usize
+}
+impl<'a> Reader<'a> {
+	pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+		Reader { bytes, position: 0 }
+	}
+	pub fn remaining(&self) -> usize {
+		self.bytes.len() - self.position
+	}
+	pub fn is_at_end(&self) -> bool {
+		self.remaining() == 0
+	}
+	pub fn take(&mut self, count: usize) -> Result<&'a [u8], Truncated> {
+		if count > self.remaining() {
+			return Err(Truncated);
+		}
+		let bytes = &self.bytes[self.position..self.position + count];
+		self.position += count;
+		Ok(bytes)
+	}
+	pub fn array<const N: usize>(&mut self) -> Result<[u8; N], Truncated> {
+		let mut array = [0; N];
+		array.copy_from_slice(self.take(N)?);
+		Ok(array)
+	}
+	pub fn u8(&mut self) -> Result<u8, Truncated> {
+		Ok(self.array::<1>()?[0])
+	}
+	pub fn u16(&mut self) -> Result<u16, Truncated> {
+		Ok(u16::from_le_bytes(self.array()?))
+	}
+	pub fn u32(&mut self) -> Result<u32, Truncated> {
+		Ok(u32::from_le_bytes(self.array()?))
+	}
+	pub fn i32(&mut self) -> Result<i32, Truncated> {
+		Ok(i32::from_le_bytes(self.array()?))
+	}
+	pub fn f32(&mut self) -> Result<f32, Truncated> {
+		Ok(f32::from_le_bytes(self.array()?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reading_past_the_end_fails() {
+		let mut reader = Reader::new(&[1, 0, 2, 0, 0, 0, 7]);
+		assert_eq!(reader.u16(), Ok(1));
+		assert_eq!(reader.u32(), Ok(2));
+		assert_eq!(reader.remaining(), 1);
+		assert_eq!(reader.u16(), Err(Truncated));
+		// Nothing is used up by a failed read
+		assert_eq!(reader.u8(), Ok(7));
+		assert!(reader.is_at_end());
+		assert_eq!(reader.take(1), Err(Truncated));
+	}
+}
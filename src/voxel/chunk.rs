@@ -0,0 +1,513 @@
+use super::{
+	back_to_front, greedy_mesh, lone_stamp, ChunkMemory, GpuMesh, Occupancy, OccupancyGrid, OccupancyStamp, ShadowBuffer, SortedInstances, Voxel,
+	VoxelStorage, OCCUPANCY_UNIT, OWN_SLOT
+};
+use crate::{camera, gl, shader::*, shader_file};
+
+use cgmath::{Matrix4, Point3, Vector3};
+
+use std::{
+	mem::size_of,
+	sync::atomic::{AtomicU64, Ordering},
+	time::Duration
+};
+
+/// The shader storage binding that voxel_drift.glslc reads the items from
+const ITEMS_BINDING: u32 = 1;
+
+/// Shared by all the chunks so that no two of them ever have the same generation
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+fn next_generation() -> u64 {
+	NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// What's drawn for one voxel that isn't empty.  The instance buffer only holds these, so each
+/// one says which cell it's for.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct ChunkItem {
+	pub size: f32,
+	pub color: (f32, f32, f32),
+	/// 1 is opaque
+	pub opacity: f32,
+	/// The cell (see `ChunkSize::index`)
+	pub index: u32
+}
+impl ChunkItem {
+	pub fn from_voxel(index: usize, voxel: Voxel) -> ChunkItem {
+		ChunkItem { size: voxel.size, color: voxel.color, opacity: voxel.opacity, index: index as u32 }
+	}
+}
+
+/// Which chunk of the world: chunk (1, 0, 0) starts where (0, 0, 0) ends along x.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChunkCoord {
+	pub x: i32,
+	pub y: i32,
+	pub z: i32
+}
+impl ChunkCoord {
+	pub fn new(x: i32, y: i32, z: i32) -> ChunkCoord {
+		ChunkCoord { x, y, z }
+	}
+	/// Squared distance in chunks, for loading the nearest chunks first
+	pub fn distance_squared(self, other: ChunkCoord) -> i32 {
+		let (dx, dy, dz) = (self.x - other.x, self.y - other.y, self.z - other.z);
+		dx * dx + dy * dy + dz * dz
+	}
+}
+
+/// How many voxels a chunk has along each axis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkSize {
+	pub width: usize,
+	pub height: usize,
+	pub depth: usize
+}
+impl ChunkSize {
+	pub const DEFAULT: ChunkSize = ChunkSize { width: 20, height: 60, depth: 20 };
+
+	pub fn new(width: usize, height: usize, depth: usize) -> ChunkSize {
+		ChunkSize { width, height, depth }
+	}
+	/// The number of voxels
+	pub fn len(&self) -> usize {
+		self.width * self.height * self.depth
+	}
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+	pub fn contains(&self, x: usize, y: usize, z: usize) -> bool {
+		x < self.width && y < self.height && z < self.depth
+	}
+	/// Where the voxel at `x, y, z` is stored: z changes fastest, then y, then x.
+	pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+		z + self.depth * y + self.depth * self.height * x
+	}
+	/// The inverse of `index`.  The vertex shader does the same with each instance's `index`
+	/// attribute (see `voxel_position` in voxel.glslv): the tests check that the two agree.
+	pub fn position(&self, index: usize) -> (usize, usize, usize) {
+		(index / (self.depth * self.height), index / self.depth % self.height, index % self.depth)
+	}
+	/// As the `chunk_size` uniform
+	pub fn as_uniform(&self) -> Vector3<i32> {
+		Vector3::new(self.width as i32, self.height as i32, self.depth as i32)
+	}
+}
+impl Default for ChunkSize {
+	fn default() -> ChunkSize {
+		ChunkSize::DEFAULT
+	}
+}
+
+/// A block of voxels.  The voxels themselves live in `voxels`, compressed; what's drawn is the list
+/// of the ones that aren't empty, built from them whenever they change.  The animation only changes
+/// that list, not the voxels.
+pub struct Chunk {
+	voxels: VoxelStorage,
+	/// Changed whenever `voxels` changes
+	generation: u64,
+	/// The instances, kept on the CPU as well so that reading and writing them doesn't wait on the
+	/// GPU.  Big enough for every cell, but only the first `instance_count` are used.
+	pub items: ShadowBuffer<ChunkItem>,
+	instance_count: usize,
+	/// `items` has to be built again from `voxels`
+	instances_stale: bool,
+	pub vao: u32,
+	/// For `RenderMode::Greedy`, built the first time it's drawn that way
+	mesh: Option<GpuMesh>,
+	/// For the ambient occlusion, built the first time it's drawn
+	occupancy: Option<Occupancy>,
+	/// For `draw_sorted`, built the first time it's drawn that way
+	sorted: Option<SortedInstances>,
+	pub size: ChunkSize,
+	pub x: f32,
+	pub y: f32,
+	pub z: f32
+}
+
+impl Chunk {
+	pub(super) fn build_chunk_shader() -> Result<ReloadableProgram, ShaderError> {
+		let program = ReloadableProgram::new(vec![
+			shader_file!(Stage::Vertex, "voxel.glslv"),
+			shader_file!(Stage::Geometry, "voxel.glslg"),
+			shader_file!(Stage::Fragment, "voxel.glslf"),
+		])?;
+
+		// DEBUG:
+		print!("{}", program.program().reflection());
+		if let Err(err) = camera::check_camera_block(&program.program().reflection()) {
+			eprintln!("{}", err);
+		}
+
+		Ok(program)
+	}
+	/// An empty chunk
+	pub fn new(chunk_shader: &ShaderProgram, size: ChunkSize, x: f32, y: f32, z: f32) -> Chunk {
+		Chunk::with_voxels(chunk_shader, VoxelStorage::new(size), x, y, z)
+	}
+	pub fn with_voxels(chunk_shader: &ShaderProgram, voxels: VoxelStorage, x: f32, y: f32, z: f32) -> Chunk {
+		let items = ShadowBuffer::new(vec![ChunkItem::from_voxel(0, Voxel::EMPTY); voxels.len()]);
+		Chunk::with_buffer(chunk_shader, voxels, items, x, y, z)
+	}
+	/// `items` has to have room for every cell.  What's in it is replaced.
+	pub fn with_buffer(chunk_shader: &ShaderProgram, voxels: VoxelStorage, items: ShadowBuffer<ChunkItem>, x: f32, y: f32, z: f32) -> Chunk {
+		assert_eq!(items.len(), voxels.len(), "A {:?} chunk needs room for {} items", voxels.size(), voxels.len());
+		let mut ret = Chunk {
+			x, y, z,
+			size: voxels.size(),
+			voxels,
+			generation: next_generation(),
+			items,
+			instance_count: 0,
+			instances_stale: true,
+			vao: 0,
+			mesh: None,
+			occupancy: None,
+			sorted: None
+		};
+		unsafe {
+			// 1) Create the vao: Used for drawing.
+			gl::GenVertexArrays(1, &mut ret.vao);
+			gl::BindVertexArray(ret.vao);
+
+			// 2) Describe the attributes using a temporary ChunkItem to get the offsets.  They all read
+			// from binding 0, which is pointed at the buffer when drawing (the data can move around in it).
+			let reflection = chunk_shader.reflection();
+			let temp = ChunkItem { size: 1.0, color: (1.0, 1.0, 1.0), opacity: 1.0, index: 0 };
+
+			// 2.a) Size
+			let location = reflection.attribute_location("size");
+			// Measure the distance to the start of the struct field and use that as the offset:
+			let offset = std::ptr::addr_of!(temp.size) as usize - &temp as *const _ as usize;
+			if (location != -1) {
+				gl::VertexAttribFormat(location as u32, 1, gl::FLOAT, gl::FALSE, offset as u32);
+				gl::VertexAttribBinding(location as u32, 0);
+				gl::EnableVertexAttribArray(location as u32);
+			} else {
+				println!("Attribute size didn't appear in the shader program.");
+			}
+			// 2.b) Color
+			let location = reflection.attribute_location("color");
+			let offset = std::ptr::addr_of!(temp.color) as usize - &temp as *const _ as usize;
+			if (location != -1) {
+				gl::VertexAttribFormat(location as u32, 3, gl::FLOAT, gl::FALSE, offset as u32);
+				gl::VertexAttribBinding(location as u32, 0);
+				gl::EnableVertexAttribArray(location as u32);
+			} else {
+				println!("Attribute color didn't appear in the shader program.");
+			}
+			// 2.c) Opacity
+			let location = reflection.attribute_location("opacity");
+			let offset = std::ptr::addr_of!(temp.opacity) as usize - &temp as *const _ as usize;
+			if (location != -1) {
+				gl::VertexAttribFormat(location as u32, 1, gl::FLOAT, gl::FALSE, offset as u32);
+				gl::VertexAttribBinding(location as u32, 0);
+				gl::EnableVertexAttribArray(location as u32);
+			} else {
+				println!("Attribute opacity didn't appear in the shader program.");
+			}
+			// 2.d) Which cell, as an integer
+			let location = reflection.attribute_location("index");
+			let offset = std::ptr::addr_of!(temp.index) as usize - &temp as *const _ as usize;
+			if (location != -1) {
+				gl::VertexAttribIFormat(location as u32, 1, gl::UNSIGNED_INT, offset as u32);
+				gl::VertexAttribBinding(location as u32, 0);
+				gl::EnableVertexAttribArray(location as u32);
+			} else {
+				println!("Attribute index didn't appear in the shader program.");
+			}
+			gl::BindVertexArray(0);
+		}
+		ret.refresh_instances();
+		ret
+	}
+
+	pub fn voxels(&self) -> &VoxelStorage {
+		&self.voxels
+	}
+	pub fn get(&self, x: usize, y: usize, z: usize) -> Voxel {
+		self.voxels.get_at(x, y, z)
+	}
+	pub fn set(&mut self, x: usize, y: usize, z: usize, voxel: Voxel) {
+		self.voxels.set_at(x, y, z, voxel);
+		self.voxels_changed();
+	}
+	/// Start over with other voxels of the same size.
+	pub fn replace_voxels(&mut self, voxels: VoxelStorage) {
+		assert_eq!(voxels.size(), self.size);
+		self.voxels = voxels;
+		self.voxels_changed();
+	}
+	fn voxels_changed(&mut self) {
+		self.generation = next_generation();
+		self.instances_stale = true;
+	}
+	/// Changes whenever the voxels do (but not when only the instances are animated).  Unique
+	/// among all chunks, also the ones that were dropped.
+	pub fn generation(&self) -> u64 {
+		self.generation
+	}
+
+	/// The instances that are drawn
+	pub fn instances(&mut self) -> &[ChunkItem] {
+		self.refresh_instances();
+		&self.items.items()[..self.instance_count]
+	}
+	pub fn instance_count(&mut self) -> usize {
+		self.refresh_instances();
+		self.instance_count
+	}
+	/// Change any number of the instances.  They're uploaded together the next time the chunk is
+	/// drawn.  Changing the voxels starts them over.
+	pub fn update<R>(&mut self, f: impl FnOnce(&mut [ChunkItem]) -> R) -> R {
+		self.refresh_instances();
+		self.items.update_range(0..self.instance_count, f)
+	}
+	/// Rebuild the list of the voxels that aren't empty if they changed.
+	fn refresh_instances(&mut self) {
+		if !self.instances_stale {
+			return;
+		}
+		self.instances_stale = false;
+		let voxels = &self.voxels;
+		self.instance_count = voxels.filled();
+		self.items.update_range(0..self.instance_count, |items| {
+			for (item, (index, voxel)) in items.iter_mut().zip(voxels.iter_filled()) {
+				*item = ChunkItem::from_voxel(index, voxel);
+			}
+		});
+	}
+
+	pub fn memory(&self) -> ChunkMemory {
+		let instance_bytes = self.items.len() * size_of::<ChunkItem>() * self.items.copies();
+		ChunkMemory {
+			filled: self.voxels.filled(),
+			cells: self.voxels.len(),
+			palette_entries: self.voxels.palette().len(),
+			bits_per_voxel: self.voxels.bits_per_voxel(),
+			cpu_bytes: self.voxels.memory_bytes(),
+			dense_bytes: self.voxels.dense_bytes(),
+			gpu_bytes: instance_bytes
+				+ self.mesh.as_ref().map_or(0, |mesh| mesh.bytes())
+				+ self.occupancy.as_ref().map_or(0, |occupancy| occupancy.bytes())
+				+ self.sorted.as_ref().map_or(0, |sorted| sorted.bytes())
+		}
+	}
+
+	/// Run `drift` on the GPU instead (see voxel_drift.glslc).  The shadow copy isn't updated: call
+	/// `items.read_back()` before changing items on the CPU again.
+	pub fn drift_gpu(&mut self, drift_shader: &ShaderProgram, seed: u32, update: Duration) {
+		// Anything changed on the CPU has to be in the buffer first
+		self.refresh_instances();
+		self.items.flush();
+		let results = [
+			drift_shader.set("item_count", &(self.instance_count as u32)),
+			drift_shader.set("seed", &seed),
+			drift_shader.set("change_factor", &(0.5 * update.as_secs_f32()))
+		];
+		for err in results.iter().filter_map(|result| result.as_ref().err()) {
+			eprintln!("{}", err);
+		}
+		unsafe {
+			gl::BindBufferRange(
+				gl::SHADER_STORAGE_BUFFER,
+				ITEMS_BINDING,
+				self.items.buffer_id(),
+				self.items.offset() as isize,
+				(self.items.len() * size_of::<ChunkItem>()) as isize
+			);
+			drift_shader.bind();
+			gl::DispatchCompute((self.instance_count as u32).div_ceil(64).max(1), 1, 1);
+			// The results are read as vertex attributes
+			gl::MemoryBarrier(gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+		}
+	}
+
+	/// What the occupancy texture was last filled from
+	pub fn occupancy_stamp(&self) -> Option<OccupancyStamp> {
+		self.occupancy.as_ref().and_then(|occupancy| occupancy.stamp)
+	}
+	/// Fill the occupancy texture, with the border from the neighbours.  `stamp` says which
+	/// generations of this chunk and its neighbours `grid` was built from.
+	pub fn upload_occupancy(&mut self, grid: &OccupancyGrid, stamp: OccupancyStamp) {
+		let size = self.size;
+		self.occupancy.get_or_insert_with(|| Occupancy::new(size)).upload(grid, stamp);
+	}
+	/// Bind the occupancy texture.  If it wasn't filled from the current voxels (by `VoxelWorld`,
+	/// which knows the neighbours) then it's filled from them alone.
+	fn bind_occupancy(&mut self) {
+		let generation = self.generation;
+		if self.occupancy_stamp().is_none_or(|stamp| stamp[OWN_SLOT] != Some(generation)) {
+			let grid = OccupancyGrid::of_voxels(&self.voxels);
+			self.upload_occupancy(&grid, lone_stamp(generation));
+		}
+		self.occupancy.as_ref().unwrap().bind(OCCUPANCY_UNIT);
+	}
+
+	pub fn draw(&mut self, chunk_shader: &ShaderProgram) {
+		let instance_count = self.instance_count();
+		self.draw_with(chunk_shader, |_| unsafe {
+			gl::DrawArrays(gl::POINTS, 0, instance_count as i32);
+		});
+	}
+	/// Draw only the see-through voxels, from the farthest from `eye` to the nearest, so that they
+	/// can be blended in order.
+	pub fn draw_sorted(&mut self, chunk_shader: &ShaderProgram, eye: Point3<f32>) {
+		self.refresh_instances();
+		let generation = self.generation;
+		let sorted = self.sorted.get_or_insert_with(SortedInstances::new);
+		if !sorted.is_sorted_for(generation, eye) {
+			let (size, origin) = (self.size, Point3::new(self.x, self.y, self.z));
+			let (instances, positions): (Vec<u32>, Vec<Point3<f32>>) = self.items.items()[..self.instance_count]
+				.iter()
+				.enumerate()
+				.filter(|(_, item)| { item.opacity } < 1.0)
+				.map(|(instance, item)| {
+					let (x, y, z) = size.position({ item.index } as usize);
+					(instance as u32, origin + Vector3::new(x as f32, y as f32, z as f32))
+				})
+				.unzip();
+			let order: Vec<u32> = back_to_front(&positions, eye).into_iter().map(|i| instances[i as usize]).collect();
+			sorted.upload(&order, generation, eye);
+		}
+		self.draw_with(chunk_shader, |chunk| chunk.sorted.as_ref().unwrap().draw());
+	}
+	/// Set everything up for drawing the instances, and let `draw` make the draw call.
+	fn draw_with(&mut self, chunk_shader: &ShaderProgram, draw: impl FnOnce(&Chunk)) {
+		self.refresh_instances();
+		self.items.flush();
+		self.bind_occupancy();
+		unsafe {
+			gl::Enable(gl::CULL_FACE);
+
+			// Bind the program
+			chunk_shader.bind();
+			gl::BindVertexArray(self.vao);
+			gl::BindVertexBuffer(0, self.items.buffer_id(), self.items.offset() as isize, size_of::<ChunkItem>() as i32);
+			// Move the chunk in model space + TODO: apply the scale from 0-1 -> 0-CHUNK_SIZE
+			let chunk_transform: Matrix4<f32> = Matrix4::from_translation(
+				Vector3::new(self.x, self.y, self.z)
+			);
+			chunk_shader.set_optional("chunk_transform", &chunk_transform);
+			chunk_shader.set_optional("chunk_size", &self.size.as_uniform());
+		}
+		draw(self);
+		unsafe {
+			gl::BindVertexArray(0);
+		}
+		self.items.fence();
+	}
+	/// Draw the greedy mesh of the voxels, rebuilding it first if they changed.
+	pub fn draw_mesh(&mut self, mesh_shader: &ShaderProgram) {
+		self.bind_occupancy();
+		let generation = self.generation;
+		let mesh = self.mesh.get_or_insert_with(GpuMesh::new);
+		if mesh.generation != Some(generation) {
+			mesh.upload(&greedy_mesh(&self.voxels), generation);
+		}
+		mesh_shader.bind();
+		let chunk_transform = Matrix4::from_translation(Vector3::new(self.x, self.y, self.z));
+		mesh_shader.set_optional("chunk_transform", &chunk_transform);
+		unsafe {
+			gl::Enable(gl::CULL_FACE);
+		}
+		mesh.draw();
+	}
+}
+impl Drop for Chunk {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteVertexArrays(1, &self.vao);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::headless::with_test_context;
+
+	const SIZES: [ChunkSize; 4] = [
+		ChunkSize::DEFAULT,
+		ChunkSize { width: 1, height: 1, depth: 1 },
+		ChunkSize { width: 3, height: 5, depth: 7 },
+		ChunkSize { width: 16, height: 2, depth: 9 }
+	];
+
+	#[test]
+	fn position_inverts_index() {
+		for size in SIZES.iter() {
+			let mut expected = 0;
+			for x in 0..size.width {
+				for y in 0..size.height {
+					for z in 0..size.depth {
+						assert_eq!(size.index(x, y, z), expected, "{:?}", size);
+						assert_eq!(size.position(expected), (x, y, z), "{:?}", size);
+						expected += 1;
+					}
+				}
+			}
+			assert_eq!(expected, size.len());
+		}
+	}
+
+	/// The `voxel_position` function from voxel.glslv
+	fn shader_voxel_position() -> &'static str {
+		let source = include_str!("../shaders/voxel.glslv");
+		let start = source.find("ivec3 voxel_position(").expect("voxel.glslv defines voxel_position");
+		let end = start + source[start..].find("\n}").expect("voxel_position ends") + 2;
+		&source[start..end]
+	}
+
+	#[test]
+	#[ignore = "needs libOSMesa, run with --ignored"]
+	fn shader_positions_match() {
+		with_test_context(|| {
+			if !gl::DispatchCompute::is_loaded() {
+				println!("Skipping: compute shaders aren't available");
+				return;
+			}
+			// Run the vertex shader's function in a compute shader and read back what it returns for
+			// every index
+			let source = format!(
+				"#version 430\n\
+				layout(local_size_x = 64) in;\n\
+				layout(std430, binding = 0) buffer Positions {{ ivec4 positions[]; }};\n\
+				uniform ivec3 chunk_size;\n\
+				uniform int item_count;\n\
+				{}\n\
+				void main() {{\n\
+					int index = int(gl_GlobalInvocationID.x);\n\
+					if (index < item_count) {{\n\
+						positions[index] = ivec4(voxel_position(index, chunk_size), 0);\n\
+					}}\n\
+				}}\n",
+				shader_voxel_position()
+			);
+			let program = ShaderProgram::builder().stage(Stage::Compute, "voxel_position test", &source).build().unwrap();
+			for size in SIZES.iter() {
+				let mut positions = vec![[0i32; 4]; size.len()];
+				unsafe {
+					let mut buffer = 0;
+					gl::GenBuffers(1, &mut buffer);
+					gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, buffer);
+					gl::BufferData(gl::SHADER_STORAGE_BUFFER, (positions.len() * 16) as isize, std::ptr::null(), gl::STREAM_READ);
+					gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, buffer);
+					program.set("chunk_size", &size.as_uniform()).unwrap();
+					program.set("item_count", &(size.len() as i32)).unwrap();
+					program.bind();
+					gl::DispatchCompute((size.len() as u32).div_ceil(64), 1, 1);
+					gl::MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+					gl::GetBufferSubData(gl::SHADER_STORAGE_BUFFER, 0, (positions.len() * 16) as isize, positions.as_mut_ptr() as *mut _);
+					gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+					gl::DeleteBuffers(1, &buffer);
+				}
+				for (index, position) in positions.iter().enumerate() {
+					let (x, y, z) = size.position(index);
+					assert_eq!(position[..3], [x as i32, y as i32, z as i32], "index {} of {:?}", index, size);
+				}
+			}
+		});
+	}
+}
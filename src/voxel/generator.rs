@@ -0,0 +1,62 @@
+use super::{BlockId, ChunkCoord, ChunkSize, Voxel};
+
+use rand::prelude::*;
+
+/// Fills in the chunks of a `VoxelWorld`.  Runs on the loader thread.
+pub trait ChunkGenerator: Send + Sync {
+	/// The voxels of the chunk at `coord`, in `ChunkSize::index` order.  Has to return the same
+	/// voxels every time, since chunks are generated again when they come back into range.
+	fn generate(&self, coord: ChunkCoord, size: ChunkSize) -> Vec<Voxel>;
+}
+
+/// Voxels with random sizes and colors, different for every chunk.  Half of them are see-through.
+pub struct RandomGenerator {
+	pub seed: u64
+}
+impl RandomGenerator {
+	pub fn new(seed: u64) -> RandomGenerator {
+		RandomGenerator { seed }
+	}
+}
+impl ChunkGenerator for RandomGenerator {
+	fn generate(&self, coord: ChunkCoord, size: ChunkSize) -> Vec<Voxel> {
+		let mut rng = StdRng::seed_from_u64(self.seed ^ hash_coord(coord));
+		(0..size.len())
+			.map(|_| {
+				let size = rng.gen_range(0.0, 1.0);
+				let voxel = Voxel::new(BlockId::SOLID, size, (rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0)));
+				if rng.gen_bool(0.5) { voxel.with_opacity(rng.gen_range(0.2, 0.8)) } else { voxel }
+			})
+			.collect()
+	}
+}
+
+/// Mixes the coordinates into a seed (splitmix64's finalizer), so neighbouring chunks look unrelated.
+/// Each coordinate gets 21 bits, so chunks within a million of the origin never collide.
+pub fn hash_coord(coord: ChunkCoord) -> u64 {
+	let bits = |value: i32| value as u32 as u64 & 0x1f_ffff;
+	let mut hash = bits(coord.x) | bits(coord.y) << 21 | bits(coord.z) << 42;
+	hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+	hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+	hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashSet;
+
+	#[test]
+	fn nearby_chunks_hash_differently() {
+		let mut hashes = HashSet::new();
+		for x in -12..12 {
+			for y in -12..12 {
+				for z in -12..12 {
+					assert!(hashes.insert(hash_coord(ChunkCoord::new(x, y, z))), "{} {} {}", x, y, z);
+				}
+			}
+		}
+		// The sign bits of x mustn't cancel out y
+		assert_ne!(hash_coord(ChunkCoord::new(-1, 0, 0)), hash_coord(ChunkCoord::new(-(1 << 21) - 1, 1, 0)));
+	}
+}
@@ -0,0 +1,59 @@
+use super::RayHit;
+use crate::{camera, gl, shader::*, shader_file};
+use gl::types::*;
+
+use cgmath::{Point3, Vector3, Vector4};
+
+/// Outlines a voxel and shades one of its faces, to show what the cursor is pointing at.
+pub struct Highlight {
+	program: ReloadableProgram,
+	/// Has no attributes, but drawing needs one bound
+	vao: GLuint
+}
+impl Highlight {
+	pub fn new() -> Result<Highlight, ShaderError> {
+		let program = ReloadableProgram::new(vec![
+			shader_file!(Stage::Vertex, "voxel_highlight.glslv"),
+			shader_file!(Stage::Fragment, "voxel_highlight.glslf"),
+		])?;
+		if let Err(err) = camera::check_camera_block(&program.program().reflection()) {
+			eprintln!("{}", err);
+		}
+		let mut vao = 0;
+		unsafe {
+			gl::GenVertexArrays(1, &mut vao);
+		}
+		Ok(Highlight { program, vao })
+	}
+	/// Draw `hit`, whose voxel is centered on `center` in the scene.
+	pub fn draw(&self, hit: &RayHit, center: Point3<f32>) {
+		self.program.refresh();
+		let program = self.program.program();
+		program.bind();
+		program.set_optional("voxel_center", &Vector3::new(center.x, center.y, center.z));
+		unsafe {
+			gl::BindVertexArray(self.vao);
+			program.set_optional("face", &Vector3::new(0, 0, 0));
+			program.set_optional("highlight_color", &Vector4::new(0.05, 0.05, 0.05, 1.0));
+			gl::DrawArrays(gl::LINES, 0, 24);
+			// Started inside a voxel: there's no face to show
+			if hit.normal != Vector3::new(0, 0, 0) {
+				program.set_optional("face", &hit.normal);
+				program.set_optional("highlight_color", &Vector4::new(1.0, 1.0, 1.0, 0.35));
+				gl::Enable(gl::BLEND);
+				gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+				gl::Disable(gl::CULL_FACE);
+				gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+				gl::Disable(gl::BLEND);
+			}
+			gl::BindVertexArray(0);
+		}
+	}
+}
+impl Drop for Highlight {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteVertexArrays(1, &self.vao);
+		}
+	}
+}
@@ -0,0 +1,362 @@
+use super::{ChunkSize, VoxelStorage};
+use crate::{gl, shader::*};
+use gl::types::*;
+
+use cgmath::{InnerSpace, Vector3};
+
+/// The texture unit that chunks bind their `Occupancy` to
+pub const OCCUPANCY_UNIT: u32 = 1;
+
+/// The normal of each face of a voxel, and two directions along it with `u × v = normal`.  The same
+/// as the tables in voxel.glslg.
+pub const FACES: [([i32; 3], [i32; 3], [i32; 3]); 6] = [
+	([1, 0, 0], [0, 1, 0], [0, 0, 1]),
+	([-1, 0, 0], [0, 0, 1], [0, 1, 0]),
+	([0, 1, 0], [0, 0, 1], [1, 0, 0]),
+	([0, -1, 0], [1, 0, 0], [0, 0, 1]),
+	([0, 0, 1], [1, 0, 0], [0, 1, 0]),
+	([0, 0, -1], [0, 1, 0], [1, 0, 0])
+];
+
+/// How the voxels are lit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lighting {
+	/// Only the voxel colors
+	Flat = 0,
+	/// A directional sun, and ambient light from the sky above and the ground below
+	Sun = 1,
+	/// `Sun`, with less ambient light in the corners between voxels
+	Occlusion = 2
+}
+impl Lighting {
+	pub fn next(self) -> Lighting {
+		match self {
+			Lighting::Flat => Lighting::Sun,
+			Lighting::Sun => Lighting::Occlusion,
+			Lighting::Occlusion => Lighting::Flat
+		}
+	}
+	/// Set the lighting uniforms of a voxel shader program: the chunk and the greedy mesh ones.
+	pub fn apply(self, program: &ShaderProgram) {
+		program.set_optional("lighting", &(self as i32));
+		program.set_optional("sun_direction", &sun_direction());
+		program.set_optional("occupancy", &(OCCUPANCY_UNIT as i32));
+	}
+}
+
+/// Towards the sun, high up and off to the side so that the faces along x and z differ
+pub fn sun_direction() -> Vector3<f32> {
+	Vector3::new(0.4, 1.0, 0.3).normalize()
+}
+
+/// How much ambient light reaches the corner of a face, from 0 to 1, depending on the cells next
+/// to it in front of the face: the two that share an edge with the corner and the one diagonally
+/// across.  This is the reference for `corner_occlusion` in voxel_occlusion.glsl.
+pub fn vertex_occlusion(side1: bool, side2: bool, diagonal: bool) -> f32 {
+	if side1 && side2 {
+		// The diagonal cell can't make it any darker
+		return 0.0;
+	}
+	(3 - side1 as u8 - side2 as u8 - diagonal as u8) as f32 / 3.0
+}
+/// `vertex_occlusion` for a corner of a face in `grid`.  `air` is the cell in front of the face,
+/// `u` and `v` point along the face and `corner` picks the corner, with -1 or 1 for each.
+pub fn corner_occlusion(grid: &OccupancyGrid, air: [i32; 3], u: [i32; 3], v: [i32; 3], corner: [i32; 2]) -> f32 {
+	let occupied = |cell: [i32; 3]| grid.occupied(cell);
+	let offset = |cell: [i32; 3], direction: [i32; 3], step: i32| {
+		[cell[0] + direction[0] * step, cell[1] + direction[1] * step, cell[2] + direction[2] * step]
+	};
+	vertex_occlusion(
+		occupied(offset(air, u, corner[0])),
+		occupied(offset(air, v, corner[1])),
+		occupied(offset(offset(air, u, corner[0]), v, corner[1]))
+	)
+}
+
+/// The `Chunk::generation` of a chunk and of its 26 neighbours (`None` for the ones that aren't
+/// loaded), x changing fastest.  The chunk itself is in the middle.
+pub type OccupancyStamp = [Option<u64>; 27];
+
+/// Where the chunk's own generation is in its `OccupancyStamp`
+pub const OWN_SLOT: usize = 13;
+
+/// The stamp of a chunk that's lit without its neighbours
+pub fn lone_stamp(generation: u64) -> OccupancyStamp {
+	let mut stamp = [None; 27];
+	stamp[OWN_SLOT] = Some(generation);
+	stamp
+}
+
+/// Which cells of a chunk have a voxel, and of the cells right around it so that the corners at
+/// the edges of the chunk are darkened by its neighbours too.  These are the occupancy texture's
+/// contents: 255 for the cells with a voxel, x changing fastest, starting at (-1, -1, -1).
+pub struct OccupancyGrid {
+	/// Of the chunk, without the border
+	size: ChunkSize,
+	texels: Vec<u8>
+}
+impl OccupancyGrid {
+	/// `neighbours` tells whether a cell of the border (from -1 to the chunk's size along each
+	/// axis) has a voxel.
+	pub fn with_border(voxels: &VoxelStorage, neighbours: impl Fn([i32; 3]) -> bool) -> OccupancyGrid {
+		let size = voxels.size();
+		let (width, height, depth) = padded(size);
+		let mut grid = OccupancyGrid { size, texels: vec![0; width * height * depth] };
+		for (index, _) in voxels.iter_filled() {
+			let (x, y, z) = size.position(index);
+			grid.texels[x + 1 + width * (y + 1 + height * (z + 1))] = 255;
+		}
+		for z in 0..depth {
+			for y in 0..height {
+				for x in 0..width {
+					let border = x == 0 || y == 0 || z == 0 || x == width - 1 || y == height - 1 || z == depth - 1;
+					if border && neighbours([x as i32 - 1, y as i32 - 1, z as i32 - 1]) {
+						grid.texels[x + width * (y + height * z)] = 255;
+					}
+				}
+			}
+		}
+		grid
+	}
+	/// Only the chunk's own voxels: the border is empty.
+	pub fn of_voxels(voxels: &VoxelStorage) -> OccupancyGrid {
+		OccupancyGrid::with_border(voxels, |_| false)
+	}
+	/// Whether `cell` (in the chunk's coordinates) has a voxel.  Further out than the border counts
+	/// as empty.  The same as `occupied` in the voxel shaders.
+	pub fn occupied(&self, cell: [i32; 3]) -> bool {
+		let (width, height, depth) = padded(self.size);
+		let [x, y, z] = [cell[0] + 1, cell[1] + 1, cell[2] + 1];
+		let inside = x >= 0 && y >= 0 && z >= 0 && (x as usize) < width && (y as usize) < height && (z as usize) < depth;
+		inside && self.texels[x as usize + width * (y as usize + height * z as usize)] != 0
+	}
+	pub fn texels(&self) -> &[u8] {
+		&self.texels
+	}
+}
+
+/// The size of the occupancy texture of a chunk: one more cell on each side
+fn padded(size: ChunkSize) -> (usize, usize, usize) {
+	(size.width + 2, size.height + 2, size.depth + 2)
+}
+
+/// Which cells of a chunk and of the border around it have a voxel, as a 3D texture, for the
+/// ambient occlusion
+pub struct Occupancy {
+	texture: GLuint,
+	size: ChunkSize,
+	/// What the texture was filled from
+	pub stamp: Option<OccupancyStamp>
+}
+impl Occupancy {
+	pub fn new(size: ChunkSize) -> Occupancy {
+		let mut texture = 0;
+		let (width, height, depth) = padded(size);
+		unsafe {
+			gl::GenTextures(1, &mut texture);
+			gl::BindTexture(gl::TEXTURE_3D, texture);
+			gl::TexStorage3D(gl::TEXTURE_3D, 1, gl::R8, width as GLsizei, height as GLsizei, depth as GLsizei);
+			gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+			gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+			gl::BindTexture(gl::TEXTURE_3D, 0);
+		}
+		Occupancy { texture, size, stamp: None }
+	}
+	pub fn upload(&mut self, grid: &OccupancyGrid, stamp: OccupancyStamp) {
+		assert_eq!(grid.size, self.size);
+		let (width, height, depth) = padded(self.size);
+		unsafe {
+			gl::BindTexture(gl::TEXTURE_3D, self.texture);
+			gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+			gl::TexSubImage3D(
+				gl::TEXTURE_3D,
+				0,
+				0,
+				0,
+				0,
+				width as GLsizei,
+				height as GLsizei,
+				depth as GLsizei,
+				gl::RED,
+				gl::UNSIGNED_BYTE,
+				grid.texels.as_ptr() as *const _
+			);
+			gl::BindTexture(gl::TEXTURE_3D, 0);
+		}
+		self.stamp = Some(stamp);
+	}
+	pub fn bind(&self, unit: u32) {
+		unsafe {
+			gl::ActiveTexture(gl::TEXTURE0 + unit);
+			gl::BindTexture(gl::TEXTURE_3D, self.texture);
+			gl::ActiveTexture(gl::TEXTURE0);
+		}
+	}
+	pub fn bytes(&self) -> usize {
+		let (width, height, depth) = padded(self.size);
+		width * height * depth
+	}
+}
+impl Drop for Occupancy {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteTextures(1, &self.texture);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{headless::with_test_context, voxel::{BlockId, Voxel}};
+
+	fn stone() -> Voxel {
+		Voxel::solid(BlockId::STONE, (0.5, 0.5, 0.5))
+	}
+
+	#[test]
+	fn corners_get_darker_with_neighbours() {
+		assert_eq!(vertex_occlusion(false, false, false), 1.0);
+		assert_eq!(vertex_occlusion(false, false, true), 2.0 / 3.0);
+		assert_eq!(vertex_occlusion(true, false, true), 1.0 / 3.0);
+		assert_eq!(vertex_occlusion(true, true, false), 0.0);
+		assert_eq!(vertex_occlusion(true, true, true), 0.0);
+
+		// A floor with a wall along x = 0: the top faces next to the wall are darker on that side
+		let mut voxels = VoxelStorage::new(ChunkSize::new(4, 3, 4));
+		for x in 0..4 {
+			for z in 0..4 {
+				voxels.set_at(x, 0, z, stone());
+				voxels.set_at(0, 1, z, stone());
+			}
+		}
+		let grid = OccupancyGrid::of_voxels(&voxels);
+		let (_, u, v) = FACES[2];
+		// Top of (1, 0, 1): u is z and v is x
+		let air = [1, 1, 1];
+		assert_eq!(corner_occlusion(&grid, air, u, v, [1, 1]), 1.0);
+		assert_eq!(corner_occlusion(&grid, air, u, v, [1, -1]), 1.0 / 3.0);
+		assert_eq!(corner_occlusion(&grid, air, u, v, [-1, -1]), 1.0 / 3.0);
+		// Away from the wall, and at the edge of the chunk
+		assert_eq!(corner_occlusion(&grid, [3, 1, 3], u, v, [1, 1]), 1.0);
+	}
+
+	#[test]
+	fn neighbours_darken_the_edges() {
+		// A floor, with the neighbour along +x having a wall right at the border
+		let mut voxels = VoxelStorage::new(ChunkSize::new(4, 3, 4));
+		for x in 0..4 {
+			for z in 0..4 {
+				voxels.set_at(x, 0, z, stone());
+			}
+		}
+		let (_, u, v) = FACES[2];
+		let air = [3, 1, 1];
+		let alone = OccupancyGrid::of_voxels(&voxels);
+		assert_eq!(corner_occlusion(&alone, air, u, v, [1, 1]), 1.0);
+		let walled = OccupancyGrid::with_border(&voxels, |cell| cell[0] == 4 && cell[1] == 1);
+		assert_eq!(corner_occlusion(&walled, air, u, v, [1, 1]), 1.0 / 3.0);
+		assert_eq!(corner_occlusion(&walled, air, u, v, [1, -1]), 1.0);
+		// Only the border is asked about
+		assert!(!walled.occupied([5, 1, 1]));
+		assert!(walled.occupied([4, 1, 0]));
+		assert!(walled.occupied([0, 0, 0]));
+	}
+
+	#[test]
+	fn faces_point_the_right_way() {
+		for &(normal, u, v) in FACES.iter() {
+			let cross = Vector3::from(u).cross(Vector3::from(v));
+			assert_eq!(cross, Vector3::from(normal));
+		}
+	}
+
+	#[test]
+	fn texels_are_x_first() {
+		let size = ChunkSize::new(3, 2, 4);
+		let mut voxels = VoxelStorage::new(size);
+		voxels.set_at(2, 1, 0, stone());
+		voxels.set_at(0, 0, 3, stone());
+		let grid = OccupancyGrid::of_voxels(&voxels);
+		let texels = grid.texels();
+		assert_eq!(texels.len(), 5 * 4 * 6);
+		assert_eq!(texels.iter().filter(|&&texel| texel == 255).count(), 2);
+		// With the border, (0, 0, 0) is at (1, 1, 1)
+		assert_eq!(texels[3 + 5 * (2 + 4)], 255);
+		assert_eq!(texels[1 + 5 * (1 + 4 * 4)], 255);
+	}
+
+	#[test]
+	#[ignore = "needs libOSMesa, run with --ignored"]
+	fn shader_occlusion_matches() {
+		with_test_context(|| {
+			if !gl::DispatchCompute::is_loaded() {
+				println!("Skipping: compute shaders aren't available");
+				return;
+			}
+			let size = ChunkSize::new(5, 4, 3);
+			let mut voxels = VoxelStorage::new(size);
+			for index in 0..size.len() {
+				if index % 3 == 0 || index % 7 == 1 {
+					let (x, y, z) = size.position(index);
+					voxels.set_at(x, y, z, stone());
+				}
+			}
+			// Every corner of every face of every cell, one invocation each
+			let count = size.len() * 6 * 4;
+			let source = format!(
+				"#version 430\n\
+				layout(local_size_x = 64) in;\n\
+				layout(std430, binding = 0) buffer Results {{ float results[]; }};\n\
+				uniform ivec3 chunk_size;\n\
+				uniform int count;\n\
+				const ivec3 NORMALS[6] = ivec3[](ivec3(1, 0, 0), ivec3(-1, 0, 0), ivec3(0, 1, 0), ivec3(0, -1, 0), ivec3(0, 0, 1), ivec3(0, 0, -1));\n\
+				const ivec3 US[6] = ivec3[](ivec3(0, 1, 0), ivec3(0, 0, 1), ivec3(0, 0, 1), ivec3(1, 0, 0), ivec3(1, 0, 0), ivec3(0, 1, 0));\n\
+				const ivec3 VS[6] = ivec3[](ivec3(0, 0, 1), ivec3(0, 1, 0), ivec3(1, 0, 0), ivec3(0, 0, 1), ivec3(0, 1, 0), ivec3(1, 0, 0));\n\
+				{}\n\
+				void main() {{\n\
+					int i = int(gl_GlobalInvocationID.x);\n\
+					if (i < count) {{\n\
+						int cell = i / 24, face = i / 4 % 6, corner = i % 4;\n\
+						ivec3 voxel = ivec3(cell % chunk_size.x, cell / chunk_size.x % chunk_size.y, cell / (chunk_size.x * chunk_size.y));\n\
+						ivec2 signs = ivec2(corner % 2 * 2 - 1, corner / 2 * 2 - 1);\n\
+						results[i] = corner_occlusion(voxel + NORMALS[face], US[face], VS[face], signs);\n\
+					}}\n\
+				}}\n",
+				include_str!("../shaders/voxel_occlusion.glsl")
+			);
+			let program = ShaderProgram::builder().stage(Stage::Compute, "corner_occlusion test", &source).build().unwrap();
+			// A checkerboard around the chunk, to check the border too
+			let grid = OccupancyGrid::with_border(&voxels, |cell| (cell[0] + cell[1] + cell[2]) % 2 == 0);
+			let mut occupancy = Occupancy::new(size);
+			occupancy.upload(&grid, lone_stamp(0));
+			let mut results = vec![0f32; count];
+			unsafe {
+				let mut buffer = 0;
+				gl::GenBuffers(1, &mut buffer);
+				gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, buffer);
+				gl::BufferData(gl::SHADER_STORAGE_BUFFER, (count * 4) as isize, std::ptr::null(), gl::STREAM_READ);
+				gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, buffer);
+				occupancy.bind(OCCUPANCY_UNIT);
+				program.set("occupancy", &(OCCUPANCY_UNIT as i32)).unwrap();
+				program.set("chunk_size", &size.as_uniform()).unwrap();
+				program.set("count", &(count as i32)).unwrap();
+				program.bind();
+				gl::DispatchCompute((count as u32).div_ceil(64), 1, 1);
+				gl::MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+				gl::GetBufferSubData(gl::SHADER_STORAGE_BUFFER, 0, (count * 4) as isize, results.as_mut_ptr() as *mut _);
+				gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+				gl::DeleteBuffers(1, &buffer);
+			}
+			for (i, result) in results.iter().enumerate() {
+				let (cell, face, corner) = (i / 24, i / 4 % 6, i % 4);
+				let (x, y, z) = (cell % size.width, cell / size.width % size.height, cell / (size.width * size.height));
+				let (normal, u, v) = FACES[face];
+				let air = [x as i32 + normal[0], y as i32 + normal[1], z as i32 + normal[2]];
+				let signs = [corner as i32 % 2 * 2 - 1, corner as i32 / 2 * 2 - 1];
+				let expected = corner_occlusion(&grid, air, u, v, signs);
+				assert!((result - expected).abs() < 1e-5, "cell {:?}, face {}, corner {:?}: {} instead of {}", (x, y, z), face, signs, result, expected);
+			}
+		});
+	}
+}
@@ -0,0 +1,352 @@
+use super::{Voxel, VoxelStorage};
+use crate::{camera, gl, shader::*, shader_file};
+use gl::types::*;
+
+use std::{mem::size_of, ptr};
+
+/// How a `Chunk` is drawn
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+	/// Every voxel is a point that voxel.glslg turns into a cube
+	GeometryShader,
+	/// Only the visible faces, merged on the CPU by `greedy_mesh`
+	Greedy
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct MeshVertex {
+	pub position: [f32; 3],
+	pub normal: [f32; 3],
+	pub color: [f32; 3]
+}
+
+/// An indexed triangle list
+#[derive(Clone, Debug, Default)]
+pub struct VoxelMesh {
+	pub vertices: Vec<MeshVertex>,
+	pub indices: Vec<u32>
+}
+impl VoxelMesh {
+	pub fn triangle_count(&self) -> usize {
+		self.indices.len() / 3
+	}
+	/// Two triangles from four corners, counterclockwise when seen from the front
+	fn quad(&mut self, corners: [[f32; 3]; 4], normal: [f32; 3], color: [f32; 3]) {
+		let first = self.vertices.len() as u32;
+		for &position in corners.iter() {
+			self.vertices.push(MeshVertex { position, normal, color });
+		}
+		self.indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+	}
+}
+
+/// Build the visible faces of the opaque voxels of a chunk.  Faces between two opaque voxels are
+/// left out, and neighbouring faces that point the same way and have the same color are merged into
+/// one rectangle.  The see-through voxels aren't part of the mesh, and don't hide the faces behind
+/// them: they're drawn like in `RenderMode::GeometryShader`, so that they can be blended.
+///
+/// Voxels that aren't empty always fill their whole cell (the size doesn't scale them like it does
+/// in voxel.glslg).  The voxel at `x, y, z` spans `x - 0.5` to `x + 0.5` and so
+/// on, the same as in the geometry shader.  Faces on the chunk's border are always kept.
+pub fn greedy_mesh(voxels: &VoxelStorage) -> VoxelMesh {
+	let size = voxels.size();
+	let dims = [size.width, size.height, size.depth];
+	// Unpacked once, since every cell is looked at several times
+	let voxels: Vec<Voxel> = voxels.to_vec();
+	let opaque = |p: [usize; 3]| voxels[size.index(p[0], p[1], p[2])].is_opaque();
+	// Colors are compared exactly
+	let color_key = |p: [usize; 3]| {
+		let color = voxels[size.index(p[0], p[1], p[2])].color;
+		[color.0.to_bits(), color.1.to_bits(), color.2.to_bits()]
+	};
+
+	let mut mesh = VoxelMesh::default();
+	for axis in 0..3 {
+		// The two axes of the slice, chosen so that u x v points along +axis
+		let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+		let mut mask: Vec<Option<[u32; 3]>> = vec![None; dims[u] * dims[v]];
+		for &positive in [false, true].iter() {
+			for slice in 0..dims[axis] {
+				// Which faces of this slice are visible (and their color)
+				for j in 0..dims[v] {
+					for i in 0..dims[u] {
+						let mut p = [0; 3];
+						p[axis] = slice;
+						p[u] = i;
+						p[v] = j;
+						let neighbour = if positive {
+							slice + 1 < dims[axis]
+						} else {
+							slice > 0
+						};
+						let covered = neighbour && {
+							let mut q = p;
+							q[axis] = if positive { slice + 1 } else { slice - 1 };
+							opaque(q)
+						};
+						mask[i + j * dims[u]] = if opaque(p) && !covered { Some(color_key(p)) } else { None };
+					}
+				}
+
+				// Grow rectangles out of the mask: first along u, then along v as long as the whole
+				// row matches
+				for j in 0..dims[v] {
+					let mut i = 0;
+					while i < dims[u] {
+						let key = match mask[i + j * dims[u]] {
+							Some(key) => key,
+							None => {
+								i += 1;
+								continue;
+							}
+						};
+						let mut width = 1;
+						while i + width < dims[u] && mask[i + width + j * dims[u]] == Some(key) {
+							width += 1;
+						}
+						let mut height = 1;
+						while j + height < dims[v] && (i..i + width).all(|k| mask[k + (j + height) * dims[u]] == Some(key)) {
+							height += 1;
+						}
+						for row in j..j + height {
+							for cell in mask[i + row * dims[u]..i + width + row * dims[u]].iter_mut() {
+								*cell = None;
+							}
+						}
+
+						let plane = slice as f32 + if positive { 0.5 } else { -0.5 };
+						let corner = |du: usize, dv: usize| {
+							let mut position = [0.0; 3];
+							position[axis] = plane;
+							position[u] = (i + du) as f32 - 0.5;
+							position[v] = (j + dv) as f32 - 0.5;
+							position
+						};
+						let mut corners = [corner(0, 0), corner(width, 0), corner(width, height), corner(0, height)];
+						if !positive {
+							corners.reverse();
+						}
+						let mut normal = [0.0; 3];
+						normal[axis] = if positive { 1.0 } else { -1.0 };
+						let color = [f32::from_bits(key[0]), f32::from_bits(key[1]), f32::from_bits(key[2])];
+						mesh.quad(corners, normal, color);
+						i += width;
+					}
+				}
+			}
+		}
+	}
+	mesh
+}
+
+/// The attribute locations in voxel_mesh.glslv
+const POSITION_LOCATION: GLuint = 0;
+const NORMAL_LOCATION: GLuint = 1;
+const COLOR_LOCATION: GLuint = 2;
+
+pub fn build_mesh_shader() -> Result<ReloadableProgram, ShaderError> {
+	let program = ReloadableProgram::new(vec![
+		shader_file!(Stage::Vertex, "voxel_mesh.glslv"),
+		shader_file!(Stage::Fragment, "voxel_mesh.glslf"),
+	])?;
+	if let Err(err) = camera::check_camera_block(&program.program().reflection()) {
+		eprintln!("{}", err);
+	}
+	Ok(program)
+}
+
+/// A `VoxelMesh` on the GPU.  The buffers are reused when the mesh is rebuilt.
+pub struct GpuMesh {
+	vao: GLuint,
+	vertex_buffer: GLuint,
+	index_buffer: GLuint,
+	vertex_count: usize,
+	index_count: usize,
+	/// The `Chunk::generation` the mesh was built from
+	pub generation: Option<u64>
+}
+impl GpuMesh {
+	pub fn new() -> GpuMesh {
+		let mut mesh = GpuMesh {
+			vao: 0,
+			vertex_buffer: 0,
+			index_buffer: 0,
+			vertex_count: 0,
+			index_count: 0,
+			generation: None
+		};
+		unsafe {
+			gl::GenVertexArrays(1, &mut mesh.vao);
+			gl::GenBuffers(1, &mut mesh.vertex_buffer);
+			gl::GenBuffers(1, &mut mesh.index_buffer);
+
+			gl::BindVertexArray(mesh.vao);
+			gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vertex_buffer);
+			// The element array binding is part of the VAO
+			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, mesh.index_buffer);
+			let stride = size_of::<MeshVertex>() as GLsizei;
+			for &(location, offset) in [(POSITION_LOCATION, 0), (NORMAL_LOCATION, 12), (COLOR_LOCATION, 24)].iter() {
+				gl::VertexAttribPointer(location, 3, gl::FLOAT, gl::FALSE, stride, offset as *const _);
+				gl::EnableVertexAttribArray(location);
+			}
+			gl::BindVertexArray(0);
+			gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+		}
+		mesh
+	}
+	pub fn upload(&mut self, mesh: &VoxelMesh, generation: u64) {
+		unsafe {
+			gl::BindBuffer(gl::ARRAY_BUFFER, self.vertex_buffer);
+			gl::BufferData(
+				gl::ARRAY_BUFFER,
+				(mesh.vertices.len() * size_of::<MeshVertex>()) as GLsizeiptr,
+				mesh.vertices.as_ptr() as *const _,
+				gl::DYNAMIC_DRAW
+			);
+			gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+			gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.index_buffer);
+			gl::BufferData(
+				gl::COPY_WRITE_BUFFER,
+				(mesh.indices.len() * size_of::<u32>()) as GLsizeiptr,
+				mesh.indices.as_ptr() as *const _,
+				gl::DYNAMIC_DRAW
+			);
+			gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+		}
+		self.vertex_count = mesh.vertices.len();
+		self.index_count = mesh.indices.len();
+		self.generation = Some(generation);
+	}
+	/// The size of the buffers
+	pub fn bytes(&self) -> usize {
+		self.vertex_count * size_of::<MeshVertex>() + self.index_count * size_of::<u32>()
+	}
+	pub fn draw(&self) {
+		unsafe {
+			gl::BindVertexArray(self.vao);
+			gl::DrawElements(gl::TRIANGLES, self.index_count as GLsizei, gl::UNSIGNED_INT, ptr::null());
+			gl::BindVertexArray(0);
+		}
+	}
+}
+impl Drop for GpuMesh {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteBuffers(1, &self.vertex_buffer);
+			gl::DeleteBuffers(1, &self.index_buffer);
+			gl::DeleteVertexArrays(1, &self.vao);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::voxel::{BlockId, ChunkSize};
+
+	const RED: (f32, f32, f32) = (1.0, 0.0, 0.0);
+	const BLUE: (f32, f32, f32) = (0.0, 0.0, 1.0);
+
+	/// A chunk with the voxels that `voxel` returns a color for
+	fn chunk(size: ChunkSize, voxel: impl Fn(usize, usize, usize) -> Option<(f32, f32, f32)>) -> VoxelStorage {
+		let voxels: Vec<Voxel> = (0..size.len())
+			.map(|index| {
+				let (x, y, z) = size.position(index);
+				match voxel(x, y, z) {
+					Some(color) => Voxel::solid(BlockId::SOLID, color),
+					None => Voxel::EMPTY
+				}
+			})
+			.collect();
+		VoxelStorage::from_voxels(size, &voxels)
+	}
+	fn triangles(size: ChunkSize, voxel: impl Fn(usize, usize, usize) -> Option<(f32, f32, f32)>) -> usize {
+		let mesh = greedy_mesh(&chunk(size, voxel));
+		check_winding(&mesh);
+		mesh.triangle_count()
+	}
+	/// Every triangle faces the way its normal points
+	fn check_winding(mesh: &VoxelMesh) {
+		for triangle in mesh.indices.chunks(3) {
+			let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[triangle[i] as usize]);
+			let edge1 = [0, 1, 2].map(|i| b.position[i] - a.position[i]);
+			let edge2 = [0, 1, 2].map(|i| c.position[i] - a.position[i]);
+			let cross = [
+				edge1[1] * edge2[2] - edge1[2] * edge2[1],
+				edge1[2] * edge2[0] - edge1[0] * edge2[2],
+				edge1[0] * edge2[1] - edge1[1] * edge2[0]
+			];
+			let dot: f32 = (0..3).map(|i| cross[i] * a.normal[i]).sum();
+			assert!(dot > 0.0, "triangle {:?} faces away from its normal {:?}", triangle, a.normal);
+		}
+	}
+
+	#[test]
+	fn empty_chunk_has_no_triangles() {
+		assert_eq!(triangles(ChunkSize::new(4, 4, 4), |_, _, _| None), 0);
+	}
+
+	#[test]
+	fn single_voxel_is_a_cube() {
+		let size = ChunkSize::new(3, 3, 3);
+		let mesh = greedy_mesh(&chunk(size, |x, y, z| if (x, y, z) == (1, 1, 1) { Some(RED) } else { None }));
+		assert_eq!(mesh.triangle_count(), 12);
+		assert_eq!(mesh.vertices.len(), 24);
+		for vertex in mesh.vertices.iter() {
+			assert!(vertex.position.iter().all(|&coordinate| coordinate == 0.5 || coordinate == 1.5));
+		}
+		check_winding(&mesh);
+	}
+
+	#[test]
+	fn solid_block_merges_into_six_faces() {
+		assert_eq!(triangles(ChunkSize::new(5, 7, 3), |_, _, _| Some(RED)), 12);
+		// A flat layer is still a box
+		assert_eq!(triangles(ChunkSize::new(4, 3, 4), |_, y, _| if y == 1 { Some(BLUE) } else { None }), 12);
+		assert_eq!(triangles(ChunkSize::new(6, 1, 1), |_, _, _| Some(RED)), 12);
+	}
+
+	#[test]
+	fn hidden_faces_are_culled() {
+		let size = ChunkSize::new(2, 1, 1);
+		// Same color: one box
+		assert_eq!(triangles(size, |_, _, _| Some(RED)), 12);
+		// Different colors: the shared face is gone but nothing merges, so 5 faces each
+		assert_eq!(triangles(size, |x, _, _| Some(if x == 0 { RED } else { BLUE })), 20);
+	}
+
+	#[test]
+	fn see_through_voxels_are_left_out() {
+		let size = ChunkSize::new(3, 1, 1);
+		let mut voxels = chunk(size, |x, _, _| if x == 0 { Some(RED) } else { None });
+		voxels.set_at(1, 0, 0, Voxel::solid(BlockId::SOLID, BLUE).with_opacity(0.5));
+		// The red voxel keeps the face behind the glass, and the glass has none
+		let mesh = greedy_mesh(&voxels);
+		assert_eq!(mesh.triangle_count(), 12);
+		assert!(mesh.vertices.iter().all(|vertex| vertex.color == [1.0, 0.0, 0.0]));
+	}
+
+	#[test]
+	fn diagonal_voxels_share_no_faces() {
+		// A 2x2x2 checkerboard: 4 voxels that only touch at edges
+		let checkerboard = |x: usize, y: usize, z: usize| if (x + y + z).is_multiple_of(2) { Some(RED) } else { None };
+		assert_eq!(triangles(ChunkSize::new(2, 2, 2), checkerboard), 4 * 12);
+	}
+
+	#[test]
+	fn striped_colors_only_merge_along_stripes() {
+		// A 4x4x1 slab with a different color in every column of x: the +z and -z faces become 4 strips
+		// each, the x sides 1 each (-x is all red, +x all blue) and the y sides 4 each
+		let colors = [RED, BLUE, RED, BLUE];
+		assert_eq!(triangles(ChunkSize::new(4, 4, 1), |x, _, _| Some(colors[x])), (4 + 4 + 1 + 1 + 4 + 4) * 2);
+	}
+
+	#[test]
+	fn hollow_box_has_inside_faces() {
+		// A 3x3x3 cube with the middle missing: 6 outside faces and the 6 faces of the hole
+		let hollow = |x: usize, y: usize, z: usize| if (x, y, z) == (1, 1, 1) { None } else { Some(RED) };
+		assert_eq!(triangles(ChunkSize::new(3, 3, 3), hollow), 24);
+	}
+}
@@ -0,0 +1,588 @@
+use crate::{
+	demo::{EventResponse, GlContext, SceneEvent, SceneItem, View},
+	gl,
+	input::{ActionMap, Binding, Input},
+	shader::*,
+	shader_file,
+};
+
+use cgmath::{Point3, Vector3};
+use glutin::{
+	dpi::PhysicalPosition,
+	event::{ElementState, MouseButton, VirtualKeyCode},
+};
+use rand::prelude::*;
+
+use std::{
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::Duration,
+};
+
+pub mod bench;
+mod buffer;
+pub use buffer::ShadowBuffer;
+mod bytes;
+mod chunk;
+pub use chunk::{Chunk, ChunkCoord, ChunkItem, ChunkSize};
+mod storage;
+pub use storage::{BlockId, ChunkMemory, Voxel, VoxelStorage};
+mod mesher;
+pub use mesher::{build_mesh_shader, greedy_mesh, GpuMesh, MeshVertex, RenderMode, VoxelMesh};
+mod generator;
+pub use generator::{ChunkGenerator, RandomGenerator};
+mod picking;
+pub use picking::{cast_ray, RayHit};
+mod highlight;
+pub use highlight::Highlight;
+mod lighting;
+pub use lighting::{lone_stamp, Lighting, Occupancy, OccupancyGrid, OccupancyStamp, OCCUPANCY_UNIT, OWN_SLOT};
+pub mod transparency;
+pub use transparency::Transparency;
+use transparency::{back_to_front, SortedInstances, VoxelPass, WeightedBlended};
+pub mod noise;
+pub mod terrain;
+pub use terrain::{Biome, TerrainGenerator};
+mod world;
+pub use world::{BufferPool, VoxelWorld};
+pub mod vox;
+use vox::{ModelGenerator, VoxFile};
+pub mod save;
+
+/// Build the compute shader version of `drift`.
+fn build_drift_shader() -> Result<ReloadableProgram, ShaderError> {
+	ReloadableProgram::new(vec![shader_file!(Stage::Compute, "voxel_drift.glslc")])
+}
+
+/// One step of the random walk that every item's size and color go through (which cell it's in and
+/// its opacity stay the same).  This is the reference for voxel_drift.glslc.
+pub fn drift(items: &mut [ChunkItem], rng: &mut impl Rng, update: Duration) {
+	let change_factor = 0.5 * update.as_secs_f32(); // 2.0 max change per second
+	for item in items.iter_mut() {
+		item.color.0 = (item.color.0 + rng.gen_range(-change_factor, change_factor)).clamp(0.0, 1.0);
+		item.color.1 = (item.color.1 + rng.gen_range(-change_factor, change_factor)).clamp(0.0, 1.0);
+		item.color.2 = (item.color.2 + rng.gen_range(-change_factor, change_factor)).clamp(0.0, 1.0);
+		item.size = (item.size + rng.gen_range(-change_factor, change_factor)).clamp(0.0, 1.0);
+	}
+}
+
+/// Where `voxel.export` saves the loaded chunks
+const EXPORT_FILE: &str = "./export.vox";
+/// Where `voxel.save` writes the chunks, in a directory per project
+const SAVE_DIRECTORY: &str = "./saves";
+/// How far away voxels can be picked with the mouse
+const PICK_DISTANCE: f32 = 200.0;
+/// What `voxel.brush` cycles through
+const BRUSH_COLORS: [(f32, f32, f32); 6] = [
+	(0.9, 0.2, 0.2),
+	(0.95, 0.75, 0.2),
+	(0.3, 0.75, 0.3),
+	(0.2, 0.5, 0.9),
+	(0.95, 0.95, 0.95),
+	(0.15, 0.15, 0.15)
+];
+
+/// Chunks around the camera, either randomly animated or generated terrain.  Everything GL related
+/// is created in `init`.
+///
+/// The voxel under the cursor is highlighted.  While `voxel.edit` (Alt) is held, left click removes
+/// it, right click places a voxel on the highlighted face and middle click paints it with the brush
+/// color.
+/// `voxel.save` keeps the edits, and `voxel.load` brings them back (in a later run too, given the
+/// same seed).
+///
+/// See-through voxels are blended with weighted blended order-independent transparency, or sorted
+/// back to front where that isn't available (`voxel.transparency` switches between the two).  The
+/// greedy meshes only have the opaque voxels, so the see-through ones are drawn the same way in
+/// both render modes.
+pub struct VoxelProject {
+	chunk_shader: Option<ReloadableProgram>,
+	mesh_shader: Option<ReloadableProgram>,
+	render_mode: RenderMode,
+	lighting: Lighting,
+	transparency: Transparency,
+	/// The targets for `Transparency::WeightedBlended`
+	weighted_blended: Option<WeightedBlended>,
+	/// Only there if compute shaders are supported
+	drift_shader: Option<ReloadableProgram>,
+	/// Let the sizes and colors drift around randomly
+	animate: bool,
+	/// Animate with `drift_shader` instead of on the CPU
+	gpu_drift: bool,
+	world: VoxelWorld,
+	/// Seeded so that a run can be reproduced (see the golden image tests)
+	rng: StdRng,
+	highlight: Option<Highlight>,
+	/// The last view, for picking
+	view: Option<View>,
+	cursor: Option<PhysicalPosition>,
+	/// The voxel under the cursor
+	hover: Option<RayHit>,
+	/// Which of `BRUSH_COLORS` new voxels get
+	brush: usize,
+	/// Where `voxel.save` and `voxel.load` keep the chunks
+	save_directory: PathBuf
+}
+impl VoxelProject {
+	/// Random voxels that drift around
+	pub fn new(seed: u64) -> VoxelProject {
+		let mut project = VoxelProject::with_generator(seed, Arc::new(RandomGenerator::new(seed)), true);
+		project.save_directory = Path::new(SAVE_DIRECTORY).join(format!("random-{}", seed));
+		project
+	}
+	/// A landscape from `TerrainGenerator`, standing still
+	pub fn terrain(seed: u64) -> VoxelProject {
+		let mut project = VoxelProject::with_generator(seed, Arc::new(TerrainGenerator::new(seed)), false);
+		project.world.radius = 2;
+		project.save_directory = Path::new(SAVE_DIRECTORY).join(format!("terrain-{}", seed));
+		project
+	}
+	/// The models of a .vox file, standing still and centered on the origin.  `name` picks the save
+	/// directory.
+	pub fn model(name: &str, file: &VoxFile) -> VoxelProject {
+		let size = ChunkSize::DEFAULT;
+		let voxels = file.to_voxels();
+		let extent = file.models.iter().fold(Vector3::new(-1, 0, 0), |extent, model| {
+			let model = model.grid_size();
+			Vector3::new(extent.x + model.x + 1, extent.y.max(model.y), extent.z.max(model.z))
+		});
+		// The middle of chunk (0, 0, 0) is the origin
+		let offset = Vector3::new(size.width as i32, size.height as i32, size.depth as i32) / 2 - extent / 2;
+		let generator = ModelGenerator::new(voxels, offset, size);
+		let mut project = VoxelProject::with_generator(0, Arc::new(generator), false);
+		// Load the whole model (when looking at it from the origin)
+		let chunks = |extent: i32, size: usize| (extent / 2) / size as i32 + 1;
+		project.world.radius = chunks(extent.x, size.width).max(chunks(extent.z, size.depth));
+		project.world.vertical_radius = chunks(extent.y, size.height);
+		project.save_directory = Path::new(SAVE_DIRECTORY).join(format!("model-{}", name));
+		project
+	}
+	pub fn with_generator(seed: u64, generator: Arc<dyn ChunkGenerator>, animate: bool) -> VoxelProject {
+		// Chunk (0, 0, 0) is centered on the origin
+		let size = ChunkSize::DEFAULT;
+		let origin = Point3::new(-(size.width as f32 / 2.0), -(size.height as f32 / 2.0), -(size.depth as f32 / 2.0));
+		VoxelProject {
+			chunk_shader: None,
+			mesh_shader: None,
+			render_mode: RenderMode::GeometryShader,
+			lighting: Lighting::Occlusion,
+			transparency: Transparency::WeightedBlended,
+			weighted_blended: None,
+			drift_shader: None,
+			animate,
+			gpu_drift: false,
+			world: VoxelWorld::new(size, origin, generator),
+			rng: StdRng::seed_from_u64(seed),
+			highlight: None,
+			view: None,
+			cursor: None,
+			hover: None,
+			brush: 0,
+			save_directory: Path::new(SAVE_DIRECTORY).join(format!("voxels-{}", seed))
+		}
+	}
+	fn toggle_gpu_drift(&mut self) {
+		if self.drift_shader.is_none() {
+			println!("Compute shaders aren't available, so the voxels are animated on the CPU.");
+			return;
+		}
+		if !self.gpu_drift && self.render_mode == RenderMode::Greedy {
+			println!("The greedy meshes are built on the CPU, so they can't be animated on the GPU.");
+			return;
+		}
+		self.gpu_drift = !self.gpu_drift;
+		if !self.gpu_drift {
+			// Pick up where the GPU left off
+			for (_, chunk) in self.world.chunks_mut() {
+				chunk.items.read_back();
+			}
+		}
+		println!("Voxel animation: {}", if self.gpu_drift { "GPU" } else { "CPU" });
+	}
+	fn toggle_render_mode(&mut self) {
+		if self.mesh_shader.is_none() {
+			return;
+		}
+		self.render_mode = match self.render_mode {
+			RenderMode::GeometryShader => RenderMode::Greedy,
+			RenderMode::Greedy => RenderMode::GeometryShader
+		};
+		if self.render_mode == RenderMode::Greedy && self.gpu_drift {
+			self.toggle_gpu_drift();
+		}
+		println!("Voxel rendering: {:?}", self.render_mode);
+	}
+	pub fn set_lighting(&mut self, lighting: Lighting) {
+		self.lighting = lighting;
+	}
+	pub fn set_transparency(&mut self, transparency: Transparency) {
+		self.transparency = transparency;
+	}
+	fn next_lighting(&mut self) {
+		self.lighting = self.lighting.next();
+		println!("Voxel lighting: {:?}", self.lighting);
+	}
+	fn next_transparency(&mut self) {
+		self.transparency = self.transparency.next(self.weighted_blended.is_some());
+		println!("Voxel transparency: {:?}", self.transparency);
+	}
+	/// The voxel at `cursor` in the last view
+	fn pick(&self, cursor: PhysicalPosition) -> Option<RayHit> {
+		let (origin, direction) = self.view?.ray(cursor);
+		self.world.pick(origin, direction, PICK_DISTANCE)
+	}
+	/// Remove, place or paint the voxel at `cursor`.
+	fn edit(&mut self, button: MouseButton, cursor: PhysicalPosition) {
+		let hit = match self.pick(cursor) {
+			Some(hit) => hit,
+			None => return
+		};
+		let brush = BRUSH_COLORS[self.brush];
+		match button {
+			MouseButton::Left => {
+				self.world.set_voxel(hit.voxel, Voxel::EMPTY);
+			}
+			// Not when the camera is inside the voxel, since there's no face to put it on
+			MouseButton::Right if hit.normal != Vector3::new(0, 0, 0) && self.world.voxel(hit.adjacent()).is_some_and(|voxel| voxel.is_empty()) => {
+				self.world.set_voxel(hit.adjacent(), Voxel::solid(BlockId::SOLID, brush));
+			}
+			MouseButton::Middle => {
+				if let Some(voxel) = self.world.voxel(hit.voxel) {
+					self.world.set_voxel(hit.voxel, Voxel { color: brush, ..voxel });
+				}
+			}
+			_ => {}
+		}
+		self.hover = self.pick(cursor);
+	}
+	fn next_brush(&mut self) {
+		self.brush = (self.brush + 1) % BRUSH_COLORS.len();
+		println!("Voxel brush: {:?}", BRUSH_COLORS[self.brush]);
+	}
+	/// Save the voxels of all the loaded chunks as a single model
+	fn export(&self) {
+		let voxels: Vec<_> = self
+			.world
+			.chunks()
+			.flat_map(|(coord, chunk)| {
+				let start = Vector3::new(coord.x * chunk.size.width as i32, coord.y * chunk.size.height as i32, coord.z * chunk.size.depth as i32);
+				let size = chunk.size;
+				chunk.voxels().iter_filled().map(move |(index, voxel)| {
+					let (x, y, z) = size.position(index);
+					(Point3::new(x as i32, y as i32, z as i32) + start, voxel)
+				})
+			})
+			.collect();
+		let result = VoxFile::from_voxels(&voxels).and_then(|file| file.save(Path::new(EXPORT_FILE)));
+		match result {
+			Ok(()) => println!("Saved {} voxels to {}", voxels.len(), EXPORT_FILE),
+			Err(err) => eprintln!("Couldn't save {}: {}", EXPORT_FILE, err)
+		}
+	}
+	/// Save the voxels of the loaded chunks (not the animation)
+	fn save(&self) {
+		match self.world.save(&self.save_directory) {
+			Ok(chunks) => println!("Saved {} chunks to {}", chunks, self.save_directory.display()),
+			Err(err) => eprintln!("Couldn't save to {}: {}", self.save_directory.display(), err)
+		}
+	}
+	/// Load the chunks again, the saved ones from the save directory
+	fn load(&mut self) {
+		if !self.save_directory.is_dir() {
+			println!("Nothing was saved to {} yet", self.save_directory.display());
+			return;
+		}
+		self.world.load_saves(self.save_directory.clone());
+		self.hover = None;
+		if let Some(view) = self.view {
+			self.world.set_center(view.eye);
+		}
+		println!("Loading the chunks saved in {}", self.save_directory.display());
+	}
+	fn print_memory(&self) {
+		let (chunks, total) = self.world.memory();
+		for (coord, memory) in chunks {
+			println!("Chunk {:?}: {}", coord, memory);
+		}
+		println!("All {} chunks: {}", self.world.len(), total);
+	}
+}
+impl SceneItem for VoxelProject {
+	fn init(&mut self, _gl: &GlContext) {
+		let chunk_shader = Chunk::build_chunk_shader().unwrap_or_else(|err| panic!("{}", err));
+		// Load the chunks around the origin right away, so the first frame isn't empty (and the golden
+		// image is the same every time)
+		self.world.set_center(Point3::new(0.0, 0.0, 0.0));
+		self.world.finish_loading(&chunk_shader.program());
+		self.chunk_shader = Some(chunk_shader);
+		match build_mesh_shader() {
+			Ok(mesh_shader) => self.mesh_shader = Some(mesh_shader),
+			Err(err) => eprintln!("{}", err)
+		}
+		match Highlight::new() {
+			Ok(highlight) => self.highlight = Some(highlight),
+			Err(err) => eprintln!("{}", err)
+		}
+		match WeightedBlended::new() {
+			Ok(weighted_blended) => self.weighted_blended = Some(weighted_blended),
+			Err(err) => eprintln!("{}", err)
+		}
+		if self.weighted_blended.is_none() && self.transparency == Transparency::WeightedBlended {
+			self.transparency = Transparency::Sorted;
+		}
+
+		if gl::DispatchCompute::is_loaded() {
+			match build_drift_shader() {
+				Ok(drift_shader) => self.drift_shader = Some(drift_shader),
+				Err(err) => eprintln!("{}", err)
+			}
+		}
+	}
+	fn anim(&mut self, update: Duration) {
+		if !self.animate {
+			return;
+		}
+		// Provide updates
+		match &self.drift_shader {
+			Some(drift_shader) if self.gpu_drift => {
+				drift_shader.refresh();
+				let drift_shader = drift_shader.program();
+				for (_, chunk) in self.world.chunks_mut() {
+					chunk.drift_gpu(&drift_shader, self.rng.gen(), update);
+				}
+			}
+			_ => {
+				let rng = &mut self.rng;
+				for (_, chunk) in self.world.chunks_mut() {
+					chunk.update(|items| drift(items, rng, update));
+				}
+			}
+		}
+	}
+	fn draw(&mut self, _alpha: f32) {
+		if let Some(chunk_shader) = &self.chunk_shader {
+			// Pick up any edits to the voxel shaders (debug builds only)
+			chunk_shader.refresh();
+			let chunk_shader = chunk_shader.program();
+			self.world.upload(&chunk_shader);
+			self.lighting.apply(&chunk_shader);
+			// Without see-through voxels there's nothing for the extra passes to do
+			let blended = match (self.transparency, &self.weighted_blended, self.view) {
+				(Transparency::Sorted, _, Some(_)) | (Transparency::WeightedBlended, Some(_), Some(_)) => self.world.has_see_through(),
+				_ => false
+			};
+			match (&self.mesh_shader, self.render_mode) {
+				(Some(mesh_shader), RenderMode::Greedy) => {
+					mesh_shader.refresh();
+					let mesh_shader = mesh_shader.program();
+					self.lighting.apply(&mesh_shader);
+					self.world.draw_meshes(&mesh_shader);
+					// The meshes only have the opaque voxels.  Without blending, the see-through
+					// ones are drawn as if they were opaque.
+					if !blended && self.world.has_see_through() {
+						VoxelPass::Blended.apply(&chunk_shader);
+						self.world.draw(&chunk_shader);
+					}
+				}
+				_ => {
+					if blended {
+						VoxelPass::Opaque.apply(&chunk_shader);
+					} else {
+						VoxelPass::All.apply(&chunk_shader);
+					}
+					self.world.draw(&chunk_shader);
+				}
+			}
+			match (self.transparency, &mut self.weighted_blended, self.view) {
+				(Transparency::Sorted, _, Some(view)) if blended => {
+					VoxelPass::Blended.apply(&chunk_shader);
+					unsafe {
+						gl::Enable(gl::BLEND);
+						gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+						gl::DepthMask(gl::FALSE);
+					}
+					self.world.draw_sorted(&chunk_shader, view.eye);
+					unsafe {
+						gl::DepthMask(gl::TRUE);
+						gl::Disable(gl::BLEND);
+					}
+				}
+				(Transparency::WeightedBlended, Some(oit), Some(view)) if blended => match oit.begin(view.viewport) {
+					Ok(()) => {
+						VoxelPass::Accumulate.apply(&chunk_shader);
+						self.world.draw(&chunk_shader);
+						oit.finish();
+					}
+					Err(err) => {
+						eprintln!("{}", err);
+						self.weighted_blended = None;
+						self.transparency = Transparency::Sorted;
+					}
+				},
+				_ => {}
+			}
+		}
+		if let (Some(highlight), Some(hit)) = (&self.highlight, &self.hover) {
+			highlight.draw(hit, self.world.voxel_center(hit.voxel));
+		}
+	}
+	fn view(&mut self, view: &View) {
+		self.world.set_center(view.eye);
+		self.view = Some(*view);
+		// The camera may have moved even if the cursor didn't
+		self.hover = self.cursor.and_then(|cursor| self.pick(cursor));
+	}
+	fn handle_event(&mut self, event: &SceneEvent, input: &Input) -> EventResponse {
+		match event {
+			SceneEvent::MouseButton { button, state: ElementState::Pressed, position, .. } if input.action_down("voxel.edit") => {
+				self.edit(*button, *position);
+				EventResponse::Consumed
+			}
+			SceneEvent::CursorMoved { position } => {
+				self.cursor = Some(*position);
+				self.hover = self.pick(*position);
+				EventResponse::Ignored
+			}
+			_ => EventResponse::Ignored
+		}
+	}
+	fn register_actions(&self, actions: &mut ActionMap) {
+		// Held while clicking, see `handle_event`
+		actions.register("voxel.edit", Binding::Key(VirtualKeyCode::LAlt));
+		actions.register("voxel.gpu_drift", Binding::Key(VirtualKeyCode::G));
+		actions.register("voxel.animate", Binding::Key(VirtualKeyCode::N));
+		actions.register("voxel.render_mode", Binding::Key(VirtualKeyCode::R));
+		actions.register("voxel.lighting", Binding::Key(VirtualKeyCode::L));
+		actions.register("voxel.transparency", Binding::Key(VirtualKeyCode::B));
+		actions.register("voxel.memory", Binding::Key(VirtualKeyCode::K));
+		actions.register("voxel.brush", Binding::Key(VirtualKeyCode::C));
+		actions.register("voxel.export", Binding::Key(VirtualKeyCode::X));
+		actions.register("voxel.save", Binding::Key(VirtualKeyCode::F5));
+		actions.register("voxel.load", Binding::Key(VirtualKeyCode::F6));
+	}
+	fn action(&mut self, name: &str) {
+		match name {
+			"voxel.gpu_drift" => self.toggle_gpu_drift(),
+			"voxel.animate" => self.animate = !self.animate,
+			"voxel.render_mode" => self.toggle_render_mode(),
+			"voxel.lighting" => self.next_lighting(),
+			"voxel.transparency" => self.next_transparency(),
+			"voxel.memory" => self.print_memory(),
+			"voxel.brush" => self.next_brush(),
+			"voxel.export" => self.export(),
+			"voxel.save" => self.save(),
+			"voxel.load" => self.load(),
+			_ => {}
+		}
+	}
+	fn shutdown(&mut self) {
+		self.world.clear();
+		self.highlight = None;
+		self.weighted_blended = None;
+		self.chunk_shader = None;
+		self.mesh_shader = None;
+		self.drift_shader = None;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::headless::with_test_context;
+
+	fn test_items() -> Vec<ChunkItem> {
+		// Include the edges of the range so that clamping gets exercised
+		(0..ChunkSize::DEFAULT.len())
+			.map(|i| {
+				let value = [0.0, 0.5, 1.0][i % 3];
+				ChunkItem { size: value, color: (value, 1.0 - value, value), opacity: 1.0, index: i as u32 }
+			})
+			.collect()
+	}
+	fn values(item: &ChunkItem) -> [f32; 4] {
+		let ChunkItem { size, color, .. } = *item;
+		[size, color.0, color.1, color.2]
+	}
+	/// Run `steps` steps of the compute shader over `items` and read back the result.
+	fn run_gpu(items: Vec<ChunkItem>, seed: u64, steps: u32, update: Duration) -> Vec<ChunkItem> {
+		let chunk_shader = Chunk::build_chunk_shader().unwrap();
+		let drift_shader = build_drift_shader().unwrap();
+		let size = ChunkSize::DEFAULT;
+		let voxels = VoxelStorage::from_voxels(size, &vec![Voxel::solid(BlockId::SOLID, (1.0, 1.0, 1.0)); size.len()]);
+		let mut chunk = Chunk::with_voxels(&chunk_shader.program(), voxels, 0.0, 0.0, 0.0);
+		chunk.update(|old| old.copy_from_slice(&items));
+		let mut rng = StdRng::seed_from_u64(seed);
+		for _ in 0..steps {
+			chunk.drift_gpu(&drift_shader.program(), rng.gen(), update);
+		}
+		chunk.items.read_back();
+		chunk.items.items().to_vec()
+	}
+	/// The largest and mean absolute change of any value
+	fn changes(before: &[ChunkItem], after: &[ChunkItem]) -> (f32, f32) {
+		let deltas: Vec<f32> = before
+			.iter()
+			.zip(after)
+			.flat_map(|(a, b)| {
+				let (a, b) = (values(a), values(b));
+				(0..4).map(move |i| (a[i] - b[i]).abs())
+			})
+			.collect();
+		let max = deltas.iter().cloned().fold(0.0, f32::max);
+		(max, deltas.iter().sum::<f32>() / deltas.len() as f32)
+	}
+
+	#[test]
+	fn cpu_drift_stays_in_range() {
+		let mut items = test_items();
+		let mut rng = StdRng::seed_from_u64(7);
+		for _ in 0..50 {
+			drift(&mut items, &mut rng, Duration::from_millis(500));
+		}
+		assert!(items.iter().flat_map(|item| values(item).to_vec()).all(|value| (0.0..=1.0).contains(&value)));
+	}
+
+	#[test]
+	#[ignore = "needs libOSMesa, run with --ignored"]
+	fn gpu_drift_stays_in_range() {
+		with_test_context(|| {
+			let items = run_gpu(test_items(), 7, 50, Duration::from_millis(500));
+			assert_eq!(items.len(), ChunkSize::DEFAULT.len());
+			assert!(items.iter().flat_map(|item| values(item).to_vec()).all(|value| (0.0..=1.0).contains(&value)));
+		});
+	}
+
+	#[test]
+	#[ignore = "needs libOSMesa, run with --ignored"]
+	fn gpu_drift_is_deterministic() {
+		with_test_context(|| {
+			let step = Duration::from_millis(16);
+			let first = run_gpu(test_items(), 1, 10, step);
+			let second = run_gpu(test_items(), 1, 10, step);
+			let other_seed = run_gpu(test_items(), 2, 10, step);
+			let as_values = |items: &[ChunkItem]| items.iter().map(values).collect::<Vec<_>>();
+			assert_eq!(as_values(&first), as_values(&second));
+			assert_ne!(as_values(&first), as_values(&other_seed));
+		});
+	}
+
+	#[test]
+	#[ignore = "needs libOSMesa, run with --ignored"]
+	fn gpu_drift_matches_cpu_reference() {
+		with_test_context(|| {
+			// Start in the middle so that nothing gets clamped: every step is uniform in [-0.1, 0.1]
+			let start: Vec<ChunkItem> = (0..ChunkSize::DEFAULT.len()).map(|i| ChunkItem { size: 0.5, color: (0.5, 0.5, 0.5), opacity: 0.5, index: i as u32 }).collect();
+			let update = Duration::from_millis(200);
+
+			let mut cpu = start.clone();
+			drift(&mut cpu, &mut StdRng::seed_from_u64(3), update);
+			let gpu = run_gpu(start.clone(), 3, 1, update);
+
+			for (name, after) in [("CPU", &cpu), ("GPU", &gpu)].iter() {
+				let (max, mean) = changes(&start, after);
+				assert!(max <= 0.1 + 1e-6, "{} changed a value by {}", name, max);
+				assert!((mean - 0.05).abs() < 0.005, "{} changed values by {} on average", name, mean);
+			}
+		});
+	}
+}
@@ -0,0 +1,167 @@
+use rand::prelude::*;
+
+/// Seeded gradient (Perlin) noise.  Coordinates are `f64` so that chunks far from the origin don't
+/// lose precision.
+pub struct Perlin {
+	/// A shuffled 0..256, twice, so lookups can skip the wrap around
+	permutation: [u8; 512]
+}
+impl Perlin {
+	pub fn new(seed: u64) -> Perlin {
+		let mut values: Vec<u8> = (0..=255).collect();
+		values.shuffle(&mut StdRng::seed_from_u64(seed));
+		let mut permutation = [0; 512];
+		for (i, slot) in permutation.iter_mut().enumerate() {
+			*slot = values[i % 256];
+		}
+		Perlin { permutation }
+	}
+	fn hash(&self, x: i64, y: i64, z: i64) -> u8 {
+		let p = &self.permutation;
+		p[p[p[(x & 255) as usize] as usize + (y & 255) as usize] as usize + (z & 255) as usize]
+	}
+
+	/// Roughly -1 to 1, 0 at every integer point.
+	pub fn noise2(&self, x: f64, y: f64) -> f64 {
+		// The 2D noise is a slice through the 3D noise: z = 0 never needs the gradients' z
+		self.noise3(x, y, 0.0)
+	}
+	/// Roughly -1 to 1, 0 at every integer point.
+	pub fn noise3(&self, x: f64, y: f64, z: f64) -> f64 {
+		let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+		let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+		let (ix, iy, iz) = (x0 as i64, y0 as i64, z0 as i64);
+		let corner = |dx: i64, dy: i64, dz: i64| {
+			gradient(self.hash(ix + dx, iy + dy, iz + dz), fx - dx as f64, fy - dy as f64, fz - dz as f64)
+		};
+		let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+		lerp(
+			w,
+			lerp(v, lerp(u, corner(0, 0, 0), corner(1, 0, 0)), lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+			lerp(v, lerp(u, corner(0, 0, 1), corner(1, 0, 1)), lerp(u, corner(0, 1, 1), corner(1, 1, 1)))
+		)
+	}
+}
+
+/// 6t^5 - 15t^4 + 10t^3: the first and second derivative are 0 at both ends, so cells join smoothly
+fn fade(t: f64) -> f64 {
+	t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+	a + t * (b - a)
+}
+/// The dot product of the offset with one of the 12 edge directions of a cube (Improved Noise, Perlin 2002)
+fn gradient(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+	match hash & 15 {
+		0 | 12 => x + y,
+		1 | 14 => -x + y,
+		2 => x - y,
+		3 => -x - y,
+		4 => x + z,
+		5 => -x + z,
+		6 => x - z,
+		7 => -x - z,
+		8 => y + z,
+		9 | 13 => -y + z,
+		10 => y - z,
+		_ => -y - z
+	}
+}
+
+/// Several octaves of noise added together, each at a higher frequency and lower amplitude.
+#[derive(Clone, Copy, Debug)]
+pub struct Fractal {
+	pub octaves: u32,
+	/// Of the first octave, in cycles per voxel
+	pub frequency: f64,
+	/// How much the frequency grows per octave
+	pub lacunarity: f64,
+	/// How much the amplitude shrinks per octave
+	pub gain: f64
+}
+impl Fractal {
+	pub fn new(octaves: u32, frequency: f64) -> Fractal {
+		Fractal { octaves, frequency, lacunarity: 2.0, gain: 0.5 }
+	}
+	/// Fractal Brownian motion, roughly -1 to 1
+	pub fn fbm2(&self, noise: &Perlin, x: f64, y: f64) -> f64 {
+		self.sum(|frequency, octave| noise.noise2(x * frequency + octave, y * frequency + octave))
+	}
+	/// Fractal Brownian motion, roughly -1 to 1
+	pub fn fbm3(&self, noise: &Perlin, x: f64, y: f64, z: f64) -> f64 {
+		self.sum(|frequency, octave| noise.noise3(x * frequency + octave, y * frequency + octave, z * frequency + octave))
+	}
+	/// Sharp crests where the noise crosses 0, like mountain ridges.  0 to 1.
+	pub fn ridged2(&self, noise: &Perlin, x: f64, y: f64) -> f64 {
+		let mut total = 0.0;
+		let mut amplitude = 1.0;
+		let mut frequency = self.frequency;
+		let mut max = 0.0;
+		// Each octave is weighted by the one before, so the detail sits on the ridges
+		let mut weight = 1.0;
+		for octave in 0..self.octaves {
+			let offset = octave as f64 * 17.0;
+			let ridge = 1.0 - noise.noise2(x * frequency + offset, y * frequency + offset).abs();
+			let ridge = ridge * ridge * weight;
+			weight = (ridge * 2.0).clamp(0.0, 1.0);
+			total += ridge * amplitude;
+			max += amplitude;
+			amplitude *= self.gain;
+			frequency *= self.lacunarity;
+		}
+		total / max
+	}
+	/// `fbm2` with its input pushed around by two more fbms, which bends the features into swirls.
+	/// `strength` is in voxels.
+	pub fn warped2(&self, noise: &Perlin, x: f64, y: f64, strength: f64) -> f64 {
+		let warp_x = self.fbm2(noise, x + 51.7, y + 93.2);
+		let warp_y = self.fbm2(noise, x - 37.1, y + 12.9);
+		self.fbm2(noise, x + strength * warp_x, y + strength * warp_y)
+	}
+
+	/// Add up the octaves.  `octave` is given an offset so the octaves don't all line up at the origin.
+	fn sum(&self, octave: impl Fn(f64, f64) -> f64) -> f64 {
+		let mut total = 0.0;
+		let mut amplitude = 1.0;
+		let mut frequency = self.frequency;
+		let mut max = 0.0;
+		for i in 0..self.octaves {
+			total += octave(frequency, i as f64 * 17.0) * amplitude;
+			max += amplitude;
+			amplitude *= self.gain;
+			frequency *= self.lacunarity;
+		}
+		total / max
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn noise_is_seeded() {
+		let (a, b, c) = (Perlin::new(1), Perlin::new(1), Perlin::new(2));
+		let sample = |noise: &Perlin| (0..50).map(|i| noise.noise3(i as f64 * 0.37, i as f64 * 0.11, 1.5)).collect::<Vec<_>>();
+		assert_eq!(sample(&a), sample(&b));
+		assert_ne!(sample(&a), sample(&c));
+	}
+
+	#[test]
+	fn noise_is_bounded_and_continuous() {
+		let noise = Perlin::new(3);
+		let fractal = Fractal::new(4, 0.05);
+		let mut previous = fractal.fbm2(&noise, 0.0, 0.0);
+		for i in 1..2000 {
+			let x = i as f64 * 0.01;
+			let value = noise.noise3(x, x * 0.5, -x);
+			assert!(value.abs() <= 1.1, "{} at {}", value, x);
+			assert!((0.0..=1.0).contains(&fractal.ridged2(&noise, x, 3.0)));
+
+			// A small step never makes a big jump
+			let fbm = fractal.fbm2(&noise, x, 0.0);
+			assert!((fbm - previous).abs() < 0.05, "{} -> {} at {}", previous, fbm, x);
+			previous = fbm;
+		}
+	}
+}
@@ -0,0 +1,153 @@
+use cgmath::{InnerSpace, Point3, Vector3};
+
+/// Where a ray ran into a solid voxel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+	pub voxel: Point3<i32>,
+	/// Points out of the face the ray went in through.  Zero if the ray started inside the voxel.
+	pub normal: Vector3<i32>,
+	/// How far along the ray the face is
+	pub distance: f32
+}
+impl RayHit {
+	/// The cell on the other side of the hit face, where a new voxel would go
+	pub fn adjacent(&self) -> Point3<i32> {
+		self.voxel + self.normal
+	}
+}
+
+/// Walk through the cells of the unit grid that a ray passes, in order, until `is_solid` is true for
+/// one of them (Amanatides & Woo, "A Fast Voxel Traversal Algorithm for Ray Tracing").  Cell
+/// `x, y, z` spans `x` to `x + 1` and so on.  Gives up after `max_distance`.
+pub fn cast_ray(
+	origin: Point3<f32>,
+	direction: Vector3<f32>,
+	max_distance: f32,
+	mut is_solid: impl FnMut(Point3<i32>) -> bool
+) -> Option<RayHit> {
+	if direction.magnitude2() == 0.0 || !direction.magnitude2().is_finite() {
+		return None;
+	}
+	let direction = direction.normalize();
+	let origin = [origin.x, origin.y, origin.z];
+	let direction = [direction.x, direction.y, direction.z];
+
+	let mut voxel = [0; 3];
+	let mut step = [0; 3];
+	// How far along the ray the next cell border is on each axis
+	let mut next_border = [f32::INFINITY; 3];
+	// How far along the ray it is from one cell border to the next on each axis
+	let mut border_distance = [f32::INFINITY; 3];
+	for axis in 0..3 {
+		voxel[axis] = origin[axis].floor() as i32;
+		if direction[axis] > 0.0 {
+			step[axis] = 1;
+			next_border[axis] = (voxel[axis] as f32 + 1.0 - origin[axis]) / direction[axis];
+			border_distance[axis] = 1.0 / direction[axis];
+		} else if direction[axis] < 0.0 {
+			step[axis] = -1;
+			next_border[axis] = (voxel[axis] as f32 - origin[axis]) / direction[axis];
+			border_distance[axis] = -1.0 / direction[axis];
+		}
+	}
+
+	let mut normal = [0; 3];
+	let mut distance = 0.0;
+	loop {
+		if is_solid(Point3::new(voxel[0], voxel[1], voxel[2])) {
+			return Some(RayHit {
+				voxel: Point3::new(voxel[0], voxel[1], voxel[2]),
+				normal: Vector3::new(normal[0], normal[1], normal[2]),
+				distance
+			});
+		}
+		// Cross whichever border comes first
+		let axis = if next_border[0] < next_border[1] {
+			if next_border[0] < next_border[2] { 0 } else { 2 }
+		} else if next_border[1] < next_border[2] {
+			1
+		} else {
+			2
+		};
+		distance = next_border[axis];
+		if distance > max_distance {
+			return None;
+		}
+		voxel[axis] += step[axis];
+		normal = [0; 3];
+		normal[axis] = -step[axis];
+		next_border[axis] += border_distance[axis];
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Every cell the ray passes through, up to `max_distance`
+	fn visited(origin: Point3<f32>, direction: Vector3<f32>, max_distance: f32) -> Vec<Point3<i32>> {
+		let mut cells = Vec::new();
+		cast_ray(origin, direction, max_distance, |voxel| {
+			cells.push(voxel);
+			false
+		});
+		cells
+	}
+
+	#[test]
+	fn axis_aligned_rays_hit_the_nearest_face() {
+		let solid = |voxel: Point3<i32>| voxel == Point3::new(5, 0, 0) || voxel == Point3::new(8, 0, 0);
+		let hit = cast_ray(Point3::new(0.5, 0.5, 0.5), Vector3::new(1.0, 0.0, 0.0), 100.0, solid).unwrap();
+		assert_eq!(hit.voxel, Point3::new(5, 0, 0));
+		assert_eq!(hit.normal, Vector3::new(-1, 0, 0));
+		assert!((hit.distance - 4.5).abs() < 1e-5);
+		assert_eq!(hit.adjacent(), Point3::new(4, 0, 0));
+
+		// The same from the other side, through negative coordinates
+		let solid = |voxel: Point3<i32>| voxel == Point3::new(0, -3, 0);
+		let hit = cast_ray(Point3::new(0.5, 2.25, 0.5), Vector3::new(0.0, -2.0, 0.0), 100.0, solid).unwrap();
+		assert_eq!(hit.voxel, Point3::new(0, -3, 0));
+		assert_eq!(hit.normal, Vector3::new(0, 1, 0));
+		assert!((hit.distance - 4.25).abs() < 1e-5);
+	}
+
+	#[test]
+	fn starting_inside_a_voxel_hits_it() {
+		let hit = cast_ray(Point3::new(-1.5, 2.5, 3.5), Vector3::new(1.0, 1.0, 0.0), 10.0, |_| true).unwrap();
+		assert_eq!(hit.voxel, Point3::new(-2, 2, 3));
+		assert_eq!(hit.normal, Vector3::new(0, 0, 0));
+		assert_eq!(hit.distance, 0.0);
+	}
+
+	#[test]
+	fn misses_stop_at_the_maximum_distance() {
+		assert_eq!(cast_ray(Point3::new(0.5, 0.5, 0.5), Vector3::new(0.0, 0.0, 1.0), 3.0, |voxel| voxel.z == 5), None);
+		assert!(cast_ray(Point3::new(0.5, 0.5, 0.5), Vector3::new(0.0, 0.0, 1.0), 5.0, |voxel| voxel.z == 5).is_some());
+		assert_eq!(visited(Point3::new(0.5, 0.5, 0.5), Vector3::new(0.0, 0.0, -1.0), 3.0).len(), 4);
+		// No direction at all
+		assert_eq!(cast_ray(Point3::new(0.5, 0.5, 0.5), Vector3::new(0.0, 0.0, 0.0), 10.0, |_| true), None);
+	}
+
+	#[test]
+	fn diagonal_rays_visit_every_cell_they_cross() {
+		let origin = Point3::new(0.3, -1.7, 2.2);
+		for &direction in [Vector3::new(1.0, 0.7, -0.4), Vector3::new(-0.2, -1.0, 0.9), Vector3::new(0.5, 0.5, 0.5)].iter() {
+			let cells = visited(origin, direction, 20.0);
+			// Neighbouring cells share a face: only one coordinate changes, by one
+			for pair in cells.windows(2) {
+				let step = pair[1] - pair[0];
+				assert_eq!(step.x.abs() + step.y.abs() + step.z.abs(), 1, "{:?} to {:?}", pair[0], pair[1]);
+			}
+			// Every point along the ray is in one of them, in the same order
+			let direction = direction.normalize();
+			let mut last = 0;
+			for i in 0..2000 {
+				let point = origin + direction * (i as f32 * 0.01);
+				let cell = Point3::new(point.x.floor() as i32, point.y.floor() as i32, point.z.floor() as i32);
+				let found = cells[last..].iter().position(|&visited| visited == cell);
+				assert!(found.is_some(), "{:?} at {} was skipped", cell, i as f32 * 0.01);
+				last += found.unwrap();
+			}
+		}
+	}
+}
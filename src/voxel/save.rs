@@ -0,0 +1,588 @@
+//! Chunks on disk.  Every chunk is encoded on its own (see `encode_chunk`), and the chunks of an
+//! 8 x 8 x 8 block of chunk coordinates share a region file.
+//!
+//! Chunk layout, little endian:
+//!
+//! | bytes | what                                        |
+//! |-------|---------------------------------------------|
+//! | 4     | `CHUNK_MAGIC`                               |
+//! | 2     | version                                     |
+//! | 4 x 3 | width, height, depth                        |
+//! | 1     | compression: 0 = none, 1 = run length      |
+//! | 4     | payload length                              |
+//! | 4     | Adler-32 of the payload                     |
+//! |       | payload                                     |
+//!
+//! The payload is one record per cell in `ChunkSize::index` order, or with run length encoding
+//! pairs of a `u32` count and a record.  Version 1 records are the old `ChunkItem` (size and color,
+//! with a size of 0 for air); version 2 adds the block id in front and version 3 the opacity at the
+//! end.  Older versions are opaque.
+
+use super::{
+	bytes::{Reader, Truncated},
+	BlockId, ChunkCoord, ChunkSize, Voxel, VoxelStorage
+};
+
+use std::{
+	collections::{BTreeMap, HashMap, VecDeque},
+	error::Error,
+	fmt, fs, io,
+	path::{Path, PathBuf},
+	time::SystemTime,
+};
+
+const CHUNK_MAGIC: &[u8; 4] = b"VXCH";
+const REGION_MAGIC: &[u8; 4] = b"VXRG";
+/// What `encode_chunk` writes.  `decode_chunk` reads this and everything older.
+pub const CHUNK_VERSION: u16 = 3;
+pub const REGION_VERSION: u16 = 1;
+/// How many chunks a region spans along each axis
+pub const REGION_SIZE: i32 = 8;
+/// Anything bigger is taken to be a corrupted header, rather than allocated
+const MAX_CELLS: usize = 1 << 24;
+const HEADER_LENGTH: usize = 4 + 2 + 12 + 1 + 4 + 4;
+/// How many parsed regions `RegionCache` keeps
+const CACHED_REGIONS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+	None = 0,
+	RunLength = 1
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+	Io(io::Error),
+	/// The file doesn't start with the magic bytes of `what`
+	WrongMagic { what: &'static str },
+	/// Written by a newer version of the program
+	UnsupportedVersion { what: &'static str, found: u16, newest: u16 },
+	/// The file ends too soon
+	Truncated,
+	Corrupt(String),
+	/// The chunk isn't the size the world needs
+	WrongSize { expected: ChunkSize, found: ChunkSize }
+}
+impl From<Truncated> for SaveError {
+	fn from(_: Truncated) -> SaveError {
+		SaveError::Truncated
+	}
+}
+impl fmt::Display for SaveError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SaveError::Io(err) => write!(f, "{}", err),
+			SaveError::WrongMagic { what } => write!(f, "Not a {} file", what),
+			SaveError::UnsupportedVersion { what, found, newest } => write!(
+				f,
+				"The {} was saved with format version {}, but only versions up to {} can be read",
+				what, found, newest
+			),
+			SaveError::Truncated => write!(f, "The file is cut off"),
+			SaveError::Corrupt(message) => write!(f, "The file is corrupt: {}", message),
+			SaveError::WrongSize { expected, found } => write!(f, "The chunk is {:?} instead of {:?}", found, expected)
+		}
+	}
+}
+impl Error for SaveError {}
+impl From<io::Error> for SaveError {
+	fn from(err: io::Error) -> SaveError {
+		SaveError::Io(err)
+	}
+}
+
+/// The chunk as bytes, run length encoded if that's smaller
+pub fn encode_chunk(voxels: &VoxelStorage) -> Vec<u8> {
+	let raw = encode_payload(voxels, Compression::None);
+	let run_length = encode_payload(voxels, Compression::RunLength);
+	let (compression, payload) = if run_length.len() < raw.len() {
+		(Compression::RunLength, run_length)
+	} else {
+		(Compression::None, raw)
+	};
+	let size = voxels.size();
+	let mut bytes = Vec::with_capacity(HEADER_LENGTH + payload.len());
+	bytes.extend_from_slice(CHUNK_MAGIC);
+	bytes.extend_from_slice(&CHUNK_VERSION.to_le_bytes());
+	for &dimension in [size.width, size.height, size.depth].iter() {
+		bytes.extend_from_slice(&(dimension as u32).to_le_bytes());
+	}
+	bytes.push(compression as u8);
+	bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+	bytes.extend_from_slice(&adler32(&payload).to_le_bytes());
+	bytes.extend_from_slice(&payload);
+	bytes
+}
+fn encode_payload(voxels: &VoxelStorage, compression: Compression) -> Vec<u8> {
+	let mut payload = Vec::new();
+	match compression {
+		Compression::None => {
+			for voxel in voxels.iter() {
+				write_record(&mut payload, voxel);
+			}
+		}
+		Compression::RunLength => {
+			let mut run: Option<(Voxel, u32)> = None;
+			for voxel in voxels.iter() {
+				run = match run {
+					Some((current, count)) if current == voxel => Some((current, count + 1)),
+					Some((current, count)) => {
+						payload.extend_from_slice(&count.to_le_bytes());
+						write_record(&mut payload, current);
+						Some((voxel, 1))
+					}
+					None => Some((voxel, 1))
+				};
+			}
+			if let Some((current, count)) = run {
+				payload.extend_from_slice(&count.to_le_bytes());
+				write_record(&mut payload, current);
+			}
+		}
+	}
+	payload
+}
+fn write_record(bytes: &mut Vec<u8>, voxel: Voxel) {
+	bytes.extend_from_slice(&voxel.block.0.to_le_bytes());
+	for &value in [voxel.size, voxel.color.0, voxel.color.1, voxel.color.2, voxel.opacity].iter() {
+		bytes.extend_from_slice(&value.to_le_bytes());
+	}
+}
+
+/// Read a chunk written by `encode_chunk`, of this or an older version.
+pub fn decode_chunk(bytes: &[u8]) -> Result<VoxelStorage, SaveError> {
+	let mut reader = Reader::new(bytes);
+	if reader.take(4)? != CHUNK_MAGIC {
+		return Err(SaveError::WrongMagic { what: "chunk" });
+	}
+	let version = reader.u16()?;
+	let record_length = match version {
+		1 => 16,
+		2 => 18,
+		3 => 22,
+		_ => return Err(SaveError::UnsupportedVersion { what: "chunk", found: version, newest: CHUNK_VERSION })
+	};
+	let size = ChunkSize::new(reader.u32()? as usize, reader.u32()? as usize, reader.u32()? as usize);
+	let cells = size.width.checked_mul(size.height).and_then(|cells| cells.checked_mul(size.depth)).unwrap_or(usize::MAX);
+	if cells == 0 || cells > MAX_CELLS {
+		return Err(SaveError::Corrupt(format!("a chunk can't be {:?}", size)));
+	}
+	let compression = match reader.u8()? {
+		0 => Compression::None,
+		1 => Compression::RunLength,
+		other => return Err(SaveError::Corrupt(format!("unknown compression {}", other)))
+	};
+	let payload_length = reader.u32()? as usize;
+	let checksum = reader.u32()?;
+	let payload = reader.take(payload_length)?;
+	if adler32(payload) != checksum {
+		return Err(SaveError::Corrupt("the checksum doesn't match".to_string()));
+	}
+
+	let mut payload = Reader::new(payload);
+	let voxels = match compression {
+		Compression::None => {
+			if payload_length != cells * record_length {
+				return Err(SaveError::Corrupt(format!("{} bytes of voxels for {} cells", payload_length, cells)));
+			}
+			let voxels = (0..cells).map(|_| read_record(&mut payload, version)).collect::<Result<Vec<_>, _>>()?;
+			VoxelStorage::from_voxels(size, &voxels)
+		}
+		Compression::RunLength => {
+			// The payload limits how many runs there are but not how long they are, so they aren't expanded
+			let mut runs = Vec::with_capacity(payload_length / (4 + record_length));
+			let mut left = cells;
+			while left > 0 {
+				let count = payload.u32()? as usize;
+				if count == 0 || count > left {
+					return Err(SaveError::Corrupt(format!("a run of {} voxels with {} cells left", count, left)));
+				}
+				runs.push((count, read_record(&mut payload, version)?));
+				left -= count;
+			}
+			VoxelStorage::from_runs(size, &runs)
+		}
+	};
+	if !payload.is_at_end() {
+		return Err(SaveError::Corrupt("there's more data after the voxels".to_string()));
+	}
+	Ok(voxels)
+}
+fn read_record(payload: &mut Reader, version: u16) -> Result<Voxel, SaveError> {
+	if version == 1 {
+		let (size, r, g, b) = (payload.f32()?, payload.f32()?, payload.f32()?, payload.f32()?);
+		// Before block ids, air was a size of 0
+		Ok(if size > 0.0 { Voxel::new(BlockId::SOLID, size, (r, g, b)) } else { Voxel::EMPTY })
+	} else {
+		let voxel = Voxel::new(BlockId(payload.u16()?), payload.f32()?, (payload.f32()?, payload.f32()?, payload.f32()?));
+		Ok(if version == 2 { voxel } else { voxel.with_opacity(payload.f32()?) })
+	}
+}
+
+/// The chunks of one region, still encoded
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Region {
+	chunks: BTreeMap<ChunkCoord, Vec<u8>>
+}
+impl Region {
+	pub fn new() -> Region {
+		Region::default()
+	}
+	/// Layout: `REGION_MAGIC`, the version (u16) and the number of chunks (u32), then for every
+	/// chunk its coordinates (3 x i32), offset from the start of the file and length (u32 each),
+	/// and finally the chunks one after the other.
+	pub fn parse(bytes: &[u8]) -> Result<Region, SaveError> {
+		let mut reader = Reader::new(bytes);
+		if reader.take(4)? != REGION_MAGIC {
+			return Err(SaveError::WrongMagic { what: "region" });
+		}
+		let version = reader.u16()?;
+		if version != REGION_VERSION {
+			return Err(SaveError::UnsupportedVersion { what: "region", found: version, newest: REGION_VERSION });
+		}
+		let count = reader.u32()? as usize;
+		if count > reader.remaining() / 20 {
+			return Err(SaveError::Truncated);
+		}
+		let mut chunks = BTreeMap::new();
+		for _ in 0..count {
+			let coord = ChunkCoord::new(reader.i32()?, reader.i32()?, reader.i32()?);
+			let (offset, length) = (reader.u32()? as usize, reader.u32()? as usize);
+			let chunk = offset.checked_add(length).and_then(|end| bytes.get(offset..end)).ok_or(SaveError::Truncated)?;
+			chunks.insert(coord, chunk.to_vec());
+		}
+		Ok(Region { chunks })
+	}
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = REGION_MAGIC.to_vec();
+		bytes.extend_from_slice(&REGION_VERSION.to_le_bytes());
+		bytes.extend_from_slice(&(self.chunks.len() as u32).to_le_bytes());
+		let mut offset = bytes.len() + self.chunks.len() * 20;
+		for (coord, chunk) in self.chunks.iter() {
+			for &value in [coord.x, coord.y, coord.z].iter() {
+				bytes.extend_from_slice(&value.to_le_bytes());
+			}
+			bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+			bytes.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+			offset += chunk.len();
+		}
+		for chunk in self.chunks.values() {
+			bytes.extend_from_slice(chunk);
+		}
+		bytes
+	}
+	pub fn len(&self) -> usize {
+		self.chunks.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.chunks.is_empty()
+	}
+	pub fn insert(&mut self, coord: ChunkCoord, voxels: &VoxelStorage) {
+		self.chunks.insert(coord, encode_chunk(voxels));
+	}
+	pub fn get(&self, coord: ChunkCoord) -> Option<Result<VoxelStorage, SaveError>> {
+		self.chunks.get(&coord).map(|chunk| decode_chunk(chunk))
+	}
+}
+
+/// The region that chunk `coord` is in
+pub fn region_of(coord: ChunkCoord) -> ChunkCoord {
+	ChunkCoord::new(coord.x.div_euclid(REGION_SIZE), coord.y.div_euclid(REGION_SIZE), coord.z.div_euclid(REGION_SIZE))
+}
+pub fn region_path(directory: &Path, region: ChunkCoord) -> PathBuf {
+	directory.join(format!("r.{}.{}.{}.vxr", region.x, region.y, region.z))
+}
+fn read_region(path: &Path) -> Result<Option<Region>, SaveError> {
+	match fs::read(path) {
+		Ok(bytes) => Region::parse(&bytes).map(Some),
+		Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(err) => Err(err.into())
+	}
+}
+
+/// The modification time and length of a region file, to tell whether it was written since it was
+/// read.  `None` if there's no file.
+type RegionStamp = Option<(SystemTime, u64)>;
+fn region_stamp(path: &Path) -> Result<RegionStamp, SaveError> {
+	match fs::metadata(path) {
+		Ok(metadata) => Ok(Some((metadata.modified()?, metadata.len()))),
+		Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(err) => Err(err.into())
+	}
+}
+
+/// The regions that chunks were recently loaded from, so that loading the chunks of a region one by
+/// one reads and parses it once.  A region file that was written since it was read is read again.
+pub struct RegionCache {
+	directory: PathBuf,
+	regions: HashMap<ChunkCoord, (RegionStamp, Option<Region>)>,
+	/// Oldest first
+	order: VecDeque<ChunkCoord>
+}
+impl RegionCache {
+	pub fn new(directory: PathBuf) -> RegionCache {
+		RegionCache { directory, regions: HashMap::new(), order: VecDeque::new() }
+	}
+	/// The saved chunk at `coord`, if there is one
+	pub fn load_chunk(&mut self, coord: ChunkCoord, size: ChunkSize) -> Result<Option<VoxelStorage>, SaveError> {
+		let region = match self.region(region_of(coord))? {
+			Some(region) => region,
+			None => return Ok(None)
+		};
+		match region.get(coord) {
+			Some(Ok(voxels)) if voxels.size() != size => Err(SaveError::WrongSize { expected: size, found: voxels.size() }),
+			Some(result) => result.map(Some),
+			None => Ok(None)
+		}
+	}
+	fn region(&mut self, coord: ChunkCoord) -> Result<Option<&Region>, SaveError> {
+		let path = region_path(&self.directory, coord);
+		let stamp = region_stamp(&path)?;
+		if self.regions.get(&coord).is_none_or(|(cached, _)| *cached != stamp) {
+			let region = if stamp.is_some() { read_region(&path)? } else { None };
+			if self.regions.insert(coord, (stamp, region)).is_none() {
+				self.order.push_back(coord);
+				if self.order.len() > CACHED_REGIONS {
+					let oldest = self.order.pop_front().unwrap();
+					self.regions.remove(&oldest);
+				}
+			}
+		}
+		Ok(self.regions[&coord].1.as_ref())
+	}
+}
+/// Add `chunks` to the region files in `directory`, replacing what was saved for them before.
+/// Regions are written to a temporary file first, so a crash can't leave half of one behind.
+/// Returns how many region files were written.
+pub fn save_chunks<'a>(directory: &Path, chunks: impl IntoIterator<Item = (ChunkCoord, &'a VoxelStorage)>) -> Result<usize, SaveError> {
+	let mut regions: BTreeMap<ChunkCoord, Vec<(ChunkCoord, &VoxelStorage)>> = BTreeMap::new();
+	for (coord, voxels) in chunks {
+		regions.entry(region_of(coord)).or_default().push((coord, voxels));
+	}
+	fs::create_dir_all(directory)?;
+	for (&region_coord, chunks) in regions.iter() {
+		let path = region_path(directory, region_coord);
+		// A corrupted region is an error rather than overwritten, so what's left of it can be rescued
+		let mut region = read_region(&path)?.unwrap_or_default();
+		for &(coord, voxels) in chunks.iter() {
+			region.insert(coord, voxels);
+		}
+		let temporary = path.with_extension("vxr.tmp");
+		fs::write(&temporary, region.to_bytes())?;
+		fs::rename(&temporary, &path)?;
+	}
+	Ok(regions.len())
+}
+
+/// https://en.wikipedia.org/wiki/Adler-32
+fn adler32(bytes: &[u8]) -> u32 {
+	const MODULO: u32 = 65521;
+	let (mut a, mut b) = (1u32, 0u32);
+	for &byte in bytes {
+		a = (a + byte as u32) % MODULO;
+		b = (b + a) % MODULO;
+	}
+	(b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::voxel::{ChunkGenerator, TerrainGenerator};
+
+	fn terrain_chunk(coord: ChunkCoord) -> VoxelStorage {
+		let size = ChunkSize::new(16, 40, 16);
+		VoxelStorage::from_voxels(size, &TerrainGenerator::new(9).generate(coord, size))
+	}
+	/// A small chunk of layers, with some runs to compress
+	fn layered_chunk(seed: u16) -> VoxelStorage {
+		let size = ChunkSize::new(4, 6, 4);
+		let voxels: Vec<_> = (0..size.len())
+			.map(|i| match size.position(i) {
+				(_, 0..=1, _) => Voxel::solid(BlockId::STONE, (0.5, 0.5, 0.5)),
+				(x, 2, z) => Voxel::solid(BlockId::GRASS, (0.1 * x as f32, 0.8, 0.1 * (z + seed as usize) as f32)),
+				_ => Voxel::EMPTY
+			})
+			.collect();
+		VoxelStorage::from_voxels(size, &voxels)
+	}
+	/// A chunk that doesn't compress: every voxel is different
+	fn noisy_chunk() -> VoxelStorage {
+		let size = ChunkSize::new(4, 3, 5);
+		let voxels: Vec<_> = (0..size.len())
+			.map(|i| Voxel::new(BlockId(i as u16 % 7 + 1), 0.5, (i as f32 / 60.0, 0.25, 1.0)).with_opacity(1.0 - (i % 3) as f32 * 0.25))
+			.collect();
+		VoxelStorage::from_voxels(size, &voxels)
+	}
+	/// A directory for one test to write to
+	fn scratch_directory(name: &str) -> PathBuf {
+		let directory = std::env::temp_dir().join(format!("voxel-save-test-{}-{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&directory);
+		directory
+	}
+
+	#[test]
+	fn chunks_round_trip() {
+		let terrain = terrain_chunk(ChunkCoord::new(2, 0, -1));
+		let bytes = encode_chunk(&terrain);
+		assert_eq!(bytes[18], Compression::RunLength as u8);
+		assert!(bytes.len() < encode_payload(&terrain, Compression::None).len(), "{} bytes", bytes.len());
+		assert_eq!(decode_chunk(&bytes).unwrap(), terrain);
+
+		let noisy = noisy_chunk();
+		let bytes = encode_chunk(&noisy);
+		assert_eq!(bytes[18], Compression::None as u8);
+		assert_eq!(decode_chunk(&bytes).unwrap(), noisy);
+
+		let empty = VoxelStorage::new(ChunkSize::new(20, 60, 20));
+		let bytes = encode_chunk(&empty);
+		assert_eq!(bytes.len(), HEADER_LENGTH + 4 + 22);
+		assert_eq!(decode_chunk(&bytes).unwrap(), empty);
+	}
+
+	/// A chunk of `cells` cells in the layout of an older `version`, uncompressed
+	fn old_chunk(version: u16, cells: u32, payload: &[u8]) -> Vec<u8> {
+		let mut bytes = CHUNK_MAGIC.to_vec();
+		bytes.extend_from_slice(&version.to_le_bytes());
+		for dimension in [1u32, 1, cells].iter() {
+			bytes.extend_from_slice(&dimension.to_le_bytes());
+		}
+		bytes.push(0);
+		bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+		bytes.extend_from_slice(&adler32(payload).to_le_bytes());
+		bytes.extend_from_slice(payload);
+		bytes
+	}
+
+	#[test]
+	fn long_runs_dont_expand() {
+		// A few bytes for the biggest chunk there can be
+		let mut payload = (MAX_CELLS as u32).to_le_bytes().to_vec();
+		payload.extend_from_slice(&BlockId::EMPTY.0.to_le_bytes());
+		for value in [0.0f32, 0.0, 0.0, 0.0, 0.0].iter() {
+			payload.extend_from_slice(&value.to_le_bytes());
+		}
+		let mut bytes = CHUNK_MAGIC.to_vec();
+		bytes.extend_from_slice(&CHUNK_VERSION.to_le_bytes());
+		for dimension in [256u32, 256, 256].iter() {
+			bytes.extend_from_slice(&dimension.to_le_bytes());
+		}
+		bytes.push(Compression::RunLength as u8);
+		bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+		bytes.extend_from_slice(&adler32(&payload).to_le_bytes());
+		bytes.extend_from_slice(&payload);
+		let voxels = decode_chunk(&bytes).unwrap();
+		assert_eq!(voxels.len(), MAX_CELLS);
+		assert!(voxels.is_empty());
+		assert!(voxels.memory_bytes() < 1024, "{} bytes", voxels.memory_bytes());
+	}
+
+	#[test]
+	fn older_versions_are_migrated() {
+		// Two cells of air and a solid one, the way version 1 stored them
+		let records: [(f32, (f32, f32, f32)); 3] = [(0.0, (0.0, 0.0, 0.0)), (0.0, (1.0, 1.0, 1.0)), (0.75, (0.2, 0.4, 0.6))];
+		let mut payload = Vec::new();
+		for (size, color) in records.iter() {
+			for value in [*size, color.0, color.1, color.2].iter() {
+				payload.extend_from_slice(&value.to_le_bytes());
+			}
+		}
+		let voxels = decode_chunk(&old_chunk(1, 3, &payload)).unwrap();
+		assert_eq!(voxels.to_vec(), vec![Voxel::EMPTY, Voxel::EMPTY, Voxel::new(BlockId::SOLID, 0.75, (0.2, 0.4, 0.6))]);
+
+		// Version 2 has block ids, but everything is opaque
+		let mut payload = Vec::new();
+		for &(block, color) in [(BlockId::EMPTY, 0.0), (BlockId::WATER, 0.5)].iter() {
+			payload.extend_from_slice(&block.0.to_le_bytes());
+			for value in [1.0f32, color, color, color].iter() {
+				payload.extend_from_slice(&value.to_le_bytes());
+			}
+		}
+		let voxels = decode_chunk(&old_chunk(2, 2, &payload)).unwrap();
+		assert_eq!(voxels.to_vec(), vec![Voxel::EMPTY, Voxel::solid(BlockId::WATER, (0.5, 0.5, 0.5))]);
+		assert!(voxels.get(1).is_opaque());
+	}
+
+	#[test]
+	fn newer_versions_are_refused() {
+		let mut bytes = encode_chunk(&noisy_chunk());
+		bytes[4..6].copy_from_slice(&(CHUNK_VERSION + 1).to_le_bytes());
+		let err = decode_chunk(&bytes).unwrap_err();
+		assert!(matches!(err, SaveError::UnsupportedVersion { found: 4, newest: 3, .. }));
+		assert!(err.to_string().contains("version 4"), "{}", err);
+		assert!(matches!(decode_chunk(b"PNG\0 and so on"), Err(SaveError::WrongMagic { .. })));
+	}
+
+	#[test]
+	fn broken_chunks_are_errors() {
+		for bytes in [encode_chunk(&layered_chunk(0)), encode_chunk(&noisy_chunk())].iter() {
+			for length in 0..bytes.len() {
+				assert!(decode_chunk(&bytes[..length]).is_err(), "{} of {} bytes", length, bytes.len());
+			}
+			// Any changed byte is caught, by the checksum if nothing else
+			for index in 0..bytes.len() {
+				let mut broken = bytes.clone();
+				broken[index] ^= 0x5a;
+				assert!(decode_chunk(&broken).is_err(), "byte {}", index);
+			}
+		}
+		// A huge size in the header doesn't get allocated
+		let mut bytes = encode_chunk(&noisy_chunk());
+		bytes[6..18].copy_from_slice(&[0xff; 12]);
+		assert!(matches!(decode_chunk(&bytes), Err(SaveError::Corrupt(_))));
+	}
+
+	#[test]
+	fn regions_round_trip() {
+		let mut region = Region::new();
+		let coords = [ChunkCoord::new(0, 0, 0), ChunkCoord::new(-1, 0, 3), ChunkCoord::new(7, 1, -8)];
+		for (i, &coord) in coords.iter().enumerate() {
+			region.insert(coord, &layered_chunk(i as u16));
+		}
+		let bytes = region.to_bytes();
+		let read = Region::parse(&bytes).unwrap();
+		assert_eq!(read, region);
+		for (i, &coord) in coords.iter().enumerate() {
+			assert_eq!(read.get(coord).unwrap().unwrap(), layered_chunk(i as u16));
+		}
+		assert!(read.get(ChunkCoord::new(1, 0, 0)).is_none());
+		for length in 0..bytes.len() {
+			// Cut off in the middle of a chunk the region itself still reads, but not that chunk
+			if let Ok(region) = Region::parse(&bytes[..length]) {
+				assert!(coords.iter().any(|&coord| region.get(coord).is_none_or(|chunk| chunk.is_err())));
+			}
+		}
+	}
+
+	#[test]
+	fn saved_chunks_load_again() {
+		let directory = scratch_directory("saved_chunks_load_again");
+		let size = ChunkSize::new(16, 40, 16);
+		let coords = [ChunkCoord::new(0, 0, 0), ChunkCoord::new(-1, 0, 0), ChunkCoord::new(8, 0, 0)];
+		let chunks: Vec<_> = coords.iter().map(|&coord| (coord, terrain_chunk(coord))).collect();
+		// Chunks 0 and -1 are in different regions, like 0 and 8
+		assert_eq!(save_chunks(&directory, chunks.iter().map(|(coord, voxels)| (*coord, voxels))).unwrap(), 3);
+		let mut cache = RegionCache::new(directory.clone());
+		for (coord, voxels) in chunks.iter() {
+			assert_eq!(cache.load_chunk(*coord, size).unwrap().as_ref(), Some(voxels));
+		}
+		assert_eq!(cache.load_chunk(ChunkCoord::new(1, 0, 0), size).unwrap(), None);
+		assert_eq!(cache.load_chunk(ChunkCoord::new(0, 20, 0), size).unwrap(), None);
+		assert!(matches!(cache.load_chunk(coords[0], ChunkSize::new(8, 8, 8)), Err(SaveError::WrongSize { .. })));
+
+		// Saving another chunk of a region keeps the ones already in it, and the cache notices
+		let edited = noisy_chunk();
+		save_chunks(&directory, vec![(ChunkCoord::new(1, 0, 0), &edited)]).unwrap();
+		assert_eq!(cache.load_chunk(ChunkCoord::new(1, 0, 0), edited.size()).unwrap(), Some(edited));
+		assert_eq!(cache.load_chunk(coords[0], size).unwrap().as_ref(), Some(&chunks[0].1));
+		// Regions that didn't exist yet too
+		save_chunks(&directory, vec![(ChunkCoord::new(0, 20, 0), &chunks[0].1)]).unwrap();
+		assert_eq!(cache.load_chunk(ChunkCoord::new(0, 20, 0), size).unwrap().as_ref(), Some(&chunks[0].1));
+
+		// A broken region is an error, not a panic or an empty region
+		fs::write(region_path(&directory, ChunkCoord::new(0, 0, 0)), b"VXRG\x01\x00\xff\xff").unwrap();
+		assert!(cache.load_chunk(coords[0], size).is_err());
+		assert!(save_chunks(&directory, vec![(coords[0], &chunks[0].1)]).is_err());
+		let _ = fs::remove_dir_all(&directory);
+	}
+}
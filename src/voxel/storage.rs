@@ -0,0 +1,425 @@
+use super::ChunkSize;
+
+use std::{collections::HashMap, fmt, mem::size_of};
+
+/// What a voxel is made of.  `EMPTY` voxels aren't drawn at all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u16);
+impl BlockId {
+	pub const EMPTY: BlockId = BlockId(0);
+	/// Anything without a more specific material, like the random chunks
+	pub const SOLID: BlockId = BlockId(1);
+	pub const STONE: BlockId = BlockId(2);
+	pub const SOIL: BlockId = BlockId(3);
+	pub const GRASS: BlockId = BlockId(4);
+	pub const SAND: BlockId = BlockId(5);
+	pub const SNOW: BlockId = BlockId(6);
+	pub const WATER: BlockId = BlockId(7);
+}
+
+/// One cell of a chunk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Voxel {
+	pub block: BlockId,
+	/// How much of the cell the cube fills, 0 to 1
+	pub size: f32,
+	pub color: (f32, f32, f32),
+	/// 1 is opaque, less is see-through
+	pub opacity: f32
+}
+impl Voxel {
+	pub const EMPTY: Voxel = Voxel { block: BlockId::EMPTY, size: 0.0, color: (0.0, 0.0, 0.0), opacity: 0.0 };
+
+	/// An opaque voxel
+	pub fn new(block: BlockId, size: f32, color: (f32, f32, f32)) -> Voxel {
+		Voxel { block, size, color, opacity: 1.0 }
+	}
+	/// A full, opaque cube
+	pub fn solid(block: BlockId, color: (f32, f32, f32)) -> Voxel {
+		Voxel { block, size: 1.0, color, opacity: 1.0 }
+	}
+	pub fn with_opacity(self, opacity: f32) -> Voxel {
+		Voxel { opacity, ..self }
+	}
+	pub fn is_opaque(&self) -> bool {
+		self.opacity >= 1.0
+	}
+	pub fn is_empty(&self) -> bool {
+		self.block == BlockId::EMPTY
+	}
+	/// Compares the floats bit for bit, so it can be hashed
+	fn key(&self) -> (u16, u32, [u32; 4]) {
+		let color = [self.color.0.to_bits(), self.color.1.to_bits(), self.color.2.to_bits(), self.opacity.to_bits()];
+		(self.block.0, self.size.to_bits(), color)
+	}
+}
+impl Default for Voxel {
+	fn default() -> Voxel {
+		Voxel::EMPTY
+	}
+}
+
+/// The voxels of a chunk as a palette of the distinct voxels plus a bit-packed palette index per
+/// cell, using as few bits as the palette needs.  A chunk that's all air takes no bits per cell at
+/// all, one with a handful of materials 2 or 3.
+#[derive(Clone, Debug)]
+pub struct VoxelStorage {
+	size: ChunkSize,
+	/// `palette[0]` is always `Voxel::EMPTY`
+	palette: Vec<Voxel>,
+	bits: u32,
+	words: Vec<u64>,
+	/// How many cells aren't empty
+	filled: usize
+}
+impl VoxelStorage {
+	/// All empty
+	pub fn new(size: ChunkSize) -> VoxelStorage {
+		VoxelStorage {
+			size,
+			palette: vec![Voxel::EMPTY],
+			bits: 0,
+			words: Vec::new(),
+			filled: 0
+		}
+	}
+	/// `voxels` in `ChunkSize::index` order
+	pub fn from_voxels(size: ChunkSize, voxels: &[Voxel]) -> VoxelStorage {
+		assert_eq!(voxels.len(), size.len(), "A {:?} chunk needs {} voxels", size, size.len());
+		VoxelStorage::from_runs(size, &voxels.iter().map(|&voxel| (1, voxel)).collect::<Vec<_>>())
+	}
+	/// Runs of `(count, voxel)` in `ChunkSize::index` order.  Takes no more memory than the result,
+	/// however long the runs are.
+	pub fn from_runs(size: ChunkSize, runs: &[(usize, Voxel)]) -> VoxelStorage {
+		assert_eq!(runs.iter().map(|&(count, _)| count).sum::<usize>(), size.len(), "A {:?} chunk needs {} voxels", size, size.len());
+		let mut palette = vec![Voxel::EMPTY];
+		let mut lookup = HashMap::new();
+		lookup.insert(Voxel::EMPTY.key(), 0u32);
+		let runs: Vec<(usize, u32)> = runs
+			.iter()
+			.map(|&(count, voxel)| {
+				// All empty voxels are the same, whatever their size and color
+				let voxel = if voxel.is_empty() { Voxel::EMPTY } else { voxel };
+				let index = *lookup.entry(voxel.key()).or_insert_with(|| {
+					palette.push(voxel);
+					palette.len() as u32 - 1
+				});
+				(count, index)
+			})
+			.collect();
+		let mut storage = VoxelStorage {
+			size,
+			bits: bits_for(palette.len()),
+			palette,
+			words: Vec::new(),
+			filled: runs.iter().filter(|&&(_, index)| index != 0).map(|&(count, _)| count).sum()
+		};
+		if storage.bits > 0 {
+			storage.words = vec![0; size.len().div_ceil(64 / storage.bits as usize)];
+			let mut start = 0;
+			for (count, index) in runs {
+				for cell in start..start + count {
+					storage.write(cell, index);
+				}
+				start += count;
+			}
+		}
+		storage
+	}
+
+	pub fn size(&self) -> ChunkSize {
+		self.size
+	}
+	pub fn len(&self) -> usize {
+		self.size.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.filled == 0
+	}
+	/// How many cells aren't empty
+	pub fn filled(&self) -> usize {
+		self.filled
+	}
+	pub fn palette(&self) -> &[Voxel] {
+		&self.palette
+	}
+	/// Whether any of the voxels may be see-through.  The palette can still hold voxels that were
+	/// replaced, so this can be true for voxels that are all opaque, but never the other way around.
+	pub fn has_see_through(&self) -> bool {
+		self.palette.iter().any(|voxel| !voxel.is_empty() && !voxel.is_opaque())
+	}
+	pub fn bits_per_voxel(&self) -> u32 {
+		self.bits
+	}
+
+	pub fn get(&self, index: usize) -> Voxel {
+		self.palette[self.palette_index(index) as usize]
+	}
+	pub fn get_at(&self, x: usize, y: usize, z: usize) -> Voxel {
+		self.get(self.size.index(x, y, z))
+	}
+	pub fn set(&mut self, index: usize, voxel: Voxel) {
+		let voxel = if voxel.is_empty() { Voxel::EMPTY } else { voxel };
+		let old = self.palette_index(index);
+		let new = match self.palette.iter().position(|entry| entry.key() == voxel.key()) {
+			Some(new) => new as u32,
+			None => {
+				self.palette.push(voxel);
+				if self.palette.len() > 1 << self.bits {
+					self.grow();
+					// Growing may have dropped unused entries, so look again
+					return self.set(index, voxel);
+				}
+				self.palette.len() as u32 - 1
+			}
+		};
+		match (old == 0, new == 0) {
+			(true, false) => self.filled += 1,
+			(false, true) => self.filled -= 1,
+			_ => {}
+		}
+		self.write(index, new);
+	}
+	pub fn set_at(&mut self, x: usize, y: usize, z: usize, voxel: Voxel) {
+		self.set(self.size.index(x, y, z), voxel);
+	}
+	pub fn iter(&self) -> impl Iterator<Item = Voxel> + '_ {
+		(0..self.len()).map(move |index| self.get(index))
+	}
+	/// The cells that aren't empty, with their index
+	pub fn iter_filled(&self) -> impl Iterator<Item = (usize, Voxel)> + '_ {
+		self.iter().enumerate().filter(|(_, voxel)| !voxel.is_empty())
+	}
+	pub fn to_vec(&self) -> Vec<Voxel> {
+		self.iter().collect()
+	}
+
+	/// Bytes on the heap plus the struct itself
+	pub fn memory_bytes(&self) -> usize {
+		size_of::<VoxelStorage>() + self.palette.capacity() * size_of::<Voxel>() + self.words.capacity() * size_of::<u64>()
+	}
+	/// What a plain `Vec<Voxel>` of the chunk would take, for comparison
+	pub fn dense_bytes(&self) -> usize {
+		self.len() * size_of::<Voxel>()
+	}
+
+	fn palette_index(&self, index: usize) -> u32 {
+		assert!(index < self.len(), "voxel {} is outside of a {:?} chunk", index, self.size);
+		if self.bits == 0 {
+			return 0;
+		}
+		let (word, shift) = self.position(index);
+		((self.words[word] >> shift) & mask(self.bits)) as u32
+	}
+	fn write(&mut self, index: usize, value: u32) {
+		if self.bits == 0 {
+			return;
+		}
+		let (word, shift) = self.position(index);
+		self.words[word] = (self.words[word] & !(mask(self.bits) << shift)) | ((value as u64) << shift);
+	}
+	/// Indices never straddle two words, so some bits at the end of each word may go unused
+	fn position(&self, index: usize) -> (usize, u32) {
+		let per_word = 64 / self.bits as usize;
+		(index / per_word, (index % per_word) as u32 * self.bits)
+	}
+	fn pack(&mut self, indices: &[u32]) {
+		self.words.clear();
+		if self.bits == 0 {
+			return;
+		}
+		let per_word = 64 / self.bits as usize;
+		self.words = vec![0; indices.len().div_ceil(per_word)];
+		for (index, &value) in indices.iter().enumerate() {
+			self.write(index, value);
+		}
+	}
+	/// The palette outgrew the bits: drop the entries nothing uses any more, and repack with more
+	/// bits if that's still needed.
+	fn grow(&mut self) {
+		// The entry that was just pushed isn't used yet but is about to be
+		let pending = self.palette.pop();
+		let indices: Vec<u32> = (0..self.len()).map(|index| self.palette_index(index)).collect();
+		let mut used = vec![false; self.palette.len()];
+		used[0] = true;
+		for &index in indices.iter() {
+			used[index as usize] = true;
+		}
+		let mut remap = vec![0; self.palette.len()];
+		let mut palette = Vec::new();
+		for (old, voxel) in self.palette.iter().enumerate() {
+			if used[old] {
+				remap[old] = palette.len() as u32;
+				palette.push(*voxel);
+			}
+		}
+		palette.extend(pending);
+		self.palette = palette;
+		self.bits = bits_for(self.palette.len());
+		let indices: Vec<u32> = indices.iter().map(|&index| remap[index as usize]).collect();
+		self.pack(&indices);
+	}
+}
+impl PartialEq for VoxelStorage {
+	fn eq(&self, other: &VoxelStorage) -> bool {
+		self.size == other.size && self.iter().zip(other.iter()).all(|(a, b)| a.key() == b.key())
+	}
+}
+
+/// Enough bits to tell `entries` palette entries apart
+fn bits_for(entries: usize) -> u32 {
+	if entries <= 1 {
+		0
+	} else {
+		usize::BITS - (entries - 1).leading_zeros()
+	}
+}
+fn mask(bits: u32) -> u64 {
+	if bits >= 64 {
+		!0
+	} else {
+		(1 << bits) - 1
+	}
+}
+
+/// How much memory a chunk takes, on the CPU and the GPU
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChunkMemory {
+	/// Cells that are drawn
+	pub filled: usize,
+	pub cells: usize,
+	pub palette_entries: usize,
+	pub bits_per_voxel: u32,
+	/// The palette storage
+	pub cpu_bytes: usize,
+	/// What the cells would take uncompressed
+	pub dense_bytes: usize,
+	/// The instance buffer (with all of its copies, for persistent mapping), the greedy mesh, the
+	/// occupancy texture and the sorted see-through instances
+	pub gpu_bytes: usize
+}
+impl std::ops::Add for ChunkMemory {
+	type Output = ChunkMemory;
+	fn add(self, other: ChunkMemory) -> ChunkMemory {
+		ChunkMemory {
+			filled: self.filled + other.filled,
+			cells: self.cells + other.cells,
+			palette_entries: self.palette_entries + other.palette_entries,
+			bits_per_voxel: self.bits_per_voxel.max(other.bits_per_voxel),
+			cpu_bytes: self.cpu_bytes + other.cpu_bytes,
+			dense_bytes: self.dense_bytes + other.dense_bytes,
+			gpu_bytes: self.gpu_bytes + other.gpu_bytes
+		}
+	}
+}
+impl fmt::Display for ChunkMemory {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{}/{} filled, {} palette entries at {} bits: {:.1} KiB on the CPU ({:.1} KiB dense), {:.1} KiB on the GPU",
+			self.filled,
+			self.cells,
+			self.palette_entries,
+			self.bits_per_voxel,
+			self.cpu_bytes as f32 / 1024.0,
+			self.dense_bytes as f32 / 1024.0,
+			self.gpu_bytes as f32 / 1024.0
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn voxel(value: u16) -> Voxel {
+		Voxel::solid(BlockId(value), (value as f32 / 100.0, 0.5, 0.5))
+	}
+
+	#[test]
+	fn bits_grow_with_the_palette() {
+		assert_eq!(bits_for(1), 0);
+		assert_eq!(bits_for(2), 1);
+		assert_eq!(bits_for(3), 2);
+		assert_eq!(bits_for(4), 2);
+		assert_eq!(bits_for(5), 3);
+		assert_eq!(bits_for(257), 9);
+	}
+
+	#[test]
+	fn empty_chunks_take_no_bits() {
+		let size = ChunkSize::new(16, 16, 16);
+		let storage = VoxelStorage::new(size);
+		assert_eq!(storage.bits_per_voxel(), 0);
+		assert!(storage.is_empty());
+		assert!(storage.memory_bytes() < 256);
+		assert!(storage.iter().all(|voxel| voxel.is_empty()));
+	}
+
+	#[test]
+	fn round_trips_through_the_palette() {
+		let size = ChunkSize::new(7, 5, 3);
+		// Every index gets one of 20 voxels (or empty)
+		let voxels: Vec<Voxel> = (0..size.len()).map(|i| if i % 3 == 0 { Voxel::EMPTY } else { voxel((i % 20) as u16 + 1) }).collect();
+		let storage = VoxelStorage::from_voxels(size, &voxels);
+		assert_eq!(storage.to_vec(), voxels);
+		assert_eq!(storage.bits_per_voxel(), 5);
+		assert_eq!(storage.filled(), voxels.iter().filter(|voxel| !voxel.is_empty()).count());
+		assert!(storage.memory_bytes() < storage.dense_bytes());
+	}
+
+	#[test]
+	fn runs_are_the_same_as_their_voxels() {
+		let size = ChunkSize::new(4, 5, 3);
+		let runs = [(7, voxel(1)), (20, Voxel::EMPTY), (1, voxel(2)), (30, voxel(1)), (2, voxel(3))];
+		let voxels: Vec<Voxel> = runs.iter().flat_map(|&(count, voxel)| std::iter::repeat_n(voxel, count)).collect();
+		let storage = VoxelStorage::from_runs(size, &runs);
+		assert_eq!(storage.to_vec(), voxels);
+		assert_eq!(storage, VoxelStorage::from_voxels(size, &voxels));
+		assert_eq!(storage.filled(), 40);
+		assert_eq!(storage.bits_per_voxel(), 2);
+	}
+
+	#[test]
+	fn setting_grows_and_compacts_the_palette() {
+		let size = ChunkSize::new(4, 4, 4);
+		let mut storage = VoxelStorage::new(size);
+		let mut expected = vec![Voxel::EMPTY; size.len()];
+		// Write a new voxel over and over into the same few cells: the old ones fall out of the palette
+		for i in 0..500 {
+			let index = (i * 7) % 8;
+			storage.set(index, voxel(i as u16 + 1));
+			expected[index] = voxel(i as u16 + 1);
+			assert_eq!(storage.get(index), expected[index]);
+		}
+		assert_eq!(storage.to_vec(), expected);
+		assert!(storage.palette().len() <= 16, "{} palette entries", storage.palette().len());
+		assert_eq!(storage.filled(), 8);
+
+		// Clearing them again
+		for index in 0..8 {
+			storage.set(index, Voxel::EMPTY);
+		}
+		assert!(storage.is_empty());
+		assert!(storage.iter().all(|voxel| voxel.is_empty()));
+	}
+
+	#[test]
+	fn see_through_voxels_are_noticed() {
+		let mut storage = VoxelStorage::new(ChunkSize::new(2, 2, 2));
+		storage.set(0, voxel(1));
+		assert!(!storage.has_see_through());
+		storage.set(1, voxel(2).with_opacity(0.5));
+		assert!(storage.has_see_through());
+	}
+
+	#[test]
+	fn empty_voxels_are_all_the_same() {
+		let size = ChunkSize::new(2, 2, 2);
+		let mut voxels = vec![Voxel::EMPTY; size.len()];
+		voxels[3] = Voxel::new(BlockId::EMPTY, 1.0, (1.0, 0.0, 0.0));
+		let storage = VoxelStorage::from_voxels(size, &voxels);
+		assert_eq!(storage.palette().len(), 1);
+		assert_eq!(storage.get(3), Voxel::EMPTY);
+	}
+}
@@ -0,0 +1,289 @@
+use super::{
+	noise::{Fractal, Perlin},
+	BlockId, ChunkCoord, ChunkGenerator, ChunkSize, Voxel,
+};
+
+/// Show `VoxelProject::terrain` instead of the drifting random voxels
+pub const FLAG: &str = "--terrain";
+
+/// What the surface looks like in a column, picked from its height, temperature and moisture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Biome {
+	Ocean,
+	Beach,
+	Desert,
+	Grassland,
+	Forest,
+	Mountain,
+	Snow
+}
+impl Biome {
+	/// The block the top voxel of a column is made of
+	pub fn surface_block(self) -> BlockId {
+		match self {
+			Biome::Beach | Biome::Desert => BlockId::SAND,
+			Biome::Grassland | Biome::Forest => BlockId::GRASS,
+			Biome::Ocean => BlockId::SOIL,
+			Biome::Mountain => BlockId::STONE,
+			Biome::Snow => BlockId::SNOW
+		}
+	}
+	/// The color of the top voxel of a column
+	pub fn surface_color(self) -> (f32, f32, f32) {
+		match self {
+			Biome::Ocean => (0.55, 0.5, 0.35),
+			Biome::Beach => (0.9, 0.85, 0.6),
+			Biome::Desert => (0.93, 0.78, 0.45),
+			Biome::Grassland => (0.4, 0.7, 0.25),
+			Biome::Forest => (0.15, 0.45, 0.15),
+			Biome::Mountain => (0.5, 0.48, 0.45),
+			Biome::Snow => (0.95, 0.95, 1.0)
+		}
+	}
+	/// The color of the few voxels below the top one
+	pub fn soil_color(self) -> (f32, f32, f32) {
+		match self {
+			Biome::Ocean | Biome::Beach | Biome::Desert => (0.8, 0.7, 0.45),
+			Biome::Grassland | Biome::Forest => (0.45, 0.3, 0.15),
+			Biome::Mountain | Biome::Snow => (0.45, 0.43, 0.4)
+		}
+	}
+}
+
+const WATER: (f32, f32, f32) = (0.15, 0.35, 0.8);
+const WATER_OPACITY: f32 = 0.6;
+const STONE: (f32, f32, f32) = (0.35, 0.35, 0.38);
+/// How many voxels of soil lie between the surface and the stone
+const SOIL_DEPTH: i64 = 3;
+
+/// Hills, mountains, caves and biomes from layers of noise.  Everything is a function of the world
+/// position of a voxel (never of where it is in its chunk), so neighbouring chunks join up and a
+/// seed always gives the same world.
+///
+/// Solid voxels have a size of 1 and air is `Voxel::EMPTY`.  World y = 0 is the bottom of the
+/// chunks at y = 0.
+pub struct TerrainGenerator {
+	pub seed: u64,
+	/// Water fills everything below this height that isn't solid
+	pub sea_level: f64,
+	/// The height the hills vary around
+	pub base_height: f64,
+	/// How far the hills go up or down from `base_height`
+	pub hill_height: f64,
+	/// How much higher mountains go
+	pub mountain_height: f64,
+	/// Caves are where the 3D noise is above this (so higher means fewer, narrower caves)
+	pub cave_threshold: f64,
+	/// How far (in voxels) the hills are pushed around by the domain warp
+	pub warp_strength: f64,
+
+	hills: Perlin,
+	mountains: Perlin,
+	/// Where the mountains are
+	mountain_mask: Perlin,
+	caves: Perlin,
+	temperature: Perlin,
+	moisture: Perlin
+}
+impl TerrainGenerator {
+	pub fn new(seed: u64) -> TerrainGenerator {
+		// Every layer gets its own permutation so they don't line up
+		let layer = |index: u64| Perlin::new(seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(index));
+		TerrainGenerator {
+			seed,
+			sea_level: 22.0,
+			base_height: 26.0,
+			hill_height: 8.0,
+			mountain_height: 24.0,
+			cave_threshold: 0.25,
+			warp_strength: 30.0,
+			hills: layer(0),
+			mountains: layer(1),
+			mountain_mask: layer(2),
+			caves: layer(3),
+			temperature: layer(4),
+			moisture: layer(5)
+		}
+	}
+
+	/// How far up the ground goes at world column `x, z`
+	pub fn height(&self, x: f64, z: f64) -> f64 {
+		let hills = Fractal::new(4, 1.0 / 64.0).warped2(&self.hills, x, z, self.warp_strength);
+		let mountains = Fractal::new(5, 1.0 / 96.0).ridged2(&self.mountains, x, z);
+		// Only some areas get mountains, fading in smoothly
+		let mask = Fractal::new(2, 1.0 / 256.0).fbm2(&self.mountain_mask, x, z);
+		let mask = smoothstep(0.0, 0.35, mask);
+		self.base_height + hills * self.hill_height + mountains * mask * self.mountain_height
+	}
+	/// The biome of world column `x, z` whose ground ends at `height`
+	pub fn biome(&self, x: f64, z: f64, height: f64) -> Biome {
+		let climate = Fractal::new(3, 1.0 / 200.0);
+		// Higher is colder
+		let temperature = climate.fbm2(&self.temperature, x, z) - (height - self.sea_level).max(0.0) / 60.0;
+		let moisture = climate.fbm2(&self.moisture, x, z);
+		if height < self.sea_level - 1.0 {
+			Biome::Ocean
+		} else if height < self.sea_level + 1.5 {
+			Biome::Beach
+		} else if temperature < -0.3 || height > self.base_height + self.mountain_height * 0.7 {
+			Biome::Snow
+		} else if height > self.base_height + self.hill_height + self.mountain_height * 0.3 {
+			Biome::Mountain
+		} else if temperature > 0.2 && moisture < -0.1 {
+			Biome::Desert
+		} else if moisture > 0.1 {
+			Biome::Forest
+		} else {
+			Biome::Grassland
+		}
+	}
+	/// Whether the voxel at world position `x, y, z` is hollowed out by a cave
+	pub fn is_cave(&self, x: f64, y: f64, z: f64) -> bool {
+		// Squashed vertically, so the caves run more sideways than up and down
+		Fractal::new(3, 1.0 / 32.0).fbm3(&self.caves, x, y * 1.5, z) > self.cave_threshold
+	}
+
+	/// The voxel at world position `x, y, z`, given the column's height and biome
+	fn voxel(&self, x: i64, y: i64, z: i64, height: i64, biome: Biome) -> Voxel {
+		if y > height {
+			return if (y as f64) <= self.sea_level { Voxel::solid(BlockId::WATER, WATER).with_opacity(WATER_OPACITY) } else { Voxel::EMPTY };
+		}
+		// Keep a floor at the bottom, and don't open caves right below the sea or the surface
+		let roof = if (height as f64) < self.sea_level { height - 4 } else { height - 1 };
+		if y > 0 && y <= roof && self.is_cave(x as f64, y as f64, z as f64) {
+			return Voxel::EMPTY;
+		}
+		if y == height {
+			Voxel::solid(biome.surface_block(), biome.surface_color())
+		} else if y > height - SOIL_DEPTH {
+			Voxel::solid(BlockId::SOIL, biome.soil_color())
+		} else {
+			// A little variation so the stone doesn't look flat
+			let shade = 0.9 + 0.1 * self.caves.noise3(x as f64 * 0.31, y as f64 * 0.31, z as f64 * 0.31) as f32;
+			Voxel::solid(BlockId::STONE, (STONE.0 * shade, STONE.1 * shade, STONE.2 * shade))
+		}
+	}
+}
+impl ChunkGenerator for TerrainGenerator {
+	fn generate(&self, coord: ChunkCoord, size: ChunkSize) -> Vec<Voxel> {
+		let base_x = coord.x as i64 * size.width as i64;
+		let base_y = coord.y as i64 * size.height as i64;
+		let base_z = coord.z as i64 * size.depth as i64;
+		let mut voxels = vec![Voxel::EMPTY; size.len()];
+		for x in 0..size.width {
+			for z in 0..size.depth {
+				let (world_x, world_z) = (base_x + x as i64, base_z + z as i64);
+				let height = self.height(world_x as f64, world_z as f64);
+				let biome = self.biome(world_x as f64, world_z as f64, height);
+				let height = height.floor() as i64;
+				for y in 0..size.height {
+					voxels[size.index(x, y, z)] = self.voxel(world_x, base_y + y as i64, world_z, height, biome);
+				}
+			}
+		}
+		voxels
+	}
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+	let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+	t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn terrain_is_deterministic() {
+		let size = ChunkSize::new(8, 40, 8);
+		let coord = ChunkCoord::new(-3, 0, 7);
+		let a = TerrainGenerator::new(42).generate(coord, size);
+		assert_eq!(a, TerrainGenerator::new(42).generate(coord, size));
+		assert_ne!(a, TerrainGenerator::new(43).generate(coord, size));
+		assert_ne!(a, TerrainGenerator::new(42).generate(ChunkCoord::new(-2, 0, 7), size));
+	}
+
+	#[test]
+	fn neighbouring_chunks_are_seamless() {
+		// Chunks (0, 0, 0) and (1, 0, 0) of width 8 have to be exactly the two halves of chunk
+		// (0, 0, 0) of width 16, and the same along z and y
+		let generator = TerrainGenerator::new(7);
+		let small = ChunkSize::new(8, 20, 8);
+		for &(coord, big) in [
+			(ChunkCoord::new(5, 1, -4), ChunkSize::new(16, 20, 8)),
+			(ChunkCoord::new(-3, 0, 2), ChunkSize::new(8, 20, 16)),
+			(ChunkCoord::new(2, 0, 9), ChunkSize::new(8, 40, 8))
+		]
+		.iter()
+		{
+			let whole = generator.generate(coord, big);
+			// The small chunks that make up the big one
+			let (nx, ny, nz) = (big.width / small.width, big.height / small.height, big.depth / small.depth);
+			for (i, j, k) in (0..nx).flat_map(|i| (0..ny).flat_map(move |j| (0..nz).map(move |k| (i, j, k)))) {
+				let part_coord = ChunkCoord::new(
+					coord.x * nx as i32 + i as i32,
+					coord.y * ny as i32 + j as i32,
+					coord.z * nz as i32 + k as i32
+				);
+				let part = generator.generate(part_coord, small);
+				for (index, item) in part.iter().enumerate() {
+					let (x, y, z) = small.position(index);
+					let expected = whole[big.index(x + i * small.width, y + j * small.height, z + k * small.depth)];
+					assert_eq!(*item, expected, "{:?} at {:?}", part_coord, (x, y, z));
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn surface_is_continuous_across_chunk_borders() {
+		let generator = TerrainGenerator::new(11);
+		let size = ChunkSize::new(16, 60, 16);
+		// The surface height on both sides of the border between chunk x = 0 and x = 1 never jumps
+		// further than the steepest slope inside the chunks
+		let top = |voxels: &[Voxel], x: usize, z: usize| (0..size.height).rev().find(|&y| {
+			let voxel = voxels[size.index(x, y, z)];
+			!voxel.is_empty() && voxel.block != BlockId::WATER
+		});
+		let left = generator.generate(ChunkCoord::new(0, 0, 0), size);
+		let right = generator.generate(ChunkCoord::new(1, 0, 0), size);
+		let mut steepest_inside = 0;
+		for z in 0..size.depth {
+			for x in 1..size.width {
+				if let (Some(a), Some(b)) = (top(&left, x - 1, z), top(&left, x, z)) {
+					steepest_inside = steepest_inside.max((a as i64 - b as i64).abs());
+				}
+			}
+		}
+		for z in 0..size.depth {
+			if let (Some(a), Some(b)) = (top(&left, size.width - 1, z), top(&right, 0, z)) {
+				assert!((a as i64 - b as i64).abs() <= steepest_inside + 1, "the surface jumps from {} to {} at z = {}", a, b, z);
+			}
+		}
+	}
+
+	#[test]
+	fn terrain_has_ground_water_and_air() {
+		let generator = TerrainGenerator::new(3);
+		let size = ChunkSize::new(32, 60, 32);
+		let voxels = generator.generate(ChunkCoord::new(0, 0, 0), size);
+		let solid = voxels.iter().filter(|voxel| !voxel.is_empty()).count();
+		assert!(solid > 0 && solid < voxels.len());
+		// The bottom layer is always solid
+		for x in 0..size.width {
+			for z in 0..size.depth {
+				assert!(!voxels[size.index(x, 0, z)].is_empty());
+			}
+		}
+		// Nothing floats above the sea except ground
+		for x in 0..size.width {
+			for z in 0..size.depth {
+				let height = generator.height(x as f64, z as f64).floor() as usize;
+				for y in (height + 1).max(generator.sea_level as usize + 1)..size.height {
+					assert!(voxels[size.index(x, y, z)].is_empty());
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,444 @@
+//! See-through voxels.  The opaque ones are drawn first, then the others either sorted back to front
+//! and blended in that order, or with weighted blended order-independent transparency (McGuire &
+//! Bavoil, "Weighted Blended Order-Independent Transparency", JCGT 2013), which needs no sorting
+//! but only approximates the order.
+use crate::{gl, shader::*, shader_file};
+use gl::types::*;
+
+use cgmath::{MetricSpace, Point3, Vector2};
+
+use std::ptr;
+
+/// How see-through voxels are drawn
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transparency {
+	/// As if they were opaque
+	Opaque,
+	/// Blended back to front: chunk by chunk, and the voxels within each chunk
+	Sorted,
+	/// Blended in any order into `WeightedBlended`'s targets, then composited
+	WeightedBlended
+}
+impl Transparency {
+	/// The next mode, skipping `WeightedBlended` if it isn't `available`
+	pub fn next(self, available: bool) -> Transparency {
+		match self {
+			Transparency::Opaque => Transparency::Sorted,
+			Transparency::Sorted if available => Transparency::WeightedBlended,
+			Transparency::Sorted | Transparency::WeightedBlended => Transparency::Opaque
+		}
+	}
+}
+
+/// Which voxels a draw call includes and what the fragment shader writes: the `voxel_pass` uniform
+/// of voxel.glslg and voxel.glslf
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxelPass {
+	/// Every voxel, as if it were opaque
+	All = 0,
+	Opaque = 1,
+	/// The see-through voxels, with their opacity as alpha
+	Blended = 2,
+	/// The see-through voxels, into the `WeightedBlended` targets
+	Accumulate = 3
+}
+impl VoxelPass {
+	pub fn apply(self, chunk_shader: &ShaderProgram) {
+		chunk_shader.set_optional("voxel_pass", &(self as i32));
+	}
+}
+
+/// The indices of `positions` from the farthest from `eye` to the nearest
+pub fn back_to_front(positions: &[Point3<f32>], eye: Point3<f32>) -> Vec<u32> {
+	let mut order: Vec<_> = (0..positions.len() as u32).collect();
+	order.sort_by(|&a, &b| {
+		let (a, b) = (positions[a as usize].distance2(eye), positions[b as usize].distance2(eye));
+		b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal)
+	});
+	order
+}
+
+/// How much a fragment counts towards the average color, by its alpha and its distance from the
+/// camera: equation 10 of the paper.  This is the reference for `weight` in voxel.glslf.
+pub fn weight(depth: f32, alpha: f32) -> f32 {
+	alpha * (0.03 / (1e-5 + (depth / 200.0).powi(4))).clamp(1e-2, 3e3)
+}
+
+/// A see-through fragment covering a pixel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fragment {
+	pub color: [f32; 3],
+	pub alpha: f32,
+	/// From the camera
+	pub depth: f32
+}
+
+/// `fragments` blended over `background` from the farthest to the nearest: what `Sorted` draws, and
+/// the right answer.
+pub fn composite_sorted(fragments: &[Fragment], background: [f32; 3]) -> [f32; 3] {
+	let mut sorted = fragments.to_vec();
+	sorted.sort_by(|a, b| b.depth.partial_cmp(&a.depth).unwrap_or(std::cmp::Ordering::Equal));
+	sorted.iter().fold(background, |behind, fragment| {
+		let mut color = [0.0; 3];
+		for i in 0..3 {
+			color[i] = fragment.color[i] * fragment.alpha + behind[i] * (1.0 - fragment.alpha);
+		}
+		color
+	})
+}
+/// What `WeightedBlended` makes of `fragments` over `background`, in whatever order they come.  The
+/// coverage is exact, the color an average weighted by `weight`.  This is the reference for the
+/// accumulation in voxel.glslf and voxel_composite.glslf.
+pub fn composite_weighted(fragments: &[Fragment], background: [f32; 3]) -> [f32; 3] {
+	let mut sum = [0.0; 4];
+	let mut revealage = 1.0;
+	for fragment in fragments.iter() {
+		let weight = weight(fragment.depth, fragment.alpha);
+		for (sum, channel) in sum.iter_mut().zip(fragment.color.iter()) {
+			*sum += channel * fragment.alpha * weight;
+		}
+		sum[3] += fragment.alpha * weight;
+		revealage *= 1.0 - fragment.alpha;
+	}
+	let mut color = background;
+	if revealage < 1.0 {
+		for i in 0..3 {
+			color[i] = sum[i] / sum[3].clamp(1e-4, 5e4) * (1.0 - revealage) + background[i] * revealage;
+		}
+	}
+	color
+}
+
+/// A chunk's see-through instances in `back_to_front` order, as an element buffer for
+/// `Chunk::draw_sorted`
+pub struct SortedInstances {
+	buffer: GLuint,
+	count: usize,
+	/// What they were sorted for: the chunk's generation and the camera position
+	sorted_for: Option<(u64, Point3<f32>)>
+}
+impl SortedInstances {
+	pub fn new() -> SortedInstances {
+		let mut buffer = 0;
+		unsafe {
+			gl::GenBuffers(1, &mut buffer);
+		}
+		SortedInstances { buffer, count: 0, sorted_for: None }
+	}
+	pub fn is_sorted_for(&self, generation: u64, eye: Point3<f32>) -> bool {
+		self.sorted_for == Some((generation, eye))
+	}
+	pub fn upload(&mut self, indices: &[u32], generation: u64, eye: Point3<f32>) {
+		unsafe {
+			gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.buffer);
+			gl::BufferData(
+				gl::COPY_WRITE_BUFFER,
+				(indices.len() * 4) as GLsizeiptr,
+				indices.as_ptr() as *const _,
+				gl::STREAM_DRAW
+			);
+			gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+		}
+		self.count = indices.len();
+		self.sorted_for = Some((generation, eye));
+	}
+	/// Draw the instances of the bound VAO in this order
+	pub fn draw(&self) {
+		unsafe {
+			// Part of the VAO's state, so it has to be bound again every time
+			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.buffer);
+			gl::DrawElements(gl::POINTS, self.count as GLsizei, gl::UNSIGNED_INT, ptr::null());
+		}
+	}
+	pub fn bytes(&self) -> usize {
+		self.count * 4
+	}
+}
+impl Drop for SortedInstances {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteBuffers(1, &self.buffer);
+		}
+	}
+}
+
+/// The accumulation and revealage targets of weighted blended transparency, sized to the viewport,
+/// and the pass that composites them onto the scene.
+///
+/// `begin` copies the scene's depth buffer into its own, so that the opaque voxels hide the
+/// see-through ones behind them without being drawn again.
+pub struct WeightedBlended {
+	composite: ReloadableProgram,
+	/// Has no attributes, but drawing needs one bound
+	vao: GLuint,
+	fbo: GLuint,
+	/// RGBA16F: the weighted sum of the premultiplied colors, and of the weights
+	accumulation: GLuint,
+	/// R16F: how much of the background still shows through
+	revealage: GLuint,
+	/// In the same format as the target's, which copying it needs
+	depth: GLuint,
+	depth_format: GLenum,
+	size: (u32, u32),
+	/// The framebuffer and draw buffer to composite onto
+	target: (GLint, GLint)
+}
+impl WeightedBlended {
+	/// The targets are created by the first `begin`
+	pub fn new() -> Result<WeightedBlended, ShaderError> {
+		let composite = ReloadableProgram::new(vec![
+			shader_file!(Stage::Vertex, "voxel_composite.glslv"),
+			shader_file!(Stage::Fragment, "voxel_composite.glslf"),
+		])?;
+		let mut oit = WeightedBlended {
+			composite,
+			vao: 0,
+			fbo: 0,
+			accumulation: 0,
+			revealage: 0,
+			depth: 0,
+			depth_format: 0,
+			size: (0, 0),
+			target: (0, 0)
+		};
+		unsafe {
+			gl::GenVertexArrays(1, &mut oit.vao);
+			gl::GenFramebuffers(1, &mut oit.fbo);
+			gl::GenTextures(1, &mut oit.accumulation);
+			gl::GenTextures(1, &mut oit.revealage);
+			gl::GenRenderbuffers(1, &mut oit.depth);
+		}
+		Ok(oit)
+	}
+	fn resize(&mut self, width: u32, height: u32, depth_format: GLenum) -> Result<(), String> {
+		unsafe {
+			for &(texture, format) in [(self.accumulation, gl::RGBA16F), (self.revealage, gl::R16F)].iter() {
+				gl::BindTexture(gl::TEXTURE_2D, texture);
+				gl::TexImage2D(
+					gl::TEXTURE_2D,
+					0,
+					format as GLint,
+					width as GLsizei,
+					height as GLsizei,
+					0,
+					gl::RGBA,
+					gl::FLOAT,
+					ptr::null()
+				);
+				gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+				gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+			}
+			gl::BindTexture(gl::TEXTURE_2D, 0);
+			gl::BindRenderbuffer(gl::RENDERBUFFER, self.depth);
+			gl::RenderbufferStorage(gl::RENDERBUFFER, depth_format, width as GLsizei, height as GLsizei);
+			gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+			gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+			gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.accumulation, 0);
+			gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT1, gl::TEXTURE_2D, self.revealage, 0);
+			let stencil = depth_format == gl::DEPTH24_STENCIL8 || depth_format == gl::DEPTH32F_STENCIL8;
+			gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::STENCIL_ATTACHMENT, gl::RENDERBUFFER, if stencil { self.depth } else { 0 });
+			gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, self.depth);
+			let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+			gl::BindFramebuffer(gl::FRAMEBUFFER, self.target.0 as GLuint);
+			if status != gl::FRAMEBUFFER_COMPLETE {
+				return Err(format!("The transparency framebuffer is incomplete: 0x{:X}", status));
+			}
+		}
+		self.size = (width, height);
+		self.depth_format = depth_format;
+		Ok(())
+	}
+	/// Start adding up the see-through voxels into the targets, cleared, behind the depth of what's
+	/// been drawn so far.
+	pub fn begin(&mut self, viewport: Vector2<f32>) -> Result<(), String> {
+		let depth_format;
+		unsafe {
+			gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut self.target.0);
+			gl::GetIntegerv(gl::DRAW_BUFFER, &mut self.target.1);
+			depth_format = target_depth_format(self.target.0 == 0)?;
+		}
+		let size = (viewport.x as u32, viewport.y as u32);
+		if size != self.size || depth_format != self.depth_format {
+			self.resize(size.0, size.1, depth_format)?;
+		}
+		unsafe {
+			let mut read = 0;
+			gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut read);
+			gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.target.0 as GLuint);
+			gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.fbo);
+			let (width, height) = (size.0 as GLint, size.1 as GLint);
+			gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+			gl::BindFramebuffer(gl::READ_FRAMEBUFFER, read as GLuint);
+
+			gl::DrawBuffers(2, [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1].as_ptr());
+			gl::ClearBufferfv(gl::COLOR, 0, [0.0f32; 4].as_ptr());
+			gl::ClearBufferfv(gl::COLOR, 1, [1.0f32; 4].as_ptr());
+			gl::DepthMask(gl::FALSE);
+			gl::Enable(gl::BLEND);
+			gl::BlendFunci(0, gl::ONE, gl::ONE);
+			gl::BlendFunci(1, gl::ZERO, gl::ONE_MINUS_SRC_COLOR);
+		}
+		Ok(())
+	}
+	/// Composite the see-through voxels onto the framebuffer that was bound before `begin`.
+	pub fn finish(&self) {
+		self.composite.refresh();
+		let composite = self.composite.program();
+		composite.set_optional("accumulation", &0);
+		composite.set_optional("revealage", &1);
+		unsafe {
+			gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.target.0 as GLuint);
+			gl::DrawBuffer(self.target.1 as GLenum);
+			gl::ActiveTexture(gl::TEXTURE0);
+			gl::BindTexture(gl::TEXTURE_2D, self.accumulation);
+			gl::ActiveTexture(gl::TEXTURE1);
+			gl::BindTexture(gl::TEXTURE_2D, self.revealage);
+			gl::ActiveTexture(gl::TEXTURE0);
+
+			// The average color covers 1 - revealage of what's behind
+			gl::BlendFunc(gl::ONE_MINUS_SRC_ALPHA, gl::SRC_ALPHA);
+			gl::Disable(gl::DEPTH_TEST);
+			composite.bind();
+			gl::BindVertexArray(self.vao);
+			gl::DrawArrays(gl::TRIANGLES, 0, 3);
+			gl::BindVertexArray(0);
+			gl::Enable(gl::DEPTH_TEST);
+			gl::DepthMask(gl::TRUE);
+			gl::Disable(gl::BLEND);
+
+			gl::ActiveTexture(gl::TEXTURE1);
+			gl::BindTexture(gl::TEXTURE_2D, 0);
+			gl::ActiveTexture(gl::TEXTURE0);
+			gl::BindTexture(gl::TEXTURE_2D, 0);
+		}
+	}
+}
+
+/// The format of the bound draw framebuffer's depth buffer.  `glBlitFramebuffer` only copies depth
+/// between buffers of the same format, and not out of multisampled ones.
+unsafe fn target_depth_format(default_framebuffer: bool) -> Result<GLenum, String> {
+	let (depth, stencil) = if default_framebuffer {
+		(gl::DEPTH, gl::STENCIL)
+	} else {
+		(gl::DEPTH_ATTACHMENT, gl::STENCIL_ATTACHMENT)
+	};
+	let query = |attachment, name| {
+		let mut value = 0;
+		gl::GetFramebufferAttachmentParameteriv(gl::DRAW_FRAMEBUFFER, attachment, name, &mut value);
+		value
+	};
+	let mut samples = 0;
+	gl::GetIntegerv(gl::SAMPLES, &mut samples);
+	if samples > 0 {
+		return Err("Can't share the depth buffer of a multisampled framebuffer".to_string());
+	}
+	if query(depth, gl::FRAMEBUFFER_ATTACHMENT_OBJECT_TYPE) as GLenum == gl::NONE {
+		return Err("The framebuffer has no depth buffer to share".to_string());
+	}
+	let depth_bits = query(depth, gl::FRAMEBUFFER_ATTACHMENT_DEPTH_SIZE);
+	let stencil_bits = if query(stencil, gl::FRAMEBUFFER_ATTACHMENT_OBJECT_TYPE) as GLenum == gl::NONE {
+		0
+	} else {
+		query(stencil, gl::FRAMEBUFFER_ATTACHMENT_STENCIL_SIZE)
+	};
+	let float = query(depth, gl::FRAMEBUFFER_ATTACHMENT_COMPONENT_TYPE) as GLenum == gl::FLOAT;
+	match (depth_bits, stencil_bits, float) {
+		(16, 0, false) => Ok(gl::DEPTH_COMPONENT16),
+		(24, 0, false) => Ok(gl::DEPTH_COMPONENT24),
+		(24, 8, false) => Ok(gl::DEPTH24_STENCIL8),
+		(32, 0, false) => Ok(gl::DEPTH_COMPONENT32),
+		(32, 0, true) => Ok(gl::DEPTH_COMPONENT32F),
+		(32, 8, true) => Ok(gl::DEPTH32F_STENCIL8),
+		_ => Err(format!("Can't share a depth buffer with {} bits of depth and {} of stencil", depth_bits, stencil_bits))
+	}
+}
+
+impl Drop for WeightedBlended {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteVertexArrays(1, &self.vao);
+			gl::DeleteFramebuffers(1, &self.fbo);
+			gl::DeleteTextures(1, &self.accumulation);
+			gl::DeleteTextures(1, &self.revealage);
+			gl::DeleteRenderbuffers(1, &self.depth);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::prelude::*;
+
+	fn assert_close(a: [f32; 3], b: [f32; 3], tolerance: f32) {
+		for i in 0..3 {
+			assert!((a[i] - b[i]).abs() <= tolerance, "{:?} isn't {:?}", a, b);
+		}
+	}
+	fn random_fragments(rng: &mut StdRng, count: usize) -> Vec<Fragment> {
+		(0..count)
+			.map(|_| Fragment {
+				color: [rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0)],
+				alpha: rng.gen_range(0.2, 0.8),
+				depth: rng.gen_range(1.0, 100.0)
+			})
+			.collect()
+	}
+
+	#[test]
+	fn sorting_is_back_to_front() {
+		let positions = [Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, 5.0), Point3::new(3.0, 0.0, 0.0), Point3::new(0.0, -0.5, 0.0)];
+		assert_eq!(back_to_front(&positions, Point3::new(0.0, 0.0, 0.0)), vec![1, 2, 0, 3]);
+		assert_eq!(back_to_front(&positions, Point3::new(0.0, 0.0, 6.0)), vec![2, 3, 0, 1]);
+		assert!(back_to_front(&[], Point3::new(0.0, 0.0, 0.0)).is_empty());
+	}
+
+	#[test]
+	fn sorted_compositing_is_over() {
+		let background = [0.0, 0.0, 1.0];
+		assert_eq!(composite_sorted(&[], background), background);
+		let red = Fragment { color: [1.0, 0.0, 0.0], alpha: 0.5, depth: 2.0 };
+		let green = Fragment { color: [0.0, 1.0, 0.0], alpha: 0.5, depth: 1.0 };
+		// Green is in front, so there's more of it
+		let expected = [0.25, 0.5, 0.25];
+		assert_close(composite_sorted(&[red, green], background), expected, 1e-6);
+		assert_close(composite_sorted(&[green, red], background), expected, 1e-6);
+	}
+
+	#[test]
+	fn weighted_blending_matches_sorting_where_it_can() {
+		let background = [0.3, 0.6, 0.9];
+		let mut rng = StdRng::seed_from_u64(4);
+		// One layer, or layers that all have the same color: exact
+		for fragments in random_fragments(&mut rng, 20).chunks(1) {
+			assert_close(composite_weighted(fragments, background), composite_sorted(fragments, background), 1e-5);
+		}
+		let glass: Vec<_> = random_fragments(&mut rng, 5).iter().map(|fragment| Fragment { color: [0.2, 0.8, 0.7], ..*fragment }).collect();
+		assert_close(composite_weighted(&glass, background), composite_sorted(&glass, background), 1e-5);
+
+		// Otherwise the order is only approximated: off by a lot now and then, but close on average
+		let mut total = 0.0;
+		for _ in 0..50 {
+			let fragments = random_fragments(&mut rng, 3);
+			let (weighted, sorted) = (composite_weighted(&fragments, background), composite_sorted(&fragments, background));
+			assert_close(weighted, sorted, 0.5);
+			total += weighted.iter().zip(sorted.iter()).map(|(a, b)| (a - b).abs()).sum::<f32>() / 3.0;
+		}
+		assert!(total / 50.0 < 0.1, "mean error {}", total / 50.0);
+	}
+
+	#[test]
+	fn weighted_blending_ignores_the_order() {
+		let mut rng = StdRng::seed_from_u64(5);
+		let mut fragments = random_fragments(&mut rng, 6);
+		let first = composite_weighted(&fragments, [0.0, 0.0, 0.0]);
+		for _ in 0..10 {
+			fragments.shuffle(&mut rng);
+			assert_close(composite_weighted(&fragments, [0.0, 0.0, 0.0]), first, 1e-5);
+		}
+		// Nearer fragments weigh more
+		assert!(weight(1.0, 0.5) > weight(50.0, 0.5));
+		assert!(weight(50.0, 0.5) > weight(50.0, 0.25));
+	}
+}
@@ -0,0 +1,517 @@
+//! MagicaVoxel's .vox files: https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt
+//!
+//! Only the models (SIZE / XYZI) and the palette (RGBA) are read; the scene graph, materials and
+//! layers are skipped.  MagicaVoxel has z up, which becomes y here.
+
+use super::{
+	bytes::{Reader, Truncated},
+	BlockId, ChunkCoord, ChunkGenerator, ChunkSize, Voxel
+};
+
+use cgmath::{Point3, Vector3};
+
+use std::{collections::HashMap, error::Error, fmt, fs, io, path::Path};
+
+pub const FLAG: &str = "--vox";
+
+const MAGIC: &[u8; 4] = b"VOX ";
+const VERSION: u32 = 150;
+/// Models can't be bigger than this along any axis, since voxel coordinates are bytes
+pub const MAX_SIZE: u32 = 256;
+
+/// One voxel of a model, in MagicaVoxel's coordinates
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoxVoxel {
+	pub x: u8,
+	pub y: u8,
+	pub z: u8,
+	/// 1 to 255 (0 is empty and never stored)
+	pub color_index: u8
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoxModel {
+	/// Along MagicaVoxel's x, y and z
+	pub size: [u32; 3],
+	pub voxels: Vec<VoxVoxel>
+}
+impl VoxModel {
+	/// How big the model is here, with y up
+	pub fn grid_size(&self) -> Vector3<i32> {
+		Vector3::new(self.size[0] as i32, self.size[2] as i32, self.size[1] as i32)
+	}
+	/// Where `voxel` is here, with y up (and turned so that it doesn't get mirrored)
+	pub fn grid_position(&self, voxel: VoxVoxel) -> Point3<i32> {
+		Point3::new(voxel.x as i32, voxel.z as i32, self.size[1] as i32 - 1 - voxel.y as i32)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoxFile {
+	pub models: Vec<VoxModel>,
+	/// RGBA, in the order of the RGBA chunk: `palette[i]` is color index `i + 1`
+	pub palette: Vec<[u8; 4]>
+}
+impl VoxFile {
+	pub fn load(path: &Path) -> Result<VoxFile, VoxError> {
+		VoxFile::parse(&fs::read(path)?)
+	}
+	pub fn save(&self, path: &Path) -> Result<(), VoxError> {
+		Ok(fs::write(path, self.to_bytes()?)?)
+	}
+
+	pub fn parse(bytes: &[u8]) -> Result<VoxFile, VoxError> {
+		let mut reader = Reader::new(bytes);
+		if reader.take(4)? != MAGIC {
+			return Err(VoxError::NotVox);
+		}
+		// Every version so far has the same layout for the chunks read here
+		let _version = reader.u32()?;
+		let main = read_chunk(&mut reader)?;
+		if main.id != *b"MAIN" {
+			return Err(VoxError::Invalid(format!("expected a MAIN chunk, found {}", main.name())));
+		}
+
+		let mut children = Reader::new(main.children);
+		let mut models = Vec::new();
+		let mut size = None;
+		let mut palette = None;
+		while !children.is_at_end() {
+			let chunk = read_chunk(&mut children)?;
+			let mut content = Reader::new(chunk.content);
+			match &chunk.id {
+				b"SIZE" => {
+					let model_size = [content.u32()?, content.u32()?, content.u32()?];
+					if model_size.iter().any(|&size| size == 0 || size > MAX_SIZE) {
+						return Err(VoxError::Invalid(format!("a model is {:?} voxels big", model_size)));
+					}
+					size = Some(model_size);
+				}
+				b"XYZI" => {
+					let size = size.take().ok_or_else(|| VoxError::Invalid("XYZI without a SIZE before it".to_string()))?;
+					let count = content.u32()? as usize;
+					if count > content.remaining() / 4 {
+						return Err(VoxError::Truncated);
+					}
+					let mut voxels = Vec::with_capacity(count);
+					for _ in 0..count {
+						let voxel = content.take(4)?;
+						let voxel = VoxVoxel { x: voxel[0], y: voxel[1], z: voxel[2], color_index: voxel[3] };
+						if voxel.x as u32 >= size[0] || voxel.y as u32 >= size[1] || voxel.z as u32 >= size[2] {
+							return Err(VoxError::Invalid(format!("{:?} is outside of a {:?} model", voxel, size)));
+						}
+						// Some exporters write empty voxels
+						if voxel.color_index != 0 {
+							voxels.push(voxel);
+						}
+					}
+					models.push(VoxModel { size, voxels });
+				}
+				b"RGBA" => {
+					let mut colors = Vec::with_capacity(256);
+					for _ in 0..256 {
+						let color = content.take(4)?;
+						colors.push([color[0], color[1], color[2], color[3]]);
+					}
+					palette = Some(colors);
+				}
+				// PACK only gives the number of models, which the SIZE chunks already do
+				_ => {}
+			}
+		}
+		Ok(VoxFile { models, palette: palette.unwrap_or_else(default_palette) })
+	}
+
+	pub fn to_bytes(&self) -> Result<Vec<u8>, VoxError> {
+		if self.palette.len() != 256 {
+			return Err(VoxError::Invalid(format!("the palette has {} colors instead of 256", self.palette.len())));
+		}
+		let mut children = Vec::new();
+		if self.models.len() > 1 {
+			write_chunk(&mut children, b"PACK", &(self.models.len() as u32).to_le_bytes());
+		}
+		for model in self.models.iter() {
+			if model.size.iter().any(|&size| size == 0 || size > MAX_SIZE) {
+				return Err(VoxError::TooBig(model.size));
+			}
+			let size: Vec<u8> = model.size.iter().flat_map(|size| size.to_le_bytes().to_vec()).collect();
+			write_chunk(&mut children, b"SIZE", &size);
+			let mut voxels = (model.voxels.len() as u32).to_le_bytes().to_vec();
+			for voxel in model.voxels.iter() {
+				voxels.extend_from_slice(&[voxel.x, voxel.y, voxel.z, voxel.color_index]);
+			}
+			write_chunk(&mut children, b"XYZI", &voxels);
+		}
+		let palette: Vec<u8> = self.palette.iter().flat_map(|color| color.to_vec()).collect();
+		write_chunk(&mut children, b"RGBA", &palette);
+
+		let mut bytes = MAGIC.to_vec();
+		bytes.extend_from_slice(&VERSION.to_le_bytes());
+		bytes.extend_from_slice(b"MAIN");
+		bytes.extend_from_slice(&0u32.to_le_bytes());
+		bytes.extend_from_slice(&(children.len() as u32).to_le_bytes());
+		bytes.extend_from_slice(&children);
+		Ok(bytes)
+	}
+
+	/// The color of `color_index`
+	pub fn color(&self, color_index: u8) -> [u8; 4] {
+		self.palette[(color_index as usize + 255) % 256]
+	}
+	/// Every voxel of every model, with y up.  MagicaVoxel places the models with its scene graph,
+	/// which isn't read, so they're put next to each other along x instead, one voxel apart.
+	pub fn to_voxels(&self) -> Vec<(Point3<i32>, Voxel)> {
+		let mut voxels = Vec::new();
+		let mut offset = 0;
+		for model in self.models.iter() {
+			for &voxel in model.voxels.iter() {
+				let [r, g, b, a] = self.color(voxel.color_index);
+				let color = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+				// Some tools leave the alpha at 0, which would make the voxel invisible
+				let opacity = if a == 0 { 1.0 } else { a as f32 / 255.0 };
+				let position = model.grid_position(voxel) + Vector3::new(offset, 0, 0);
+				voxels.push((position, Voxel::solid(BlockId::SOLID, color).with_opacity(opacity)));
+			}
+			offset += model.grid_size().x + 1;
+		}
+		voxels
+	}
+	/// A single model of `voxels` (with y up), moved so that it starts at 0.  Only the colors and
+	/// opacities are kept: the first 255 different ones get a palette entry, and any others the
+	/// closest of those.
+	pub fn from_voxels(voxels: &[(Point3<i32>, Voxel)]) -> Result<VoxFile, VoxError> {
+		let voxels: Vec<_> = voxels.iter().filter(|(_, voxel)| !voxel.is_empty()).collect();
+		let mut palette = vec![[0, 0, 0, 255]; 256];
+		let mut color_indices = HashMap::new();
+		let mut used = 0;
+		let mut color_index = |voxel: &Voxel| {
+			let rgba = [to_byte(voxel.color.0), to_byte(voxel.color.1), to_byte(voxel.color.2), to_byte(voxel.opacity)];
+			*color_indices.entry(rgba).or_insert_with(|| {
+				if used < 255 {
+					palette[used] = rgba;
+					used += 1;
+					used as u8
+				} else {
+					closest(&palette[..255], rgba)
+				}
+			})
+		};
+
+		let min = voxels.iter().fold(Point3::new(i32::MAX, i32::MAX, i32::MAX), |min, (position, _)| {
+			Point3::new(min.x.min(position.x), min.y.min(position.y), min.z.min(position.z))
+		});
+		let max = voxels.iter().fold(Point3::new(i32::MIN, i32::MIN, i32::MIN), |max, (position, _)| {
+			Point3::new(max.x.max(position.x), max.y.max(position.y), max.z.max(position.z))
+		});
+		// MagicaVoxel's x, y and z (see `VoxModel::grid_position`)
+		let size = if voxels.is_empty() {
+			[1, 1, 1]
+		} else {
+			[(max.x - min.x + 1) as u32, (max.z - min.z + 1) as u32, (max.y - min.y + 1) as u32]
+		};
+		if size.iter().any(|&size| size > MAX_SIZE) {
+			return Err(VoxError::TooBig(size));
+		}
+		let model_voxels = voxels
+			.iter()
+			.map(|(position, voxel)| VoxVoxel {
+				x: (position.x - min.x) as u8,
+				y: (size[1] as i32 - 1 - (position.z - min.z)) as u8,
+				z: (position.y - min.y) as u8,
+				color_index: color_index(voxel)
+			})
+			.collect();
+		Ok(VoxFile { models: vec![VoxModel { size, voxels: model_voxels }], palette })
+	}
+}
+
+fn to_byte(value: f32) -> u8 {
+	(value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+/// The color index of the color in `palette` that's closest to `rgba`
+fn closest(palette: &[[u8; 4]], rgba: [u8; 4]) -> u8 {
+	let distance = |color: &[u8; 4]| (0..4).map(|i| (color[i] as i32 - rgba[i] as i32).pow(2)).sum::<i32>();
+	let (index, _) = palette.iter().enumerate().min_by_key(|(_, color)| distance(color)).unwrap();
+	index as u8 + 1
+}
+
+/// The palette MagicaVoxel uses when a file doesn't have an RGBA chunk: the 6 x 6 x 6 color cube
+/// (without black), then ramps of red, green, blue and gray, and the unused last entry.
+pub fn default_palette() -> Vec<[u8; 4]> {
+	const CUBE: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+	const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+	let mut palette = Vec::with_capacity(256);
+	for &r in CUBE.iter() {
+		for &g in CUBE.iter() {
+			for &b in CUBE.iter() {
+				palette.push([r, g, b, 0xff]);
+			}
+		}
+	}
+	palette.pop();
+	palette.extend(RAMP.iter().map(|&value| [value, 0, 0, 0xff]));
+	palette.extend(RAMP.iter().map(|&value| [0, value, 0, 0xff]));
+	palette.extend(RAMP.iter().map(|&value| [0, 0, value, 0xff]));
+	palette.extend(RAMP.iter().map(|&value| [value, value, value, 0xff]));
+	palette.push([0, 0, 0, 0]);
+	palette
+}
+
+#[derive(Debug)]
+pub enum VoxError {
+	Io(io::Error),
+	NotVox,
+	/// The file ends in the middle of something
+	Truncated,
+	Invalid(String),
+	/// A model would be bigger than `MAX_SIZE`
+	TooBig([u32; 3])
+}
+impl From<Truncated> for VoxError {
+	fn from(_: Truncated) -> VoxError {
+		VoxError::Truncated
+	}
+}
+impl fmt::Display for VoxError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			VoxError::Io(err) => write!(f, "{}", err),
+			VoxError::NotVox => write!(f, "Not a .vox file"),
+			VoxError::Truncated => write!(f, "The .vox file is cut off"),
+			VoxError::Invalid(message) => write!(f, "Invalid .vox file: {}", message),
+			VoxError::TooBig(size) => write!(f, "A {:?} model is too big for a .vox file (at most {} along each axis)", size, MAX_SIZE)
+		}
+	}
+}
+impl Error for VoxError {}
+impl From<io::Error> for VoxError {
+	fn from(err: io::Error) -> VoxError {
+		VoxError::Io(err)
+	}
+}
+
+struct RawChunk<'a> {
+	id: [u8; 4],
+	content: &'a [u8],
+	children: &'a [u8]
+}
+impl RawChunk<'_> {
+	fn name(&self) -> String {
+		String::from_utf8_lossy(&self.id).into_owned()
+	}
+}
+
+/// The chunk at the reader's position, with its content and children
+fn read_chunk<'a>(reader: &mut Reader<'a>) -> Result<RawChunk<'a>, VoxError> {
+	let id = reader.array()?;
+	let content_size = reader.u32()? as usize;
+	let children_size = reader.u32()? as usize;
+	Ok(RawChunk {
+		id,
+		content: reader.take(content_size)?,
+		children: reader.take(children_size)?
+	})
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+	bytes.extend_from_slice(id);
+	bytes.extend_from_slice(&(content.len() as u32).to_le_bytes());
+	bytes.extend_from_slice(&0u32.to_le_bytes());
+	bytes.extend_from_slice(content);
+}
+
+/// Fills the chunks of a `VoxelWorld` with a model, cut up along the chunk borders.  Everything
+/// outside of the model is empty.
+pub struct ModelGenerator {
+	size: ChunkSize,
+	/// The voxels of each chunk, by their index in it
+	chunks: HashMap<ChunkCoord, Vec<(usize, Voxel)>>,
+	/// The smallest and largest voxel
+	bounds: Option<(Point3<i32>, Point3<i32>)>
+}
+impl ModelGenerator {
+	/// `voxels` are moved by `offset` and sorted into chunks of `size`, which has to be the size of
+	/// the chunks that are generated.
+	pub fn new(voxels: Vec<(Point3<i32>, Voxel)>, offset: Vector3<i32>, size: ChunkSize) -> ModelGenerator {
+		let (width, height, depth) = (size.width as i32, size.height as i32, size.depth as i32);
+		let mut chunks: HashMap<ChunkCoord, Vec<(usize, Voxel)>> = HashMap::new();
+		let mut bounds: Option<(Point3<i32>, Point3<i32>)> = None;
+		for (position, voxel) in voxels {
+			// In the world's voxel grid (see `VoxelWorld::to_grid`)
+			let p = position + offset;
+			let coord = ChunkCoord::new(p.x.div_euclid(width), p.y.div_euclid(height), p.z.div_euclid(depth));
+			let index = size.index(p.x.rem_euclid(width) as usize, p.y.rem_euclid(height) as usize, p.z.rem_euclid(depth) as usize);
+			chunks.entry(coord).or_default().push((index, voxel));
+			bounds = Some(match bounds {
+				Some((min, max)) => (Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)), Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z))),
+				None => (p, p)
+			});
+		}
+		ModelGenerator { size, chunks, bounds }
+	}
+	/// The smallest and largest voxel (or `None` if there aren't any)
+	pub fn bounds(&self) -> Option<(Point3<i32>, Point3<i32>)> {
+		self.bounds
+	}
+}
+impl ChunkGenerator for ModelGenerator {
+	fn generate(&self, coord: ChunkCoord, size: ChunkSize) -> Vec<Voxel> {
+		assert_eq!(size, self.size, "The model was split into chunks of another size");
+		let mut voxels = vec![Voxel::EMPTY; size.len()];
+		for &(index, voxel) in self.chunks.get(&coord).into_iter().flatten() {
+			voxels[index] = voxel;
+		}
+		voxels
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::prelude::*;
+
+	/// A few models of random voxels and a random palette
+	fn random_file(seed: u64, sizes: &[[u32; 3]]) -> VoxFile {
+		let mut rng = StdRng::seed_from_u64(seed);
+		let models = sizes
+			.iter()
+			.map(|&size| {
+				let mut voxels = Vec::new();
+				for x in 0..size[0] {
+					for y in 0..size[1] {
+						for z in 0..size[2] {
+							if rng.gen_bool(0.3) {
+								voxels.push(VoxVoxel { x: x as u8, y: y as u8, z: z as u8, color_index: rng.gen_range(1, 256) as u8 });
+							}
+						}
+					}
+				}
+				VoxModel { size, voxels }
+			})
+			.collect();
+		let palette = (0..256).map(|_| [rng.gen(), rng.gen(), rng.gen(), 255]).collect();
+		VoxFile { models, palette }
+	}
+
+	#[test]
+	fn files_round_trip() {
+		for (seed, sizes) in [(1, vec![[4, 5, 6]]), (2, vec![[1, 1, 1], [8, 3, 2], [16, 16, 16]])].iter() {
+			let file = random_file(*seed, sizes);
+			let bytes = file.to_bytes().unwrap();
+			assert_eq!(&bytes[..4], b"VOX ");
+			assert_eq!(VoxFile::parse(&bytes).unwrap(), file);
+		}
+	}
+
+	#[test]
+	fn voxels_round_trip() {
+		// Colors and opacities that survive being bytes, and a corner below 0 that gets moved to 0
+		let mut rng = StdRng::seed_from_u64(3);
+		let mut voxels = Vec::new();
+		for x in -3..5 {
+			for y in -2..7 {
+				for z in 0..4 {
+					if rng.gen_bool(0.5) {
+						let color = (rng.gen_range(0, 4) as f32 * 85.0 / 255.0, 0.2, 1.0);
+						let opacity = if rng.gen_bool(0.3) { 153.0 / 255.0 } else { 1.0 };
+						let voxel = Voxel::solid(BlockId::SOLID, (color.0, to_byte(color.1) as f32 / 255.0, color.2)).with_opacity(opacity);
+						voxels.push((Point3::new(x, y, z), voxel));
+					}
+				}
+			}
+		}
+		let file = VoxFile::from_voxels(&voxels).unwrap();
+		assert_eq!(file.models[0].size, [8, 4, 9]);
+		let read = VoxFile::parse(&file.to_bytes().unwrap()).unwrap().to_voxels();
+		let moved: Vec<_> = voxels.iter().map(|&(position, voxel)| (position + Vector3::new(3, 2, 0), voxel)).collect();
+		let sorted = |mut voxels: Vec<(Point3<i32>, Voxel)>| {
+			voxels.sort_by_key(|(position, _)| (position.x, position.y, position.z));
+			voxels
+		};
+		assert_eq!(sorted(read), sorted(moved));
+	}
+
+	#[test]
+	fn extra_colors_get_the_closest_palette_entry() {
+		// 300 different colors
+		let color = |i: i32| ((i % 20) as f32 / 19.0, (i / 20) as f32 / 14.0, 0.5);
+		let voxels: Vec<_> = (0..300).map(|i| (Point3::new(i % 100, i / 100, 0), Voxel::solid(BlockId::SOLID, color(i)))).collect();
+		let file = VoxFile::from_voxels(&voxels).unwrap();
+		let model = &file.models[0];
+		assert_eq!(model.voxels.len(), 300);
+		let rgba = |color: (f32, f32, f32)| [to_byte(color.0), to_byte(color.1), to_byte(color.2), 255];
+		for (i, voxel) in model.voxels.iter().enumerate() {
+			if i < 255 {
+				assert_eq!(file.color(voxel.color_index), rgba(color(i as i32)));
+			} else {
+				assert_eq!(voxel.color_index, closest(&file.palette[..255], rgba(color(i as i32))));
+			}
+		}
+	}
+
+	#[test]
+	fn models_are_laid_out_along_x_with_z_up() {
+		let mut file = random_file(4, &[]);
+		file.models.push(VoxModel { size: [2, 3, 4], voxels: vec![VoxVoxel { x: 1, y: 0, z: 3, color_index: 1 }] });
+		file.models.push(VoxModel { size: [1, 1, 1], voxels: vec![VoxVoxel { x: 0, y: 0, z: 0, color_index: 2 }] });
+		let voxels = file.to_voxels();
+		// MagicaVoxel's z is y, and y = 0 is at the back
+		assert_eq!(voxels[0].0, Point3::new(1, 3, 2));
+		// The second model starts after the first one's 2 voxels and a gap
+		assert_eq!(voxels[1].0, Point3::new(3, 0, 0));
+	}
+
+	#[test]
+	fn files_without_a_palette_use_the_default_one() {
+		let mut file = random_file(5, &[[2, 2, 2]]);
+		let mut bytes = file.to_bytes().unwrap();
+		// Drop the RGBA chunk at the end, and fix MAIN's size
+		bytes.truncate(bytes.len() - (12 + 1024));
+		let children = (bytes.len() - 20) as u32;
+		bytes[16..20].copy_from_slice(&children.to_le_bytes());
+		let read = VoxFile::parse(&bytes).unwrap();
+		file.palette = default_palette();
+		assert_eq!(read, file);
+		assert_eq!(read.color(1), [255, 255, 255, 255]);
+		assert_eq!(read.color(215), [0, 0, 0x33, 255]);
+		assert_eq!(read.color(216), [0xee, 0, 0, 255]);
+		assert_eq!(read.color(255), [0x11, 0x11, 0x11, 255]);
+	}
+
+	#[test]
+	fn broken_files_are_errors() {
+		let bytes = random_file(6, &[[3, 3, 3]]).to_bytes().unwrap();
+		for length in [0, 3, 8, 12, 30, bytes.len() - 1].iter() {
+			assert!(VoxFile::parse(&bytes[..*length]).is_err(), "{} bytes", length);
+		}
+		assert!(matches!(VoxFile::parse(b"PNG whatever"), Err(VoxError::NotVox)));
+		// A voxel outside of its model
+		let mut file = random_file(7, &[[2, 2, 2]]);
+		file.models[0].voxels.push(VoxVoxel { x: 2, y: 0, z: 0, color_index: 1 });
+		assert!(matches!(VoxFile::parse(&file.to_bytes().unwrap()), Err(VoxError::Invalid(_))));
+		// A voxel count that's far too big
+		let mut bytes = random_file(8, &[[1, 1, 1]]).to_bytes().unwrap();
+		let count = bytes.windows(4).position(|window| window == b"XYZI").unwrap() + 12;
+		bytes[count..count + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+		assert!(VoxFile::parse(&bytes).is_err());
+	}
+
+	#[test]
+	fn big_models_are_split_across_chunks() {
+		let size = ChunkSize::new(4, 4, 4);
+		let voxels: Vec<_> = (0..10).flat_map(|x| (0..10).map(move |z| (Point3::new(x, 0, z), Voxel::solid(BlockId::SOLID, (1.0, 1.0, 1.0))))).collect();
+		let generator = ModelGenerator::new(voxels, Vector3::new(-2, 1, 0), size);
+		assert_eq!(generator.bounds(), Some((Point3::new(-2, 1, 0), Point3::new(7, 1, 9))));
+		let mut total = 0;
+		for x in -1..=2 {
+			for z in -1..=3 {
+				let chunk = generator.generate(ChunkCoord::new(x, 0, z), size);
+				total += chunk.iter().filter(|voxel| !voxel.is_empty()).count();
+				// Only the layer at y = 1
+				assert!(chunk.iter().enumerate().all(|(index, voxel)| voxel.is_empty() || size.position(index).1 == 1));
+			}
+		}
+		assert_eq!(total, 100);
+		assert_eq!(generator.generate(ChunkCoord::new(-1, 0, 0), size).iter().filter(|voxel| !voxel.is_empty()).count(), 8);
+	}
+}
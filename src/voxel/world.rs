@@ -0,0 +1,590 @@
+use super::{
+	cast_ray,
+	save::{self, SaveError},
+	Chunk, ChunkCoord,
+	ChunkGenerator,
+	ChunkMemory,
+	ChunkSize,
+	OccupancyGrid,
+	OccupancyStamp,
+	OWN_SLOT,
+	RayHit,
+	Voxel,
+	VoxelStorage,
+};
+use crate::shader::ShaderProgram;
+
+use cgmath::{EuclideanSpace, MetricSpace, Point3, Vector3};
+
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		mpsc::{self, Receiver, Sender},
+		Arc, Mutex,
+	},
+	thread::{self, JoinHandle},
+};
+
+/// Generates chunks on a background thread, compressing them there too, or reads them from the save
+/// directory if they were saved.  Nothing here touches GL.
+struct Loader {
+	requests: Option<Sender<ChunkCoord>>,
+	results: Receiver<(ChunkCoord, VoxelStorage)>,
+	/// The requested chunks the thread hasn't started on, so that it can skip the ones that aren't
+	/// wanted anymore
+	wanted: Arc<Mutex<BTreeSet<ChunkCoord>>>,
+	/// Set when the loader is dropped, so that the thread stops without going through the queue
+	cancelled: Arc<AtomicBool>,
+	thread: Option<JoinHandle<()>>
+}
+impl Loader {
+	fn new(generator: Arc<dyn ChunkGenerator>, size: ChunkSize, saves: Option<PathBuf>) -> Loader {
+		let (requests, pending) = mpsc::channel::<ChunkCoord>();
+		let (finished, results) = mpsc::channel();
+		let wanted = Arc::new(Mutex::new(BTreeSet::new()));
+		let cancelled = Arc::new(AtomicBool::new(false));
+		let (thread_wanted, thread_cancelled) = (wanted.clone(), cancelled.clone());
+		let thread = thread::Builder::new()
+			.name("chunk loader".to_string())
+			.spawn(move || {
+				let mut saves = saves.map(save::RegionCache::new);
+				for coord in pending {
+					if thread_cancelled.load(Ordering::Relaxed) {
+						break;
+					}
+					if !thread_wanted.lock().unwrap().remove(&coord) {
+						continue;
+					}
+					let saved = saves.as_mut().and_then(|saves| match saves.load_chunk(coord, size) {
+						Ok(saved) => saved,
+						Err(err) => {
+							eprintln!("Couldn't load chunk {:?}, generating it instead: {}", coord, err);
+							None
+						}
+					});
+					let voxels = saved.unwrap_or_else(|| {
+						let voxels = generator.generate(coord, size);
+						assert_eq!(voxels.len(), size.len(), "The generator made the wrong number of voxels for {:?}", coord);
+						VoxelStorage::from_voxels(size, &voxels)
+					});
+					if finished.send((coord, voxels)).is_err() {
+						break;
+					}
+				}
+			})
+			.expect("Couldn't start the chunk loader thread");
+		Loader {
+			requests: Some(requests),
+			results,
+			wanted,
+			cancelled,
+			thread: Some(thread)
+		}
+	}
+	fn request(&self, coord: ChunkCoord) {
+		if let Some(requests) = &self.requests {
+			self.wanted.lock().unwrap().insert(coord);
+			// Only fails if the thread panicked, which it already reported
+			let _ = requests.send(coord);
+		}
+	}
+	/// Skip the requested chunks that `keep` returns false for, unless they're already being loaded
+	fn retain(&self, keep: impl Fn(ChunkCoord) -> bool) {
+		self.wanted.lock().unwrap().retain(|&coord| keep(coord));
+	}
+}
+impl Drop for Loader {
+	fn drop(&mut self) {
+		// Stop after the chunk it's on, and close the channel in case it's waiting for one
+		self.cancelled.store(true, Ordering::Relaxed);
+		self.requests = None;
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+/// Chunks that went out of range, kept with their GPU buffer and VAO so that new chunks don't have
+/// to allocate any.
+pub struct BufferPool {
+	free: Vec<Chunk>,
+	/// How many chunks to keep around at most
+	pub capacity: usize,
+	created: usize
+}
+impl BufferPool {
+	pub fn new(capacity: usize) -> BufferPool {
+		BufferPool {
+			free: Vec::new(),
+			capacity,
+			created: 0
+		}
+	}
+	/// A chunk holding `voxels`: a recycled one if there is one of the right size.
+	pub fn acquire(&mut self, chunk_shader: &ShaderProgram, voxels: VoxelStorage, position: Point3<f32>) -> Chunk {
+		let size = voxels.size();
+		self.free.retain(|chunk| chunk.size == size);
+		let mut chunk = match self.free.pop() {
+			Some(mut chunk) => {
+				chunk.replace_voxels(voxels);
+				chunk
+			}
+			None => {
+				self.created += 1;
+				Chunk::with_voxels(chunk_shader, voxels, 0.0, 0.0, 0.0)
+			}
+		};
+		chunk.x = position.x;
+		chunk.y = position.y;
+		chunk.z = position.z;
+		chunk
+	}
+	pub fn release(&mut self, chunk: Chunk) {
+		if self.free.len() < self.capacity {
+			self.free.push(chunk);
+		}
+	}
+	/// How many chunks (and so buffers) were ever created
+	pub fn created(&self) -> usize {
+		self.created
+	}
+	/// Delete the pooled buffers.  Needs the GL context.
+	pub fn clear(&mut self) {
+		self.free.clear();
+	}
+}
+
+/// Chunks keyed by their coordinates, loaded and unloaded as the camera moves around.  The contents
+/// come from a `ChunkGenerator` running on a background thread, and are uploaded on the GL thread
+/// a few chunks per frame.
+pub struct VoxelWorld {
+	pub size: ChunkSize,
+	/// Where chunk (0, 0, 0) starts
+	pub origin: Point3<f32>,
+	/// How many chunks to keep loaded around the camera's chunk along x and z
+	pub radius: i32,
+	/// The same along y
+	pub vertical_radius: i32,
+	/// How many chunks `upload` uploads per frame at most
+	pub upload_budget: usize,
+	// Ordered so that anything going through all the chunks (like the random animation) is
+	// reproducible
+	chunks: BTreeMap<ChunkCoord, Chunk>,
+	/// Requested from the loader and not uploaded yet
+	pending: BTreeSet<ChunkCoord>,
+	/// Generated, waiting for the upload budget
+	ready: Vec<(ChunkCoord, VoxelStorage)>,
+	center: Option<ChunkCoord>,
+	pool: BufferPool,
+	generator: Arc<dyn ChunkGenerator>,
+	/// Where saved chunks are loaded from instead of generating them
+	saves: Option<PathBuf>,
+	loader: Loader
+}
+impl VoxelWorld {
+	pub fn new(size: ChunkSize, origin: Point3<f32>, generator: Arc<dyn ChunkGenerator>) -> VoxelWorld {
+		VoxelWorld {
+			size,
+			origin,
+			radius: 1,
+			vertical_radius: 0,
+			upload_budget: 2,
+			chunks: BTreeMap::new(),
+			pending: BTreeSet::new(),
+			ready: Vec::new(),
+			center: None,
+			pool: BufferPool::new(16),
+			loader: Loader::new(generator.clone(), size, None),
+			generator,
+			saves: None
+		}
+	}
+
+	/// The chunk that contains `position`
+	pub fn chunk_coord(&self, position: Point3<f32>) -> ChunkCoord {
+		let local = position - self.origin;
+		ChunkCoord::new(
+			(local.x / self.size.width as f32).floor() as i32,
+			(local.y / self.size.height as f32).floor() as i32,
+			(local.z / self.size.depth as f32).floor() as i32
+		)
+	}
+	/// Where the chunk at `coord` starts
+	pub fn chunk_origin(&self, coord: ChunkCoord) -> Point3<f32> {
+		self.origin + Vector3::new(
+			(coord.x * self.size.width as i32) as f32,
+			(coord.y * self.size.height as i32) as f32,
+			(coord.z * self.size.depth as i32) as f32
+		)
+	}
+	/// Which chunk the voxel at `voxel` (see `to_grid`) is in, and where in it
+	pub fn locate(&self, voxel: Point3<i32>) -> (ChunkCoord, (usize, usize, usize)) {
+		let (width, height, depth) = (self.size.width as i32, self.size.height as i32, self.size.depth as i32);
+		(
+			ChunkCoord::new(voxel.x.div_euclid(width), voxel.y.div_euclid(height), voxel.z.div_euclid(depth)),
+			(voxel.x.rem_euclid(width) as usize, voxel.y.rem_euclid(height) as usize, voxel.z.rem_euclid(depth) as usize)
+		)
+	}
+	/// `position` in the grid that voxels are addressed by: the voxel at `x, y, z` spans `x` to
+	/// `x + 1` and so on, counting from the first voxel of chunk (0, 0, 0).
+	pub fn to_grid(&self, position: Point3<f32>) -> Point3<f32> {
+		// Voxels are centered on their position in the chunk
+		Point3::from_vec(position - self.origin + Vector3::new(0.5, 0.5, 0.5))
+	}
+	/// Where the voxel at `voxel` is drawn
+	pub fn voxel_center(&self, voxel: Point3<i32>) -> Point3<f32> {
+		self.origin + voxel.to_vec().cast::<f32>().unwrap()
+	}
+	/// The voxel at `voxel`, if its chunk is loaded
+	pub fn voxel(&self, voxel: Point3<i32>) -> Option<Voxel> {
+		let (coord, (x, y, z)) = self.locate(voxel);
+		self.chunks.get(&coord).map(|chunk| chunk.get(x, y, z))
+	}
+	/// Change the voxel at `voxel`.  Returns false if its chunk isn't loaded.  Edits are lost when
+	/// the chunk is unloaded, unless it was saved with `save` first.
+	pub fn set_voxel(&mut self, voxel: Point3<i32>, value: Voxel) -> bool {
+		let (coord, (x, y, z)) = self.locate(voxel);
+		match self.chunks.get_mut(&coord) {
+			Some(chunk) => {
+				chunk.set(x, y, z, value);
+				true
+			}
+			None => false
+		}
+	}
+	/// The first voxel that isn't empty along a ray in scene coordinates.  Chunks that aren't loaded
+	/// are looked through.
+	pub fn pick(&self, origin: Point3<f32>, direction: Vector3<f32>, max_distance: f32) -> Option<RayHit> {
+		cast_ray(self.to_grid(origin), direction, max_distance, |voxel| {
+			self.voxel(voxel).is_some_and(|voxel| !voxel.is_empty())
+		})
+	}
+
+	fn in_range(&self, center: ChunkCoord, coord: ChunkCoord) -> bool {
+		(coord.x - center.x).abs() <= self.radius
+			&& (coord.z - center.z).abs() <= self.radius
+			&& (coord.y - center.y).abs() <= self.vertical_radius
+	}
+
+	/// Load the chunks around `position` and unload the ones that are too far away.  Cheap to call
+	/// every frame: nothing happens until the camera moves into another chunk.
+	pub fn set_center(&mut self, position: Point3<f32>) {
+		let center = self.chunk_coord(position);
+		if self.center == Some(center) {
+			return;
+		}
+		self.center = Some(center);
+
+		let out_of_range: Vec<ChunkCoord> = self.chunks.keys().cloned().filter(|&coord| !self.in_range(center, coord)).collect();
+		for coord in out_of_range {
+			if let Some(chunk) = self.chunks.remove(&coord) {
+				self.pool.release(chunk);
+			}
+		}
+		// The loader skips these if it hasn't started on them, and whatever it still sends gets dropped
+		let pending = std::mem::take(&mut self.pending);
+		self.pending = pending.into_iter().filter(|&coord| self.in_range(center, coord)).collect();
+		self.loader.retain(|coord| self.in_range(center, coord));
+		let ready = std::mem::take(&mut self.ready);
+		self.ready = ready.into_iter().filter(|(coord, _)| self.in_range(center, *coord)).collect();
+
+		let mut wanted = Vec::new();
+		for x in -self.radius..=self.radius {
+			for y in -self.vertical_radius..=self.vertical_radius {
+				for z in -self.radius..=self.radius {
+					let coord = ChunkCoord::new(center.x + x, center.y + y, center.z + z);
+					if !self.chunks.contains_key(&coord) && !self.pending.contains(&coord) {
+						wanted.push(coord);
+					}
+				}
+			}
+		}
+		wanted.sort_by_key(|coord| coord.distance_squared(center));
+		for coord in wanted {
+			self.pending.insert(coord);
+			self.loader.request(coord);
+		}
+	}
+
+	/// Upload up to `upload_budget` of the chunks that finished loading, nearest first.  Call once
+	/// per frame.  Returns how many were uploaded.
+	pub fn upload(&mut self, chunk_shader: &ShaderProgram) -> usize {
+		self.receive();
+		if let Some(center) = self.center {
+			// Nearest last, so they can be popped off
+			self.ready.sort_by_key(|(coord, _)| -coord.distance_squared(center));
+		}
+		let mut uploaded = 0;
+		while uploaded < self.upload_budget {
+			match self.ready.pop() {
+				Some((coord, voxels)) => {
+					self.insert(chunk_shader, coord, voxels);
+					uploaded += 1;
+				}
+				None => break
+			}
+		}
+		uploaded
+	}
+	/// Wait for every requested chunk and upload them all, ignoring the budget.
+	pub fn finish_loading(&mut self, chunk_shader: &ShaderProgram) {
+		while self.pending.len() > self.ready.len() {
+			match self.loader.results.recv() {
+				Ok(result) => self.accept(result),
+				Err(_) => break
+			}
+		}
+		for (coord, voxels) in std::mem::take(&mut self.ready) {
+			self.insert(chunk_shader, coord, voxels);
+		}
+	}
+	/// Whether there are chunks that haven't been uploaded yet
+	pub fn is_loading(&self) -> bool {
+		!self.pending.is_empty()
+	}
+	fn receive(&mut self) {
+		while let Ok(result) = self.loader.results.try_recv() {
+			self.accept(result);
+		}
+	}
+	fn accept(&mut self, (coord, voxels): (ChunkCoord, VoxelStorage)) {
+		// Skip chunks that went out of range (or were already received) in the meantime
+		let waiting = self.pending.contains(&coord) && !self.ready.iter().any(|(ready, _)| *ready == coord);
+		if waiting && voxels.size() == self.size {
+			self.ready.push((coord, voxels));
+		}
+	}
+	fn insert(&mut self, chunk_shader: &ShaderProgram, coord: ChunkCoord, voxels: VoxelStorage) {
+		self.pending.remove(&coord);
+		let chunk = self.pool.acquire(chunk_shader, voxels, self.chunk_origin(coord));
+		self.chunks.insert(coord, chunk);
+	}
+
+	pub fn get(&self, coord: ChunkCoord) -> Option<&Chunk> {
+		self.chunks.get(&coord)
+	}
+	pub fn get_mut(&mut self, coord: ChunkCoord) -> Option<&mut Chunk> {
+		self.chunks.get_mut(&coord)
+	}
+	pub fn chunks(&self) -> impl Iterator<Item = (ChunkCoord, &Chunk)> {
+		self.chunks.iter().map(|(coord, chunk)| (*coord, chunk))
+	}
+	pub fn chunks_mut(&mut self) -> impl Iterator<Item = (ChunkCoord, &mut Chunk)> {
+		self.chunks.iter_mut().map(|(coord, chunk)| (*coord, chunk))
+	}
+	pub fn len(&self) -> usize {
+		self.chunks.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.chunks.is_empty()
+	}
+	pub fn pool(&self) -> &BufferPool {
+		&self.pool
+	}
+	/// How much memory each loaded chunk takes, and all of them together
+	pub fn memory(&self) -> (Vec<(ChunkCoord, ChunkMemory)>, ChunkMemory) {
+		let chunks: Vec<_> = self.chunks().map(|(coord, chunk)| (coord, chunk.memory())).collect();
+		let total = chunks.iter().fold(ChunkMemory::default(), |total, (_, memory)| total + *memory);
+		(chunks, total)
+	}
+
+	/// Whether any of the chunks may have see-through voxels
+	pub fn has_see_through(&self) -> bool {
+		self.chunks.values().any(|chunk| chunk.voxels().has_see_through())
+	}
+	/// The generations of the chunk at `coord` and its neighbours
+	fn occupancy_stamp(&self, coord: ChunkCoord) -> OccupancyStamp {
+		let mut stamp = [None; 27];
+		for (i, generation) in stamp.iter_mut().enumerate().filter(|&(i, _)| i != OWN_SLOT) {
+			let (x, y, z) = (i as i32 % 3 - 1, i as i32 / 3 % 3 - 1, i as i32 / 9 - 1);
+			let neighbour = ChunkCoord::new(coord.x + x, coord.y + y, coord.z + z);
+			*generation = self.chunks.get(&neighbour).map(Chunk::generation);
+		}
+		stamp[OWN_SLOT] = self.chunks.get(&coord).map(Chunk::generation);
+		stamp
+	}
+	/// Fill the occupancy textures of the chunks whose voxels or neighbours changed, so that the
+	/// ambient occlusion is continuous across chunk borders.
+	fn refresh_occupancy(&mut self) {
+		let coords: Vec<ChunkCoord> = self.chunks.keys().copied().collect();
+		for coord in coords {
+			let stamp = self.occupancy_stamp(coord);
+			if self.chunks[&coord].occupancy_stamp() == Some(stamp) {
+				continue;
+			}
+			let first = Point3::new(
+				coord.x * self.size.width as i32,
+				coord.y * self.size.height as i32,
+				coord.z * self.size.depth as i32
+			);
+			let grid = OccupancyGrid::with_border(self.chunks[&coord].voxels(), |cell| {
+				let voxel = first + Vector3::from(cell);
+				self.voxel(voxel).is_some_and(|voxel| !voxel.is_empty())
+			});
+			self.chunks.get_mut(&coord).unwrap().upload_occupancy(&grid, stamp);
+		}
+	}
+
+	pub fn draw(&mut self, chunk_shader: &ShaderProgram) {
+		self.refresh_occupancy();
+		for chunk in self.chunks.values_mut() {
+			chunk.draw(chunk_shader);
+		}
+	}
+	/// The see-through voxels, back to front: the chunks by their centers, then the voxels in each.
+	/// Voxels of neighbouring chunks that overlap on screen can still come out in the wrong order.
+	pub fn draw_sorted(&mut self, chunk_shader: &ShaderProgram, eye: Point3<f32>) {
+		self.refresh_occupancy();
+		let half = Vector3::new(self.size.width as f32, self.size.height as f32, self.size.depth as f32) / 2.0;
+		let mut chunks: Vec<_> = self.chunks.values_mut().collect();
+		// Voxels are centered on their position in the chunk
+		let center = |chunk: &Chunk| Point3::new(chunk.x, chunk.y, chunk.z) + half - Vector3::new(0.5, 0.5, 0.5);
+		chunks.sort_by(|a, b| center(b).distance2(eye).partial_cmp(&center(a).distance2(eye)).unwrap_or(std::cmp::Ordering::Equal));
+		for chunk in chunks {
+			chunk.draw_sorted(chunk_shader, eye);
+		}
+	}
+	pub fn draw_meshes(&mut self, mesh_shader: &ShaderProgram) {
+		self.refresh_occupancy();
+		for chunk in self.chunks.values_mut() {
+			chunk.draw_mesh(mesh_shader);
+		}
+	}
+	/// Write the voxels of every loaded chunk to the region files in `directory`.  Returns how many
+	/// chunks were saved.
+	pub fn save(&self, directory: &Path) -> Result<usize, SaveError> {
+		save::save_chunks(directory, self.chunks().map(|(coord, chunk)| (coord, chunk.voxels())))?;
+		Ok(self.len())
+	}
+	/// From now on load chunks saved in `directory` instead of generating them, starting with the
+	/// ones already loaded.  Needs the GL context.
+	pub fn load_saves(&mut self, directory: PathBuf) {
+		// Replacing the loader stops the old one, which may still have had chunks queued
+		self.loader = Loader::new(self.generator.clone(), self.size, Some(directory.clone()));
+		self.saves = Some(directory);
+		self.clear();
+	}
+	pub fn save_directory(&self) -> Option<&Path> {
+		self.saves.as_deref()
+	}
+	/// Delete every chunk.  Needs the GL context.  They're loaded again on the next `set_center`.
+	pub fn clear(&mut self) {
+		self.chunks.clear();
+		self.pool.clear();
+		self.pending.clear();
+		self.loader.retain(|_| false);
+		self.ready.clear();
+		self.center = None;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::voxel::RandomGenerator;
+
+	fn world() -> VoxelWorld {
+		VoxelWorld::new(ChunkSize::new(4, 2, 4), Point3::new(-2.0, -1.0, -2.0), Arc::new(RandomGenerator::new(1)))
+	}
+
+	#[test]
+	fn positions_map_to_chunks() {
+		let world = world();
+		assert_eq!(world.chunk_coord(Point3::new(0.0, 0.0, 0.0)), ChunkCoord::new(0, 0, 0));
+		assert_eq!(world.chunk_coord(Point3::new(1.9, 0.9, -2.0)), ChunkCoord::new(0, 0, 0));
+		// Rounded down, not towards zero
+		assert_eq!(world.chunk_coord(Point3::new(-2.1, -1.1, 2.0)), ChunkCoord::new(-1, -1, 1));
+		let coord = ChunkCoord::new(-3, 2, 5);
+		assert_eq!(world.chunk_coord(world.chunk_origin(coord)), coord);
+	}
+
+	#[test]
+	fn voxels_map_to_chunks() {
+		let world = world();
+		assert_eq!(world.locate(Point3::new(0, 0, 0)), (ChunkCoord::new(0, 0, 0), (0, 0, 0)));
+		assert_eq!(world.locate(Point3::new(5, 1, 3)), (ChunkCoord::new(1, 0, 0), (1, 1, 3)));
+		// Negative voxels are in the chunks before 0, counting back from their far end
+		assert_eq!(world.locate(Point3::new(-1, -3, -4)), (ChunkCoord::new(-1, -2, -1), (3, 1, 0)));
+		// Voxel 0 of chunk 0 is centered on the origin of the world
+		assert_eq!(world.to_grid(Point3::new(-2.0, -1.0, -2.0)), Point3::new(0.5, 0.5, 0.5));
+		assert_eq!(world.voxel_center(Point3::new(-1, 2, 0)), Point3::new(-3.0, 1.0, -2.0));
+		let grid = world.to_grid(world.voxel_center(Point3::new(7, -5, 2)));
+		assert_eq!(Point3::new(grid.x.floor() as i32, grid.y.floor() as i32, grid.z.floor() as i32), Point3::new(7, -5, 2));
+	}
+
+	#[test]
+	fn chunks_around_the_center_are_generated() {
+		let mut world = world();
+		world.radius = 2;
+		world.set_center(Point3::new(0.0, 0.0, 0.0));
+		assert_eq!(world.pending.len(), 25);
+		// Moving within the chunk doesn't request anything new
+		world.set_center(Point3::new(1.0, 0.5, 1.0));
+		assert_eq!(world.pending.len(), 25);
+
+		let mut received = Vec::new();
+		while received.len() < 25 {
+			let (coord, voxels) = world.loader.results.recv().unwrap();
+			assert_eq!(voxels.size(), world.size);
+			received.push(coord);
+		}
+		// Nearest first
+		assert_eq!(received[0], ChunkCoord::new(0, 0, 0));
+		received.sort();
+		received.dedup();
+		assert_eq!(received.len(), 25);
+		assert!(received.iter().all(|coord| coord.y == 0 && coord.x.abs() <= 2 && coord.z.abs() <= 2));
+
+		// One step along x drops a row of 5 and asks for a new one
+		world.set_center(Point3::new(2.5, 0.0, 0.0));
+		assert_eq!(world.pending.len(), 20 + 5);
+		assert!(!world.pending.contains(&ChunkCoord::new(-2, 0, 0)));
+		assert!(world.pending.contains(&ChunkCoord::new(3, 0, 0)));
+	}
+
+	/// Generates nothing until `gate` is sent something
+	struct GatedGenerator {
+		gate: Mutex<Receiver<()>>
+	}
+	impl ChunkGenerator for GatedGenerator {
+		fn generate(&self, coord: ChunkCoord, size: ChunkSize) -> Vec<Voxel> {
+			let _ = self.gate.lock().unwrap().recv();
+			RandomGenerator::new(1).generate(coord, size)
+		}
+	}
+
+	#[test]
+	fn chunks_no_longer_wanted_are_skipped() {
+		let (gate, receiver) = mpsc::channel();
+		let generator = Arc::new(GatedGenerator { gate: Mutex::new(receiver) });
+		let size = ChunkSize::new(2, 2, 2);
+		let loader = Loader::new(generator, size, None);
+		let coords: Vec<_> = (0..4).map(|x| ChunkCoord::new(x, 0, 0)).collect();
+		for &coord in coords.iter() {
+			loader.request(coord);
+		}
+		// Wait until the thread is stuck generating the first one
+		while loader.wanted.lock().unwrap().contains(&coords[0]) {
+			thread::yield_now();
+		}
+		loader.retain(|coord| coord.x != 1 && coord.x != 2);
+		for _ in 0..coords.len() {
+			gate.send(()).unwrap();
+		}
+		assert_eq!(loader.results.recv().unwrap().0, coords[0]);
+		assert_eq!(loader.results.recv().unwrap().0, coords[3]);
+		drop(gate);
+		assert!(loader.wanted.lock().unwrap().is_empty());
+	}
+
+	#[test]
+	fn generators_are_deterministic() {
+		let generator = RandomGenerator::new(5);
+		let size = ChunkSize::new(3, 3, 3);
+		let a = generator.generate(ChunkCoord::new(1, 0, -1), size);
+		assert_eq!(a, generator.generate(ChunkCoord::new(1, 0, -1), size));
+		assert_ne!(a, generator.generate(ChunkCoord::new(-1, 0, 1), size));
+	}
+}